@@ -0,0 +1,142 @@
+use crate::texture;
+
+// How edges get smoothed. MSAA renders the scene with several samples per pixel
+// and resolves them into the frame, FXAA blurs along edges it finds in the
+// finished image, which is cheaper and works where multisampling doesn't.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AntiAliasing {
+    Off,
+    // Samples per pixel, 2, 4 or 8
+    Msaa(u32),
+    Fxaa,
+}
+
+impl AntiAliasing {
+    pub fn sample_count(&self) -> u32 {
+        match self {
+            AntiAliasing::Msaa(count) => *count,
+            _ => 1,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            AntiAliasing::Off => "Off".to_string(),
+            AntiAliasing::Msaa(count) => format!("MSAA {}x", count),
+            AntiAliasing::Fxaa => "FXAA".to_string(),
+        }
+    }
+
+    // 4x MSAA if it's there, which every native adapter has, FXAA otherwise
+    pub fn best(sample_counts: &[u32]) -> Self {
+        if sample_counts.contains(&4) { AntiAliasing::Msaa(4) } else { AntiAliasing::Fxaa }
+    }
+}
+
+// Sample counts both the colour and depth targets can be made with and resolved
+// from. Without TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES on the device, wgpu
+// only allows the counts every adapter has, whatever this one could do.
+pub fn supported_sample_counts(adapter: &wgpu::Adapter, features: wgpu::Features, color: wgpu::TextureFormat, depth: wgpu::TextureFormat) -> Vec<u32> {
+    let flags = |format: wgpu::TextureFormat| {
+        if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            adapter.get_texture_format_features(format).flags
+        } else {
+            format.guaranteed_format_features(features).flags
+        }
+    };
+    let (color, depth) = (flags(color), flags(depth));
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (color.sample_count_supported(count)
+                    && color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                    && depth.sample_count_supported(count))
+        })
+        .collect()
+}
+
+// The FXAA pass. The scene is drawn into its own target, then filtered into the frame.
+pub struct Fxaa {
+    scene: texture::Texture,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Fxaa {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("fxaa_bind_group_layout"),
+        });
+
+        let shader = crate::create_fullscreen_shader(device, "FXAA Shader", include_str!("fxaa.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("FXAA Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = crate::create_fullscreen_pipeline(device, &pipeline_layout, &shader, "fs_main", config.format, wgpu::BlendState::REPLACE, "FXAA Pipeline");
+
+        let scene = texture::Texture::create_render_target(device, config, config.format, 1, "fxaa_scene_texture");
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &scene);
+
+        Self {
+            scene,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, scene: &texture::Texture) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&scene.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&scene.sampler),
+                },
+            ],
+            label: Some("fxaa_bind_group"),
+        })
+    }
+
+    // The scene target has to match the surface
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.scene = texture::Texture::create_render_target(device, config, config.format, 1, "fxaa_scene_texture");
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.scene);
+    }
+
+    // Where the scene should be drawn this frame
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.scene.view
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        // Every pixel gets written, so there's nothing to load
+        crate::draw_fullscreen(encoder, "FXAA Pass", target, wgpu::LoadOp::Clear(wgpu::Color::BLACK), &self.pipeline, &[&self.bind_group]);
+    }
+}
@@ -0,0 +1,263 @@
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+
+use crate::component::Component;
+use crate::events::Subscriber;
+use crate::{prefab, resources, State};
+
+type Constructor = Box<dyn Fn() -> Box<dyn Component>>;
+type Startup = Box<dyn FnOnce(&mut State)>;
+
+// Game logic that isn't tied to a single object, run once per frame after the
+// components have updated.
+pub trait System {
+    fn update(&mut self, state: &mut State, dt: f32);
+}
+
+struct FnSystem<F>(F);
+
+impl<F> System for FnSystem<F>
+where
+    F: FnMut(&mut State, f32),
+{
+    fn update(&mut self, state: &mut State, dt: f32) {
+        (self.0)(state, dt)
+    }
+}
+
+// Builds and runs the engine. Games register what they need here rather than
+// editing the engine itself:
+//
+//     App::new()
+//         .register_component("Spinner", || Box::new(Spinner::default()))
+//         .preload_model("cube.obj")
+//         .on_startup(|state| { state.spawn("cube.obj", [0.0; 3], [0.0; 3], [1.0; 3], None); })
+//         .subscribe(|_state, hit: &BallHit| log::warn!("ball hit body {}", hit.other))
+//         .run()
+//         .await;
+pub struct App {
+    title: String,
+    show_editor: bool,
+    components: Vec<(&'static str, Constructor)>,
+    systems: Vec<Box<dyn System>>,
+    subscribers: Vec<Subscriber>,
+    models: Vec<String>,
+    prefabs: Vec<String>,
+    scene: Option<String>,
+    startup: Vec<Startup>,
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self {
+            title: String::from("Final Year Project"),
+            show_editor: true,
+            components: vec![],
+            systems: vec![],
+            subscribers: vec![],
+            models: vec![],
+            prefabs: vec![],
+            scene: None,
+            startup: vec![],
+        }
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    // Whether the egui editor windows are drawn
+    pub fn editor(mut self, show_editor: bool) -> Self {
+        self.show_editor = show_editor;
+        self
+    }
+
+    // Makes a component available to the editor, prefabs and scenes
+    pub fn register_component<F>(mut self, name: &'static str, constructor: F) -> Self
+    where
+        F: Fn() -> Box<dyn Component> + 'static,
+    {
+        self.components.push((name, Box::new(constructor)));
+        self
+    }
+
+    pub fn add_system<S>(mut self, system: S) -> Self
+    where
+        S: System + 'static,
+    {
+        self.systems.push(Box::new(system));
+        self
+    }
+
+    // Called every frame with the frame's delta time in seconds
+    pub fn on_update<F>(self, update: F) -> Self
+    where
+        F: FnMut(&mut State, f32) + 'static,
+    {
+        self.add_system(FnSystem(update))
+    }
+
+    // Called at the end of each frame for every published event of type `T`
+    pub fn subscribe<T, F>(mut self, handler: F) -> Self
+    where
+        T: 'static,
+        F: FnMut(&mut State, &T) + 'static,
+    {
+        self.subscribers.push(Subscriber::new(handler));
+        self
+    }
+
+    // Called once, after the preloaded models and prefabs are ready
+    pub fn on_startup<F>(mut self, startup: F) -> Self
+    where
+        F: FnOnce(&mut State) + 'static,
+    {
+        self.startup.push(Box::new(startup));
+        self
+    }
+
+    // Models are loaded before startup runs, so they can be spawned straight away
+    pub fn preload_model(mut self, file_name: &str) -> Self {
+        self.models.push(file_name.to_string());
+        self
+    }
+
+    pub fn preload_prefab(mut self, file_name: &str) -> Self {
+        self.prefabs.push(file_name.to_string());
+        self
+    }
+
+    // Scene from the res folder to open once everything is loaded
+    pub fn scene(mut self, file_name: &str) -> Self {
+        self.scene = Some(file_name.to_string());
+        self
+    }
+
+    pub async fn run(self) {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+                console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
+            } else {
+                env_logger::init();
+            }
+        }
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new().with_title(&self.title).build(&event_loop).unwrap();
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            // Winit prevents sizing with CSS, so we have to set
+            // the size manually when on web.
+            use winit::dpi::PhysicalSize;
+            window.set_inner_size(PhysicalSize::new(750, 750));
+
+            use winit::platform::web::WindowExtWebSys;
+            web_sys::window()
+                .and_then(|win| win.document())
+                .and_then(|doc| {
+                    let dst = doc.get_element_by_id("wasm-example")?;
+                    let canvas = web_sys::Element::from(window.canvas());
+                    dst.append_child(&canvas).ok()?;
+                    Some(())
+                })
+                .expect("Couldn't append canvas to document body.");
+        }
+
+        let mut state = State::new(window).await;
+        state.show_editor = self.show_editor;
+        for (name, constructor) in self.components {
+            state.registry.register(name, constructor);
+        }
+        state.systems = self.systems;
+        state.subscribers = self.subscribers;
+
+        for file_name in self.models.iter() {
+            match resources::load_model(file_name, state.data.import_settings, &state.device, &state.queue, &state.texture_bind_group_layout).await {
+                Ok(model) => state.add_model(model),
+                Err(e) => log::warn!("Couldn't load model {}: {}", file_name, e),
+            }
+        }
+        // Prefabs and their models are waited for as well, so startup can spawn them
+        for file_name in self.prefabs {
+            let prefab = match prefab::load_prefab(&file_name).await {
+                Ok(prefab) => prefab,
+                Err(e) => {
+                    log::warn!("Couldn't load prefab {}: {}", file_name, e);
+                    continue;
+                }
+            };
+            if state.find_model(&prefab.model).is_none() {
+                match resources::load_model(&prefab.model, state.data.import_settings, &state.device, &state.queue, &state.texture_bind_group_layout).await {
+                    Ok(model) => state.add_model(model),
+                    Err(e) => log::warn!("Couldn't load model {}: {}", prefab.model, e),
+                }
+            }
+            state.add_prefab(file_name, prefab);
+        }
+        if let Some(file_name) = self.scene {
+            state.load_scene_resource(&file_name);
+        }
+        for startup in self.startup {
+            startup(&mut state);
+        }
+
+        event_loop.run(move |event, _, control_flow| {
+
+            state.gui.handle_event(&event);
+
+            match event {
+                Event::WindowEvent {
+                    ref event,
+                    window_id,
+                } if window_id == state.window().id() && !state.input(event) => match event {
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                    },
+
+                    WindowEvent::ScaleFactorChanged {new_inner_size, ..} => {
+                        state.resize(**new_inner_size)
+                    },
+
+                    _ => {}
+                },
+                Event::RedrawRequested(window_id) if window_id == state.window().id() => {
+                    state.update();
+                    match state.render() {
+                        Ok(_) => {},
+
+                        Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+                        Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                        Err(e) => eprintln!("{:?}", e),
+                    }
+                }
+
+                Event::MainEventsCleared => {
+                    state.window().request_redraw();
+                }
+
+                _ => {}
+            }
+        });
+    }
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
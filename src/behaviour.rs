@@ -0,0 +1,465 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::component::{Context, FieldValue};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Status {
+    Success,
+    Failure,
+    Running,
+}
+
+// Per entity memory the leaves read and write, seeded from the tree file
+#[derive(Clone, Default)]
+pub struct Blackboard {
+    values: BTreeMap<String, FieldValue>,
+}
+
+impl Blackboard {
+    pub fn new() -> Self {
+        Self { values: BTreeMap::new() }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FieldValue> {
+        self.values.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: FieldValue) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.values.remove(name);
+    }
+
+    pub fn get_f32(&self, name: &str) -> Option<f32> {
+        match self.get(name) {
+            Some(FieldValue::F32(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn set_f32(&mut self, name: &str, value: f32) {
+        self.set(name, FieldValue::F32(value));
+    }
+
+    pub fn get_bool(&self, name: &str) -> bool {
+        matches!(self.get(name), Some(FieldValue::Bool(true)))
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.set(name, FieldValue::Bool(value));
+    }
+
+    pub fn get_vec3(&self, name: &str) -> Option<cgmath::Vector3<f32>> {
+        match self.get(name) {
+            Some(FieldValue::Vec3(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn set_vec3(&mut self, name: &str, value: cgmath::Vector3<f32>) {
+        self.set(name, FieldValue::Vec3(value));
+    }
+
+    // One "name: value" line per entry, for the inspector
+    pub fn describe(&self) -> String {
+        self.values
+            .iter()
+            .map(|(name, value)| format!("{}: {:?}", name, value))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub type ActionFn = dyn Fn(&mut Blackboard, &mut Context, f32) -> Status;
+pub type ConditionFn = dyn Fn(&Blackboard, &mut Context) -> bool;
+
+// The named actions and conditions a tree file can refer to
+#[derive(Clone, Default)]
+pub struct Leaves {
+    actions: HashMap<String, Rc<ActionFn>>,
+    conditions: HashMap<String, Rc<ConditionFn>>,
+}
+
+impl Leaves {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn action<F>(mut self, name: &str, action: F) -> Self
+    where
+        F: Fn(&mut Blackboard, &mut Context, f32) -> Status + 'static,
+    {
+        self.actions.insert(name.to_string(), Rc::new(action));
+        self
+    }
+
+    pub fn condition<F>(mut self, name: &str, condition: F) -> Self
+    where
+        F: Fn(&Blackboard, &mut Context) -> bool + 'static,
+    {
+        self.conditions.insert(name.to_string(), Rc::new(condition));
+        self
+    }
+}
+
+// A tree as written in a data file, actions and conditions are looked up by name
+// in the `Leaves` when it's built
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum NodeDef {
+    Sequence(Vec<NodeDef>),
+    Selector(Vec<NodeDef>),
+    // Start from the first child every tick instead of the running one, so
+    // conditions ahead of a running action keep being checked
+    ReactiveSequence(Vec<NodeDef>),
+    ReactiveSelector(Vec<NodeDef>),
+    // Succeeds once `success` children have, fails once that can't happen
+    Parallel { success: usize, children: Vec<NodeDef> },
+    Invert(Box<NodeDef>),
+    AlwaysSucceed(Box<NodeDef>),
+    AlwaysFail(Box<NodeDef>),
+    // Runs the child this many times, 0 repeats forever
+    Repeat(u32, Box<NodeDef>),
+    UntilFail(Box<NodeDef>),
+    // Fails without running the child for this many seconds after it succeeds
+    Cooldown(f32, Box<NodeDef>),
+    Wait(f32),
+    Condition(String),
+    Action(String),
+}
+
+// The contents of a .tree.ron file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TreeDef {
+    #[serde(default)]
+    pub blackboard: BTreeMap<String, FieldValue>,
+    pub root: NodeDef,
+}
+
+enum Kind {
+    // The flag marks the reactive versions
+    Sequence(bool),
+    Selector(bool),
+    Parallel(usize),
+    Invert,
+    AlwaysSucceed,
+    AlwaysFail,
+    Repeat(u32),
+    UntilFail,
+    Cooldown(f32),
+    Wait(f32),
+    Condition(Rc<ConditionFn>),
+    Action(Rc<ActionFn>),
+}
+
+// A node of a running tree. Composites remember which child was running so
+// they carry on from it on the next tick.
+pub struct Node {
+    label: String,
+    kind: Kind,
+    children: Vec<Node>,
+    // What the node returned on the last tick, None if it wasn't reached
+    status: Option<Status>,
+    // Running child of a sequence or selector
+    index: usize,
+    // Finished children of a parallel
+    results: Vec<Option<Status>>,
+    count: u32,
+    timer: f32,
+}
+
+impl Node {
+    fn new(label: String, kind: Kind, children: Vec<Node>) -> Self {
+        Self {
+            label,
+            kind,
+            children,
+            status: None,
+            index: 0,
+            results: vec![],
+            count: 0,
+            timer: 0.0,
+        }
+    }
+
+    pub fn sequence(children: Vec<Node>) -> Self {
+        Self::new(String::from("Sequence"), Kind::Sequence(false), children)
+    }
+
+    pub fn selector(children: Vec<Node>) -> Self {
+        Self::new(String::from("Selector"), Kind::Selector(false), children)
+    }
+
+    pub fn reactive_sequence(children: Vec<Node>) -> Self {
+        Self::new(String::from("ReactiveSequence"), Kind::Sequence(true), children)
+    }
+
+    pub fn reactive_selector(children: Vec<Node>) -> Self {
+        Self::new(String::from("ReactiveSelector"), Kind::Selector(true), children)
+    }
+
+    pub fn parallel(success: usize, children: Vec<Node>) -> Self {
+        Self::new(format!("Parallel ({})", success), Kind::Parallel(success), children)
+    }
+
+    pub fn invert(child: Node) -> Self {
+        Self::new(String::from("Invert"), Kind::Invert, vec![child])
+    }
+
+    pub fn always_succeed(child: Node) -> Self {
+        Self::new(String::from("AlwaysSucceed"), Kind::AlwaysSucceed, vec![child])
+    }
+
+    pub fn always_fail(child: Node) -> Self {
+        Self::new(String::from("AlwaysFail"), Kind::AlwaysFail, vec![child])
+    }
+
+    pub fn repeat(times: u32, child: Node) -> Self {
+        Self::new(format!("Repeat ({})", times), Kind::Repeat(times), vec![child])
+    }
+
+    pub fn until_fail(child: Node) -> Self {
+        Self::new(String::from("UntilFail"), Kind::UntilFail, vec![child])
+    }
+
+    pub fn cooldown(seconds: f32, child: Node) -> Self {
+        Self::new(format!("Cooldown ({}s)", seconds), Kind::Cooldown(seconds), vec![child])
+    }
+
+    pub fn wait(seconds: f32) -> Self {
+        Self::new(format!("Wait ({}s)", seconds), Kind::Wait(seconds), vec![])
+    }
+
+    pub fn condition<F>(name: &str, condition: F) -> Self
+    where
+        F: Fn(&Blackboard, &mut Context) -> bool + 'static,
+    {
+        Self::new(format!("Condition {}", name), Kind::Condition(Rc::new(condition)), vec![])
+    }
+
+    pub fn action<F>(name: &str, action: F) -> Self
+    where
+        F: Fn(&mut Blackboard, &mut Context, f32) -> Status + 'static,
+    {
+        Self::new(format!("Action {}", name), Kind::Action(Rc::new(action)), vec![])
+    }
+
+    pub fn from_def(def: &NodeDef, leaves: &Leaves) -> anyhow::Result<Self> {
+        let build = |children: &Vec<NodeDef>| -> anyhow::Result<Vec<Node>> {
+            children.iter().map(|c| Node::from_def(c, leaves)).collect()
+        };
+        Ok(match def {
+            NodeDef::Sequence(children) => Node::sequence(build(children)?),
+            NodeDef::Selector(children) => Node::selector(build(children)?),
+            NodeDef::ReactiveSequence(children) => Node::reactive_sequence(build(children)?),
+            NodeDef::ReactiveSelector(children) => Node::reactive_selector(build(children)?),
+            NodeDef::Parallel { success, children } => Node::parallel(*success, build(children)?),
+            NodeDef::Invert(child) => Node::invert(Node::from_def(child, leaves)?),
+            NodeDef::AlwaysSucceed(child) => Node::always_succeed(Node::from_def(child, leaves)?),
+            NodeDef::AlwaysFail(child) => Node::always_fail(Node::from_def(child, leaves)?),
+            NodeDef::Repeat(times, child) => Node::repeat(*times, Node::from_def(child, leaves)?),
+            NodeDef::UntilFail(child) => Node::until_fail(Node::from_def(child, leaves)?),
+            NodeDef::Cooldown(seconds, child) => Node::cooldown(*seconds, Node::from_def(child, leaves)?),
+            NodeDef::Wait(seconds) => Node::wait(*seconds),
+            NodeDef::Condition(name) => match leaves.conditions.get(name) {
+                Some(condition) => Self::new(format!("Condition {}", name), Kind::Condition(condition.clone()), vec![]),
+                None => anyhow::bail!("Unknown condition {}", name),
+            },
+            NodeDef::Action(name) => match leaves.actions.get(name) {
+                Some(action) => Self::new(format!("Action {}", name), Kind::Action(action.clone()), vec![]),
+                None => anyhow::bail!("Unknown action {}", name),
+            },
+        })
+    }
+
+    // Forgets any progress, used when a parent stops a running child. Cooldowns
+    // keep counting, being interrupted doesn't make them ready again.
+    pub fn reset(&mut self) {
+        self.index = 0;
+        self.results.clear();
+        self.count = 0;
+        if !matches!(self.kind, Kind::Cooldown(_)) {
+            self.timer = 0.0;
+        }
+        for child in self.children.iter_mut() {
+            child.reset();
+        }
+    }
+
+    fn clear_status(&mut self) {
+        self.status = None;
+        for child in self.children.iter_mut() {
+            child.clear_status();
+        }
+    }
+
+    // Cooldowns run on time, not on ticks, so ones the tree didn't reach this
+    // frame still count down
+    fn cool_down(&mut self, dt: f32) {
+        if let Kind::Cooldown(_) = self.kind {
+            self.timer = (self.timer - dt).max(0.0);
+        }
+        for child in self.children.iter_mut() {
+            child.cool_down(dt);
+        }
+    }
+
+    // Ticks the whole tree from this node down, call on the root once a frame
+    pub fn tick_root(&mut self, blackboard: &mut Blackboard, ctx: &mut Context, dt: f32) -> Status {
+        self.clear_status();
+        self.cool_down(dt);
+        self.tick(blackboard, ctx, dt)
+    }
+
+    fn tick(&mut self, blackboard: &mut Blackboard, ctx: &mut Context, dt: f32) -> Status {
+        let status = match &self.kind {
+            Kind::Sequence(reactive) | Kind::Selector(reactive) => {
+                // A sequence stops on the first failure, a selector on the first success
+                let stop = if matches!(self.kind, Kind::Sequence(_)) { Status::Failure } else { Status::Success };
+                let finish = if stop == Status::Failure { Status::Success } else { Status::Failure };
+                let running = self.index;
+                if *reactive {
+                    self.index = 0;
+                }
+                let mut status = finish;
+                while self.index < self.children.len() {
+                    let child = self.children[self.index].tick(blackboard, ctx, dt);
+                    if child == Status::Running {
+                        status = Status::Running;
+                        break;
+                    }
+                    if child == stop {
+                        status = stop;
+                        break;
+                    }
+                    self.index += 1;
+                }
+                // A reactive node can stop short of the child that was running, which
+                // then has to start over next time it's reached
+                if running != self.index && running < self.children.len() {
+                    self.children[running].reset();
+                }
+                if status != Status::Running {
+                    self.index = 0;
+                }
+                status
+            }
+            Kind::Parallel(success) => {
+                let success = (*success).min(self.children.len());
+                self.results.resize(self.children.len(), None);
+                for (child, result) in self.children.iter_mut().zip(self.results.iter_mut()) {
+                    if result.is_none() {
+                        let status = child.tick(blackboard, ctx, dt);
+                        if status != Status::Running {
+                            *result = Some(status);
+                        }
+                    }
+                }
+                let succeeded = self.results.iter().filter(|r| **r == Some(Status::Success)).count();
+                let failed = self.results.iter().filter(|r| **r == Some(Status::Failure)).count();
+                let status = if succeeded >= success {
+                    Status::Success
+                } else if failed > self.children.len() - success {
+                    Status::Failure
+                } else {
+                    Status::Running
+                };
+                if status != Status::Running {
+                    self.reset();
+                }
+                status
+            }
+            Kind::Invert => match self.children[0].tick(blackboard, ctx, dt) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            },
+            Kind::AlwaysSucceed => match self.children[0].tick(blackboard, ctx, dt) {
+                Status::Running => Status::Running,
+                _ => Status::Success,
+            },
+            Kind::AlwaysFail => match self.children[0].tick(blackboard, ctx, dt) {
+                Status::Running => Status::Running,
+                _ => Status::Failure,
+            },
+            Kind::Repeat(times) => {
+                let times = *times;
+                match self.children[0].tick(blackboard, ctx, dt) {
+                    Status::Failure => {
+                        self.count = 0;
+                        Status::Failure
+                    }
+                    Status::Success => {
+                        self.count += 1;
+                        if times != 0 && self.count >= times {
+                            self.count = 0;
+                            Status::Success
+                        } else {
+                            Status::Running
+                        }
+                    }
+                    Status::Running => Status::Running,
+                }
+            }
+            Kind::UntilFail => match self.children[0].tick(blackboard, ctx, dt) {
+                Status::Failure => Status::Success,
+                _ => Status::Running,
+            },
+            Kind::Cooldown(seconds) => {
+                let seconds = *seconds;
+                if self.timer > 0.0 {
+                    Status::Failure
+                } else {
+                    let status = self.children[0].tick(blackboard, ctx, dt);
+                    if status == Status::Success {
+                        self.timer = seconds;
+                    }
+                    status
+                }
+            }
+            Kind::Wait(seconds) => {
+                self.timer += dt;
+                if self.timer >= *seconds {
+                    self.timer = 0.0;
+                    Status::Success
+                } else {
+                    Status::Running
+                }
+            }
+            Kind::Condition(condition) => {
+                if condition(blackboard, ctx) {
+                    Status::Success
+                } else {
+                    Status::Failure
+                }
+            }
+            Kind::Action(action) => action(blackboard, ctx, dt),
+        };
+        self.status = Some(status);
+        status
+    }
+
+    // The tree with what each node returned on the last tick, one node per line
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        self.describe_into(0, &mut text);
+        text.trim_end().to_string()
+    }
+
+    fn describe_into(&self, depth: usize, text: &mut String) {
+        let status = match self.status {
+            Some(Status::Success) => "Success",
+            Some(Status::Failure) => "Failure",
+            Some(Status::Running) => "Running",
+            None => "-",
+        };
+        text.push_str(&format!("{}{} [{}]\n", "  ".repeat(depth), self.label, status));
+        for child in self.children.iter() {
+            child.describe_into(depth + 1, text);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use wgpu::util::DeviceExt;
+
+use crate::hdr::HDR_FORMAT;
+use crate::texture;
+
+// How many times the bright parts are halved. More spreads the glow further.
+const BLOOM_MIPS: usize = 6;
+
+// Matches `Bloom` in bloom.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    // How far each upsample reaches, in texels
+    radius: f32,
+    _padding: f32,
+}
+
+// Glow around anything brighter than the threshold. The bright parts are cut
+// out at half size, halved again a few times, then added back up the chain so
+// the blur spreads wide without wide filters. The result is in `output`, at half
+// the size of the screen, for the tonemap pass to add on.
+pub struct Bloom {
+    // Each half the size of the one before
+    mips: Vec<texture::Texture>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    // Reads the HDR scene
+    source_bind_group: wgpu::BindGroup,
+    // One reading each mip
+    mip_bind_groups: Vec<wgpu::BindGroup>,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+}
+
+impl Bloom {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, source: &texture::Texture) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("bloom_bind_group_layout"),
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[BloomUniform { threshold: 1.0, knee: 0.5, radius: 1.0, _padding: 0.0 }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let shader = crate::create_fullscreen_shader(device, "Bloom Shader", include_str!("bloom.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let prefilter_pipeline = crate::create_fullscreen_pipeline(device, &pipeline_layout, &shader, "fs_prefilter", HDR_FORMAT, wgpu::BlendState::REPLACE, "Bloom Prefilter Pipeline");
+        let downsample_pipeline = crate::create_fullscreen_pipeline(device, &pipeline_layout, &shader, "fs_downsample", HDR_FORMAT, wgpu::BlendState::REPLACE, "Bloom Downsample Pipeline");
+        // Each upsample is added onto the mip above, which already has its own share
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        let upsample_pipeline = crate::create_fullscreen_pipeline(device, &pipeline_layout, &shader, "fs_upsample", HDR_FORMAT, additive, "Bloom Upsample Pipeline");
+
+        let (mips, source_bind_group, mip_bind_groups) = Self::create_targets(device, config, &bind_group_layout, &uniform_buffer, source);
+
+        Self {
+            mips,
+            bind_group_layout,
+            uniform_buffer,
+            source_bind_group,
+            mip_bind_groups,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        source: &texture::Texture,
+    ) -> (Vec<texture::Texture>, wgpu::BindGroup, Vec<wgpu::BindGroup>) {
+        let mips: Vec<texture::Texture> = (0..BLOOM_MIPS as u32)
+            .map(|i| {
+                let mip_config = wgpu::SurfaceConfiguration {
+                    width: (config.width >> (i + 1)).max(1),
+                    height: (config.height >> (i + 1)).max(1),
+                    ..config.clone()
+                };
+                texture::Texture::create_render_target(device, &mip_config, HDR_FORMAT, 1, "bloom_texture")
+            })
+            .collect();
+        let bind_group = |texture: &texture::Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("bloom_bind_group"),
+            })
+        };
+        let source_bind_group = bind_group(source);
+        let mip_bind_groups = mips.iter().map(bind_group).collect();
+        (mips, source_bind_group, mip_bind_groups)
+    }
+
+    // `source` is the new HDR target, it's remade along with these
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, source: &texture::Texture) {
+        let (mips, source_bind_group, mip_bind_groups) = Self::create_targets(device, config, &self.bind_group_layout, &self.uniform_buffer, source);
+        self.mips = mips;
+        self.source_bind_group = source_bind_group;
+        self.mip_bind_groups = mip_bind_groups;
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, threshold: f32, knee: f32, radius: f32) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[BloomUniform { threshold, knee, radius, _padding: 0.0 }]));
+    }
+
+    pub fn output(&self) -> &texture::Texture {
+        &self.mips[0]
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        crate::draw_fullscreen(encoder, "Bloom Prefilter Pass", &self.mips[0].view, clear, &self.prefilter_pipeline, &[&self.source_bind_group]);
+        for i in 1..self.mips.len() {
+            crate::draw_fullscreen(encoder, "Bloom Downsample Pass", &self.mips[i].view, clear, &self.downsample_pipeline, &[&self.mip_bind_groups[i - 1]]);
+        }
+        for i in (1..self.mips.len()).rev() {
+            crate::draw_fullscreen(encoder, "Bloom Upsample Pass", &self.mips[i - 1].view, wgpu::LoadOp::Load, &self.upsample_pipeline, &[&self.mip_bind_groups[i]]);
+        }
+    }
+}
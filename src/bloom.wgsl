@@ -0,0 +1,75 @@
+// Bloom, cuts out the bright parts of the HDR scene and blurs them down and back up a chain of mips
+
+struct Bloom {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    _padding: f32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: Bloom;
+
+// Single very bright pixels would flicker as things move, they're capped first
+const MAX_BRIGHTNESS: f32 = 64.0;
+
+fn sample_source(uv: vec2<f32>, offset: vec2<f32>, texel: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_source, uv + offset * texel, 0.0).rgb;
+}
+
+// 13 taps over a 4x4 texel area of the source, weighted so edges don't shimmer
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let a = sample_source(uv, vec2<f32>(-2.0, -2.0), texel);
+    let b = sample_source(uv, vec2<f32>(0.0, -2.0), texel);
+    let c = sample_source(uv, vec2<f32>(2.0, -2.0), texel);
+    let d = sample_source(uv, vec2<f32>(-2.0, 0.0), texel);
+    let e = sample_source(uv, vec2<f32>(0.0, 0.0), texel);
+    let f = sample_source(uv, vec2<f32>(2.0, 0.0), texel);
+    let g = sample_source(uv, vec2<f32>(-2.0, 2.0), texel);
+    let h = sample_source(uv, vec2<f32>(0.0, 2.0), texel);
+    let i = sample_source(uv, vec2<f32>(2.0, 2.0), texel);
+    let j = sample_source(uv, vec2<f32>(-1.0, -1.0), texel);
+    let k = sample_source(uv, vec2<f32>(1.0, -1.0), texel);
+    let l = sample_source(uv, vec2<f32>(-1.0, 1.0), texel);
+    let m = sample_source(uv, vec2<f32>(1.0, 1.0), texel);
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+// Keeps what's over the threshold, easing in across the knee instead of a hard cut
+fn threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = max(bloom.knee, 0.0001);
+    let soft = clamp(brightness - bloom.threshold + knee, 0.0, 2.0 * knee);
+    let curve = soft * soft / (4.0 * knee);
+    let contribution = max(curve, brightness - bloom.threshold) / max(brightness, 0.0001);
+    return color * contribution;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = min(downsample(in.uv), vec3<f32>(MAX_BRIGHTNESS));
+    return vec4<f32>(threshold(color), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent filter from the smaller mip, added onto the one above
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = bloom.radius / vec2<f32>(textureDimensions(t_source));
+    let uv = in.uv;
+    var color = sample_source(uv, vec2<f32>(0.0, 0.0), texel) * 4.0;
+    color += (sample_source(uv, vec2<f32>(-1.0, 0.0), texel) + sample_source(uv, vec2<f32>(1.0, 0.0), texel)
+        + sample_source(uv, vec2<f32>(0.0, -1.0), texel) + sample_source(uv, vec2<f32>(0.0, 1.0), texel)) * 2.0;
+    color += sample_source(uv, vec2<f32>(-1.0, -1.0), texel) + sample_source(uv, vec2<f32>(1.0, -1.0), texel)
+        + sample_source(uv, vec2<f32>(-1.0, 1.0), texel) + sample_source(uv, vec2<f32>(1.0, 1.0), texel);
+    return vec4<f32>(color / 16.0, 1.0);
+}
@@ -1,6 +1,10 @@
 use winit::{
     event::*,
 };
+use serde::{Deserialize, Serialize};
+use cgmath::{InnerSpace, SquareMatrix};
+
+use crate::physics::Ray;
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -12,6 +16,7 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 
 
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -30,6 +35,30 @@ impl Camera {
 
         return OPENGL_TO_WGPU_MATRIX * proj * view;
     }
+
+    // Ray from the eye through a point on the screen, given in pixels from the
+    // top left of a screen `width` by `height` pixels
+    pub fn screen_ray(&self, x: f32, y: f32, width: f32, height: f32) -> Ray {
+        let ndc_x = 2.0 * x / width - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / height;
+        let view_proj = self.build_view_projection_matrix();
+        let inverse = view_proj.invert().unwrap_or(cgmath::Matrix4::identity());
+        // Depths of the near and far planes are read off the matrix rather than
+        // assumed, so this keeps working whatever the depth range ends up being
+        let forward = (self.target - self.eye).normalize();
+        let depth = |distance: f32| {
+            let clip = view_proj * (self.eye + forward * distance).to_homogeneous();
+            clip.z / clip.w
+        };
+        let near = inverse * cgmath::Vector4::new(ndc_x, ndc_y, depth(self.znear), 1.0);
+        let far = inverse * cgmath::Vector4::new(ndc_x, ndc_y, depth(self.zfar), 1.0);
+        let near = near.truncate() / near.w;
+        let far = far.truncate() / far.w;
+        Ray {
+            origin: near,
+            direction: (far - near).normalize(),
+        }
+    }
 }
 
 pub struct CameraController {
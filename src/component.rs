@@ -1,9 +1,199 @@
-use winit::event;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use winit::event::{self, VirtualKeyCode};
+use crate::events::{Event, Events};
 use crate::physics::RigidBody;
+use crate::scheduler::Scheduler;
+
+// What a component can reach while it runs
+pub struct Context<'a> {
+    pub rigidbodys: &'a mut Vec<RigidBody>,
+    // Index of the component's own body in `rigidbodys`
+    pub body_index: usize,
+    pub events: &'a mut Events,
+    pub scheduler: &'a mut Scheduler,
+}
 
+impl<'a> Context<'a> {
+    pub fn body(&mut self) -> &mut RigidBody {
+        &mut self.rigidbodys[self.body_index]
+    }
+}
 
 pub trait Component {
-    fn start(&mut self,rigidbodys : &mut Vec<RigidBody>,body_index: usize);
-    fn update(&mut self, dt: f32,rigidbodys : &mut Vec<RigidBody>,body_index: usize);
+    // Name the component is registered under in the `ComponentRegistry`
+    fn name(&self) -> &'static str;
+    fn start(&mut self, ctx: &mut Context);
+    fn update(&mut self, dt: f32, ctx: &mut Context);
     fn input(&mut self,event: &event::WindowEvent) -> bool;
-}
\ No newline at end of file
+    // Called for every event delivered at the end of the frame
+    fn on_event(&mut self, _event: &Event, _ctx: &mut Context) {}
+    // Settings exposed to the inspector and saved with the scene
+    fn fields(&mut self) -> Vec<(&'static str, Field<'_>)> {
+        vec![]
+    }
+    // Read only values shown live in the inspector, like a state machine's current state
+    fn status(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+}
+
+// A reflected, editable reference to one of a component's settings
+pub enum Field<'a> {
+    F32(&'a mut f32),
+    Bool(&'a mut bool),
+    Key(&'a mut VirtualKeyCode),
+    Vec3(&'a mut cgmath::Vector3<f32>),
+    String(&'a mut String),
+    // One of a fixed set of names, saved as a string
+    Choice(&'a mut String, &'static [&'static str]),
+}
+
+// Owned copy of a field, this is what gets serialized
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    F32(f32),
+    Bool(bool),
+    Key(VirtualKeyCode),
+    Vec3(cgmath::Vector3<f32>),
+    String(String),
+}
+
+impl<'a> Field<'a> {
+    pub fn get(&self) -> FieldValue {
+        match self {
+            Field::F32(v) => FieldValue::F32(**v),
+            Field::Bool(v) => FieldValue::Bool(**v),
+            Field::Key(v) => FieldValue::Key(**v),
+            Field::Vec3(v) => FieldValue::Vec3(**v),
+            Field::String(v) => FieldValue::String((**v).clone()),
+            Field::Choice(v, _) => FieldValue::String((**v).clone()),
+        }
+    }
+
+    // Mismatched types are ignored so old saves still load after a field changes type
+    pub fn set(&mut self, value: &FieldValue) {
+        match (self, value) {
+            (Field::F32(v), FieldValue::F32(new)) => **v = *new,
+            (Field::Bool(v), FieldValue::Bool(new)) => **v = *new,
+            (Field::Key(v), FieldValue::Key(new)) => **v = *new,
+            (Field::Vec3(v), FieldValue::Vec3(new)) => **v = *new,
+            (Field::String(v), FieldValue::String(new)) => **v = new.clone(),
+            (Field::Choice(v, options), FieldValue::String(new)) if options.contains(&new.as_str()) => **v = new.clone(),
+            _ => {}
+        }
+    }
+}
+
+impl FieldValue {
+    pub fn as_field(&mut self) -> Field<'_> {
+        match self {
+            FieldValue::F32(v) => Field::F32(v),
+            FieldValue::Bool(v) => Field::Bool(v),
+            FieldValue::Key(v) => Field::Key(v),
+            FieldValue::Vec3(v) => Field::Vec3(v),
+            FieldValue::String(v) => Field::String(v),
+        }
+    }
+}
+
+// The saved settings of a component, used to rebuild it through the registry
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ComponentData {
+    pub name: String,
+    pub fields: BTreeMap<String, FieldValue>,
+}
+
+impl ComponentData {
+    pub fn fields(&mut self) -> Vec<(&str, Field<'_>)> {
+        self.fields.iter_mut().map(|(name, value)| (name.as_str(), value.as_field())).collect()
+    }
+}
+
+pub fn save(component: &mut dyn Component) -> ComponentData {
+    ComponentData {
+        name: component.name().to_string(),
+        fields: component.fields().into_iter().map(|(name, field)| (name.to_string(), field.get())).collect(),
+    }
+}
+
+// Sets a live component's fields from saved data, leaving the rest of its state alone
+pub fn load(component: &mut dyn Component, data: &ComponentData) {
+    for (name, mut field) in component.fields() {
+        if let Some(value) = data.fields.get(name) {
+            field.set(value);
+        }
+    }
+}
+
+struct ComponentEntry {
+    name: &'static str,
+    constructor: Box<dyn Fn() -> Box<dyn Component>>,
+}
+
+// Every component the editor and scene loader know how to create
+pub struct ComponentRegistry {
+    entries: Vec<ComponentEntry>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self { entries: vec![] }
+    }
+
+    // Registering a name twice replaces the old constructor
+    pub fn register<F>(&mut self, name: &'static str, constructor: F)
+    where
+        F: Fn() -> Box<dyn Component> + 'static,
+    {
+        let entry = ComponentEntry { name, constructor: Box::new(constructor) };
+        match self.entries.iter().position(|e| e.name == name) {
+            Some(index) => self.entries[index] = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|e| e.name).collect()
+    }
+
+    // A component with its default settings
+    pub fn create(&self, name: &str) -> Option<Box<dyn Component>> {
+        self.entries.iter().find(|e| e.name == name).map(|e| (e.constructor)())
+    }
+
+    pub fn build(&self, data: &ComponentData) -> Option<Box<dyn Component>> {
+        let mut component = match self.create(&data.name) {
+            Some(component) => component,
+            None => {
+                log::warn!("Component {} isn't registered", data.name);
+                return None;
+            }
+        };
+        load(component.as_mut(), data);
+        Some(component)
+    }
+
+    // Default settings of a registered component, for templates and prefabs
+    pub fn default_data(&self, name: &str) -> Option<ComponentData> {
+        self.create(name).map(|mut c| save(c.as_mut()))
+    }
+}
+
+impl Default for ComponentRegistry {
+    // The components that ship with the engine
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register("Paddle", || Box::new(crate::components::paddle::Paddle::default()));
+        registry.register("AI Paddle", || Box::new(crate::components::ai_paddle::AiPaddle::default()));
+        registry.register("Ball", || Box::new(crate::components::ball::Ball::new()));
+        registry.register("Goal Zone", || Box::new(crate::components::goal_zone::GoalZone));
+        registry.register("Script", || Box::new(crate::components::script::Script::default()));
+        registry.register("Behaviour Tree", || {
+            use crate::components::behaviour_tree::{patrol_leaves, BehaviourTree};
+            Box::new(BehaviourTree::from_file("Behaviour Tree", "patrol.tree.ron", patrol_leaves()))
+        });
+        registry
+    }
+}
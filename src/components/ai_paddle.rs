@@ -0,0 +1,213 @@
+use crate::component::{Component, Context, Field};
+use crate::components::ball::{BallHit, Goal, Served};
+use crate::events::Event;
+use crate::physics::{RigidBody, Shape};
+use winit::event::*;
+use cgmath::Vector3;
+use std::collections::VecDeque;
+
+const DIFFICULTIES: [&str; 5] = ["Easy", "Medium", "Hard", "Perfect", "Custom"];
+
+// How far ahead the ball's path is followed, and in what steps
+const PREDICT_STEP: f32 = 1.0 / 60.0;
+const PREDICT_SECONDS: f32 = 5.0;
+
+// A paddle that plays itself. Like `Paddle` it slides along x. It follows the
+// ball the `Ball` component last announced, marching its path forward off
+// everything it would bounce on to find where it crosses the paddle's line, and
+// heads there. While the ball is going away it drifts back to where it started.
+//
+// The difficulty picks its reaction delay, top speed and aiming error. Set it
+// to Custom to use the values of those fields instead.
+pub struct AiPaddle {
+    difficulty: String,
+    // Seconds old the ball it sees is, so a bounce or a hit only reaches it that late
+    reaction_delay: f32,
+    max_speed: f32,
+    // Furthest the paddle aims from the predicted point, picked fresh for each shot
+    error: f32,
+    ball: Option<usize>,
+    home: Vector3<f32>,
+    target: f32,
+    // (time, position, velocity) of the ball, oldest first
+    seen: VecDeque<(f32, Vector3<f32>, Vector3<f32>)>,
+    time: f32,
+    aim_offset: f32,
+    last_ball_velocity: Vector3<f32>,
+    rng: u32,
+}
+
+impl AiPaddle {
+    pub fn new() -> Self {
+        Self {
+            difficulty: String::from("Medium"),
+            reaction_delay: 0.18,
+            max_speed: 7.0,
+            error: 0.8,
+            ball: None,
+            home: Vector3::new(0.0, 0.0, 0.0),
+            target: 0.0,
+            seen: VecDeque::new(),
+            time: 0.0,
+            aim_offset: 0.0,
+            last_ball_velocity: Vector3::new(0.0, 0.0, 0.0),
+            rng: 0x9e37_79b9,
+        }
+    }
+
+    // (reaction delay, max speed, error)
+    fn settings(&self) -> (f32, f32, f32) {
+        match self.difficulty.as_str() {
+            "Easy" => (0.3, 5.0, 1.5),
+            "Medium" => (0.18, 7.0, 0.8),
+            "Hard" => (0.08, 10.0, 0.3),
+            "Perfect" => (0.0, 20.0, 0.0),
+            _ => (self.reaction_delay, self.max_speed, self.error),
+        }
+    }
+
+    // Records where the ball is now and forgets what's older than the delay
+    // needs. The front is then the ball as it was `reaction_delay` ago, or None
+    // if it hasn't been watched that long yet.
+    fn delayed_ball(&mut self, ball: &RigidBody, reaction_delay: f32) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.seen.push_back((self.time, ball.position, ball.velocity));
+        let seen_at = self.time - reaction_delay;
+        while self.seen.len() > 1 && self.seen[1].0 <= seen_at {
+            self.seen.pop_front();
+        }
+        self.seen
+            .front()
+            .filter(|(time, _, _)| *time <= seen_at)
+            .map(|(_, position, velocity)| (*position, *velocity))
+    }
+
+    // xorshift, random enough for aiming mistakes and works the same on web
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32
+    }
+
+    fn think(&mut self, ctx: &mut Context) {
+        let (reaction_delay, _, error) = self.settings();
+        let ball = match self.ball {
+            Some(ball) if ball < ctx.rigidbodys.len() && !ctx.rigidbodys[ball].removed => ball,
+            _ => {
+                self.target = self.home.x;
+                return;
+            }
+        };
+        // Until it has seen the ball for long enough it keeps its old target
+        let mut probe = ctx.rigidbodys[ball].clone();
+        let (position, velocity) = match self.delayed_ball(&probe, reaction_delay) {
+            Some(seen) => seen,
+            None => return,
+        };
+        probe.position = position;
+        probe.velocity = velocity;
+        // A new trajectory, so a new mistake
+        if velocity != self.last_ball_velocity {
+            self.last_ball_velocity = velocity;
+            self.aim_offset = (self.random() * 2.0 - 1.0) * error;
+        }
+        self.target = match predict_intercept(ctx.rigidbodys, ball, probe, ctx.body_index) {
+            Some(x) => x + self.aim_offset,
+            None => self.home.x,
+        };
+    }
+}
+
+impl Default for AiPaddle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Follows the ball forward until it reaches the paddle's y, bouncing it off
+// every other body on the way. `probe` is the ball as the paddle saw it. None if
+// it's heading away or doesn't get there in time.
+fn predict_intercept(rigidbodys: &[RigidBody], ball: usize, mut probe: RigidBody, paddle: usize) -> Option<f32> {
+    let line = rigidbodys[paddle].position.y;
+    if (line - probe.position.y) * probe.velocity.y <= 0.0 {
+        return None;
+    }
+    if let Shape::Box(_) = probe.shape {
+        probe.shape = Shape::Sphere(0.0);
+    }
+    let mut time = 0.0;
+    while time < PREDICT_SECONDS {
+        probe.position += probe.velocity * PREDICT_STEP;
+        time += PREDICT_STEP;
+        if (line - probe.position.y) * probe.velocity.y <= 0.0 {
+            return Some(probe.position.x);
+        }
+        for (index, other) in rigidbodys.iter().enumerate() {
+            if index == ball || index == paddle || other.trigger || other.removed {
+                continue;
+            }
+            if probe.is_intersecting(other) {
+                let normal = probe.contact_normal(other);
+                probe.reflect(normal);
+            }
+        }
+    }
+    None
+}
+
+impl Component for AiPaddle {
+    fn name(&self) -> &'static str {
+        "AI Paddle"
+    }
+    fn start(&mut self, ctx: &mut Context) {
+        //initialize paddle, it returns here between shots
+        self.home = ctx.body().position;
+        self.target = self.home.x;
+        self.rng ^= (ctx.body_index as u32 + 1).wrapping_mul(0x85eb_ca6b);
+    }
+    fn update(&mut self, dt: f32, ctx: &mut Context) {
+        //update paddle
+        let (_, max_speed, _) = self.settings();
+        self.time += dt;
+        self.think(ctx);
+
+        let rigidbody = ctx.body();
+        let distance = self.target - rigidbody.position.x;
+        rigidbody.velocity.x = if distance.abs() < 0.05 || dt <= 0.0 {
+            0.0
+        } else {
+            // Don't overshoot the target in one frame
+            (distance / dt).max(-max_speed).min(max_speed)
+        };
+    }
+    fn on_event(&mut self, event: &Event, _ctx: &mut Context) {
+        let ball = if let Some(served) = event.get::<Served>() {
+            Some(served.ball)
+        } else if let Some(hit) = event.get::<BallHit>() {
+            Some(hit.ball)
+        } else if event.is::<Goal>() {
+            None
+        } else {
+            return;
+        };
+        // What it saw of another ball doesn't tell it anything about this one
+        if ball != self.ball {
+            self.seen.clear();
+        }
+        self.ball = ball;
+    }
+    fn input(&mut self, _event: &WindowEvent) -> bool {
+        false
+    }
+    fn fields(&mut self) -> Vec<(&'static str, Field<'_>)> {
+        vec![
+            ("difficulty", Field::Choice(&mut self.difficulty, &DIFFICULTIES)),
+            ("reaction delay", Field::F32(&mut self.reaction_delay)),
+            ("max speed", Field::F32(&mut self.max_speed)),
+            ("error", Field::F32(&mut self.error)),
+        ]
+    }
+    fn status(&self) -> Vec<(&'static str, String)> {
+        vec![("target x", format!("{:.2}", self.target))]
+    }
+}
@@ -1,45 +1,143 @@
-use crate::component::Component;
+use crate::component::{Component, Context, Field};
+use crate::components::goal_zone::GoalEntered;
+use crate::events::{CollisionStarted, Event};
+use crate::pong::MatchOver;
+use crate::fsm::{FsmState, StateMachine};
 use winit::event::*;
-use crate::physics::RigidBody;
 use cgmath::Vector3;
 
 use gloo::console::log;
 
 
 
-pub struct Ball {}
+// Published when the ball bounces off another body
+pub struct BallHit {
+    pub ball: usize,
+    pub other: usize,
+}
+
+// Published when the ball is put into play
+pub struct Served {
+    pub ball: usize,
+}
+
+// Published when the ball leaves the court through a goal or past the bounds.
+// `side` is 1 if it went out past the positive y edge and -1 past the negative one.
+pub struct Goal {
+    pub ball: usize,
+    pub side: i32,
+}
+
+struct BallData {
+    start_velocity: Vector3<f32>,
+    // How far from the serve position along y the ball can go before it's a goal
+    bounds: f32,
+    serve_delay: f32,
+    serve_position: Vector3<f32>,
+    // Sign applied to the start velocity, the next serve goes towards whoever conceded
+    direction: f32,
+    // Side of the goal zone the ball went into this point
+    goal_side: Option<i32>,
+}
+
+// The ball runs the Pong flow as a state machine:
+// Serve -> InPlay once the serve delay is up, InPlay -> Scored when it enters a
+// goal zone or leaves the court, and Scored -> Serve two seconds later. Any
+// state goes to Stopped when the match is over.
+pub struct Ball {
+    data: BallData,
+    fsm: StateMachine<BallData>,
+}
 
 impl Ball {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            data: BallData {
+                start_velocity: Vector3::new(3.0, -5.0, 0.0),
+                bounds: 8.0,
+                serve_delay: 1.0,
+                serve_position: Vector3::new(0.0, 0.0, 0.0),
+                direction: 1.0,
+                goal_side: None,
+            },
+            fsm: flow(),
+        }
     }
 }
 
+fn flow() -> StateMachine<BallData> {
+    StateMachine::new()
+        .state(FsmState::new("Serve").on_enter(|ball: &mut BallData, ctx| {
+            ball.goal_side = None;
+            let rigidbody = ctx.body();
+            rigidbody.position = ball.serve_position;
+            rigidbody.velocity = Vector3::new(0.0, 0.0, 0.0);
+        }))
+        .state(FsmState::new("InPlay")
+            .on_enter(|ball: &mut BallData, ctx| {
+                ctx.body().velocity = ball.start_velocity * ball.direction;
+                ctx.events.publish(Served { ball: ctx.body_index });
+            })
+            .on_event(|ball: &mut BallData, event, ctx| {
+                if let Some(goal) = event.get::<GoalEntered>() {
+                    if goal.body == ctx.body_index {
+                        let zone = ctx.rigidbodys[goal.zone].position;
+                        ball.goal_side = Some(if zone.y > ball.serve_position.y { 1 } else { -1 });
+                    }
+                } else if let Some(collision) = event.get::<CollisionStarted>() {
+                    let other = if collision.a == ctx.body_index {
+                        collision.b
+                    } else if collision.b == ctx.body_index {
+                        collision.a
+                    } else {
+                        return;
+                    };
+                    if ctx.rigidbodys[other].trigger {
+                        return;
+                    }
+                    let normal = ctx.rigidbodys[ctx.body_index].contact_normal(&ctx.rigidbodys[other]);
+                    let rigidbody = ctx.body();
+                    rigidbody.reflect(normal);
+                    log!("rigidbody.velocity: {:?}", rigidbody.velocity.y);
+                    ctx.events.publish(BallHit { ball: ctx.body_index, other });
+                }
+            }))
+        .state(FsmState::new("Scored").on_enter(|ball: &mut BallData, ctx| {
+            let out_past_top = ctx.body().position.y > ball.serve_position.y;
+            let side = ball.goal_side.unwrap_or(if out_past_top { 1 } else { -1 });
+            ctx.body().velocity = Vector3::new(0.0, 0.0, 0.0);
+            ball.direction = side as f32 * ball.start_velocity.y.signum();
+            ctx.events.publish(Goal { ball: ctx.body_index, side });
+        }))
+        .state(FsmState::new("Stopped").on_enter(|ball: &mut BallData, ctx| {
+            let rigidbody = ctx.body();
+            rigidbody.position = ball.serve_position;
+            rigidbody.velocity = Vector3::new(0.0, 0.0, 0.0);
+        }))
+        .transition("Serve", "InPlay", |ball, time_in_state, _| time_in_state >= ball.serve_delay)
+        .transition("InPlay", "Scored", |ball, _, ctx| {
+            ball.goal_side.is_some() || (ctx.body().position.y - ball.serve_position.y).abs() > ball.bounds
+        })
+        .transition_after("Scored", "Serve", 2.0)
+        .transition_on::<MatchOver>("*", "Stopped")
+}
+
 impl Component for Ball {
-    fn start(&mut self,rigidbodys : &mut Vec<RigidBody>,body_index: usize) {
-        //initialize ball
-        let rigidbody = &mut rigidbodys[body_index];
-        rigidbody.velocity = Vector3::new(3.0, -5.0, 0.0);
+    fn name(&self) -> &'static str {
+        "Ball"
+    }
+    fn start(&mut self, ctx: &mut Context) {
+        //initialize ball, it's served from wherever it was placed
+        self.data.serve_position = ctx.body().position;
         log!("ball start");
 
     }
-    fn update(&mut self, dt: f32, rigidbodys : &mut Vec<RigidBody>,body_index: usize){
+    fn update(&mut self, dt: f32, ctx: &mut Context){
         //update ball
-        let test = rigidbodys.clone();
-
-        let rigidbody = &mut rigidbodys[body_index];
-        for i in 0..test.len(){
-            if i != body_index{
-                let other = &test[i];
-                if rigidbody.is_intersecting(other){
-                    let arb : Vector3<f32> = Vector3::unit_x();
-                    let perp = rigidbody.velocity.cross(arb);
-                    rigidbody.velocity = perp;
-                    log!("rigidbody.velocity: {:?}", rigidbody.velocity.y);
-                }
-            }
-        }
-
+        self.fsm.update(&mut self.data, dt, ctx);
+    }
+    fn on_event(&mut self, event: &Event, ctx: &mut Context) {
+        self.fsm.handle_event(&mut self.data, event, ctx);
     }
     fn input(&mut self,event: &WindowEvent) -> bool{
         //input for ball
@@ -47,4 +145,14 @@ impl Component for Ball {
             _ => false
         }
     }
-}
\ No newline at end of file
+    fn fields(&mut self) -> Vec<(&'static str, Field<'_>)> {
+        vec![
+            ("start velocity", Field::Vec3(&mut self.data.start_velocity)),
+            ("bounds", Field::F32(&mut self.data.bounds)),
+            ("serve delay", Field::F32(&mut self.data.serve_delay)),
+        ]
+    }
+    fn status(&self) -> Vec<(&'static str, String)> {
+        vec![("state", self.fsm.current().to_string())]
+    }
+}
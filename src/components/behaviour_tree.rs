@@ -0,0 +1,186 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use cgmath::{InnerSpace, Vector3};
+use winit::event::*;
+
+use crate::behaviour::{Blackboard, Leaves, Node, Status, TreeDef};
+use crate::component::{Component, Context, Field};
+use crate::resources;
+
+// Ticks a behaviour tree once a frame with the object's own blackboard. The tree
+// is either built in code, or read from a .tree.ron file in res with its
+// actions and conditions looked up in `leaves`:
+//
+//     registry.register("Guard", || Box::new(BehaviourTree::from_file("Guard", "guard.tree.ron", guard_leaves())));
+//
+// The tree and what each node returned last frame show up in the Objects window.
+// The registered "Behaviour Tree" component reads its file with `patrol_leaves`.
+pub struct BehaviourTree {
+    name: &'static str,
+    root: Option<Node>,
+    blackboard: Blackboard,
+    leaves: Leaves,
+    // Trees built in code have no file to point at
+    in_code: bool,
+    file_name: String,
+    loaded_file: String,
+    inbox: Rc<RefCell<Option<(String, String)>>>,
+}
+
+impl BehaviourTree {
+    pub fn new(name: &'static str, root: Node) -> Self {
+        Self {
+            name,
+            root: Some(root),
+            blackboard: Blackboard::new(),
+            leaves: Leaves::new(),
+            in_code: true,
+            file_name: String::new(),
+            loaded_file: String::new(),
+            inbox: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    pub fn from_file(name: &'static str, file_name: &str, leaves: Leaves) -> Self {
+        Self {
+            name,
+            root: None,
+            blackboard: Blackboard::new(),
+            leaves,
+            in_code: false,
+            file_name: file_name.to_string(),
+            loaded_file: String::new(),
+            inbox: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    pub fn with_blackboard(mut self, blackboard: Blackboard) -> Self {
+        self.blackboard = blackboard;
+        self
+    }
+
+    pub fn blackboard_mut(&mut self) -> &mut Blackboard {
+        &mut self.blackboard
+    }
+
+    fn request_tree(&mut self) {
+        if self.file_name.is_empty() || self.file_name == self.loaded_file {
+            return;
+        }
+        self.loaded_file = self.file_name.clone();
+        let file_name = self.file_name.clone();
+        let inbox = self.inbox.clone();
+        resources::spawn(async move {
+            match resources::load_string(&file_name).await {
+                Ok(text) => *inbox.borrow_mut() = Some((file_name, text)),
+                Err(e) => log::warn!("Couldn't load behaviour tree {}: {}", file_name, e),
+            }
+        });
+    }
+
+    fn poll_tree(&mut self) {
+        self.request_tree();
+        let loaded = self.inbox.borrow_mut().take();
+        if let Some((file_name, text)) = loaded {
+            let tree = ron::from_str::<TreeDef>(&text)
+                .map_err(anyhow::Error::from)
+                .and_then(|tree| Ok((Node::from_def(&tree.root, &self.leaves)?, tree.blackboard)));
+            match tree {
+                Ok((root, blackboard)) => {
+                    for (name, value) in blackboard {
+                        if self.blackboard.get(&name).is_none() {
+                            self.blackboard.set(&name, value);
+                        }
+                    }
+                    self.root = Some(root);
+                }
+                Err(e) => log::warn!("Couldn't build behaviour tree {}: {}", file_name, e),
+            }
+        }
+    }
+}
+
+impl Component for BehaviourTree {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn start(&mut self, _ctx: &mut Context) {
+        self.request_tree();
+    }
+    fn update(&mut self, dt: f32, ctx: &mut Context) {
+        self.poll_tree();
+        if let Some(root) = &mut self.root {
+            root.tick_root(&mut self.blackboard, ctx, dt);
+        }
+    }
+    fn input(&mut self, _event: &WindowEvent) -> bool {
+        false
+    }
+    fn fields(&mut self) -> Vec<(&'static str, Field<'_>)> {
+        if self.in_code {
+            return vec![];
+        }
+        vec![("tree", Field::String(&mut self.file_name))]
+    }
+    fn status(&self) -> Vec<(&'static str, String)> {
+        let tree = match &self.root {
+            Some(root) => root.describe(),
+            None => String::from("loading"),
+        };
+        vec![("tree", format!("\n{}", tree)), ("blackboard", format!("\n{}", self.blackboard.describe()))]
+    }
+}
+
+// Leaves for walking a square around where the object started. The blackboard
+// can set `speed` and `range`, the half width of the square.
+//
+//     NextWaypoint  sets `target` to the next corner, always succeeds
+//     MoveToTarget  heads for `target` along the ground, succeeds on arrival
+//     HasTarget     whether there's a `target` to head for
+pub fn patrol_leaves() -> Leaves {
+    Leaves::new()
+        .action("NextWaypoint", |blackboard, ctx, _dt| {
+            let home = match blackboard.get_vec3("home") {
+                Some(home) => home,
+                None => {
+                    let home = ctx.body().position;
+                    blackboard.set_vec3("home", home);
+                    home
+                }
+            };
+            let range = blackboard.get_f32("range").unwrap_or(5.0);
+            let corners = [(1.0, 1.0), (-1.0, 1.0), (-1.0, -1.0), (1.0, -1.0)];
+            let waypoint = blackboard.get_f32("waypoint").unwrap_or(0.0) as usize % corners.len();
+            let (x, z) = corners[waypoint];
+            blackboard.set_vec3("target", home + Vector3::new(x, 0.0, z) * range);
+            blackboard.set_f32("waypoint", ((waypoint + 1) % corners.len()) as f32);
+            Status::Success
+        })
+        .action("MoveToTarget", |blackboard, ctx, dt| {
+            let target = match blackboard.get_vec3("target") {
+                Some(target) => target,
+                None => return Status::Failure,
+            };
+            let speed = blackboard.get_f32("speed").unwrap_or(2.0);
+            let body = ctx.body();
+            let mut offset = target - body.position;
+            offset.y = 0.0;
+            if offset.magnitude() < 0.1 {
+                body.velocity.x = 0.0;
+                body.velocity.z = 0.0;
+                blackboard.remove("target");
+                return Status::Success;
+            }
+            // Don't overshoot the target in one frame
+            let velocity = if dt > 0.0 && offset.magnitude() / dt < speed {
+                offset / dt
+            } else {
+                offset.normalize() * speed
+            };
+            body.velocity.x = velocity.x;
+            body.velocity.z = velocity.z;
+            Status::Running
+        })
+        .condition("HasTarget", |blackboard, _ctx| blackboard.get_vec3("target").is_some())
+}
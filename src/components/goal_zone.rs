@@ -0,0 +1,39 @@
+use crate::component::{Component, Context};
+use crate::events::{CollisionStarted, Event};
+use winit::event::*;
+
+// Published when a body enters a goal zone
+pub struct GoalEntered {
+    pub zone: usize,
+    pub body: usize,
+}
+
+// Turns its body into a trigger and reports what goes into it. A `Ball` that
+// enters one scores against that side.
+pub struct GoalZone;
+
+impl Component for GoalZone {
+    fn name(&self) -> &'static str {
+        "Goal Zone"
+    }
+    fn start(&mut self, ctx: &mut Context) {
+        ctx.body().trigger = true;
+    }
+    fn update(&mut self, _dt: f32, _ctx: &mut Context) {
+    }
+    fn on_event(&mut self, event: &Event, ctx: &mut Context) {
+        if let Some(collision) = event.get::<CollisionStarted>() {
+            let body = if collision.a == ctx.body_index {
+                collision.b
+            } else if collision.b == ctx.body_index {
+                collision.a
+            } else {
+                return;
+            };
+            ctx.events.publish(GoalEntered { zone: ctx.body_index, body });
+        }
+    }
+    fn input(&mut self, _event: &WindowEvent) -> bool {
+        false
+    }
+}
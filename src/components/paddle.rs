@@ -1,12 +1,12 @@
-use crate::component::Component;
+use crate::component::{Component, Context, Field};
 use winit::event::*;
 
 
-use crate::physics::RigidBody;
 use gloo::console::log;
 
 pub struct Paddle {
     input_keys : Vec<VirtualKeyCode>,
+    speed : f32,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
@@ -22,27 +22,37 @@ impl Paddle {
             is_left_pressed: false,
             is_right_pressed: false,
             input_keys,
+            speed: 10.0,
         }
     }
     
 }
 
+impl Default for Paddle {
+    fn default() -> Self {
+        Self::new(vec![VirtualKeyCode::I, VirtualKeyCode::K, VirtualKeyCode::J, VirtualKeyCode::L])
+    }
+}
+
 
 impl Component for Paddle{
-    fn start(&mut self,rigidbodys : &mut Vec<RigidBody>,body_index: usize){
+    fn name(&self) -> &'static str {
+        "Paddle"
+    }
+    fn start(&mut self, _ctx: &mut Context){
         //initialize paddle
     }
-    fn update(&mut self, dt: f32, rigidbodys : &mut Vec<RigidBody>,body_index: usize){
+    fn update(&mut self, _dt: f32, ctx: &mut Context){
         //update paddle
 
         
-        let rigidbody = &mut rigidbodys[body_index];
+        let rigidbody = ctx.body();
         
         if self.is_left_pressed {
-            rigidbody.velocity.x = -10.0;
+            rigidbody.velocity.x = -self.speed;
         }
         else if self.is_right_pressed {
-            rigidbody.velocity.x = 10.0;
+            rigidbody.velocity.x = self.speed;
         }
         else{
             rigidbody.velocity.x = 0.0;
@@ -83,4 +93,12 @@ impl Component for Paddle{
             _ => false,
         }
     }
+    fn fields(&mut self) -> Vec<(&'static str, Field<'_>)> {
+        let mut fields = vec![("speed", Field::F32(&mut self.speed))];
+        let names = ["forward", "backward", "left", "right"];
+        for (name, key) in names.into_iter().zip(self.input_keys.iter_mut()) {
+            fields.push((name, Field::Key(key)));
+        }
+        fields
+    }
 }
\ No newline at end of file
@@ -0,0 +1,352 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use cgmath::{Deg, Euler, InnerSpace, Quaternion, Vector3};
+use gloo::console::log;
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+use wasm_timer::Instant;
+use winit::event::*;
+
+use crate::component::{Component, Context, Field};
+use crate::events::{CollisionEnded, CollisionStarted, Event};
+use crate::{resources, State};
+
+// How often a script file is fetched again to look for changes
+const RELOAD_SECONDS: f32 = 1.0;
+
+// One per script file, however many objects run it. The first script to poll
+// after `RELOAD_SECONDS` starts the fetch, and `version` goes up whenever the
+// text changes so every script using the file sees it.
+struct WatchedFile {
+    text: Option<String>,
+    version: u32,
+    loading: bool,
+    last_check: Option<Instant>,
+}
+
+thread_local! {
+    static WATCHED_FILES: RefCell<HashMap<String, Rc<RefCell<WatchedFile>>>> = RefCell::new(HashMap::new());
+}
+
+fn watch(file_name: &str) -> Rc<RefCell<WatchedFile>> {
+    WATCHED_FILES.with(|files| {
+        files
+            .borrow_mut()
+            .entry(file_name.to_string())
+            .or_insert_with(|| {
+                Rc::new(RefCell::new(WatchedFile { text: None, version: 0, loading: false, last_check: None }))
+            })
+            .clone()
+    })
+}
+
+// Fetches the file again if nobody has in the last `RELOAD_SECONDS`
+fn check(file_name: &str, watched: &Rc<RefCell<WatchedFile>>) {
+    {
+        let mut file = watched.borrow_mut();
+        let due = file.last_check.is_none_or(|t| t.elapsed().as_secs_f32() > RELOAD_SECONDS);
+        if file.loading || !due {
+            return;
+        }
+        file.loading = true;
+        file.last_check = Some(Instant::now());
+    }
+    let file_name = file_name.to_string();
+    let watched = watched.clone();
+    resources::spawn(async move {
+        let loaded = resources::load_string(&file_name).await;
+        let mut file = watched.borrow_mut();
+        file.loading = false;
+        match loaded {
+            Ok(text) => {
+                if file.text.as_ref() != Some(&text) {
+                    file.text = Some(text);
+                    file.version += 1;
+                }
+            }
+            Err(e) => log::warn!("Couldn't load script {}: {}", file_name, e),
+        }
+    });
+}
+
+type Commands = Rc<RefCell<Vec<Box<dyn FnOnce(&mut State)>>>>;
+
+// Runs a Rhai script from the res folder. The script can define any of
+//
+//     fn start() { }
+//     fn update(dt) { }
+//     fn input(key, pressed) { }
+//     fn on_collision(other) { }
+//     fn on_collision_end(other) { }
+//
+// Inside them `this` is the object: `this.position`, `this.rotation` (euler
+// degrees), `this.velocity`, `this.angular_velocity` and `this.mass` are read
+// from the rigid body before each call and written back after. Any other
+// property set on `this` is kept between calls, so that's where script state
+// lives. Scripts can also call `vec3(x, y, z)`, `is_key_down("A")`,
+// `spawn(model, position)`, `spawn_prefab(file, position)` and `print`.
+//
+// The file is fetched again every second and the script restarts if it has
+// changed. Natively that's the build's copy of res, which a rebuild refreshes.
+pub struct Script {
+    file_name: String,
+    engine: Engine,
+    ast: Option<AST>,
+    scope: Scope<'static>,
+    this: Map,
+    // The file `watched` is for, changes when the inspector points us elsewhere
+    loaded_file: String,
+    watched: Option<Rc<RefCell<WatchedFile>>>,
+    // The version of the file that was last compiled
+    version: u32,
+    needs_start: bool,
+    keys_down: Rc<RefCell<HashSet<String>>>,
+    commands: Commands,
+}
+
+impl Script {
+    pub fn new(file_name: &str) -> Self {
+        let keys_down = Rc::new(RefCell::new(HashSet::new()));
+        let commands: Commands = Rc::new(RefCell::new(vec![]));
+        Self {
+            file_name: file_name.to_string(),
+            engine: create_engine(keys_down.clone(), commands.clone()),
+            ast: None,
+            scope: Scope::new(),
+            this: Map::new(),
+            loaded_file: String::new(),
+            watched: None,
+            version: 0,
+            needs_start: false,
+            keys_down,
+            commands,
+        }
+    }
+
+    // Recompiles when the file changes, or when the inspector names another one
+    fn poll_source(&mut self) {
+        if self.file_name.is_empty() {
+            return;
+        }
+        if self.watched.is_none() || self.file_name != self.loaded_file {
+            self.watched = Some(watch(&self.file_name));
+            self.loaded_file = self.file_name.clone();
+            self.version = 0;
+        }
+        let watched = match &self.watched {
+            Some(watched) => watched.clone(),
+            None => return,
+        };
+        check(&self.file_name, &watched);
+        let file = watched.borrow();
+        // Broken text keeps its version too, so the same error isn't reported every second
+        if file.version == self.version {
+            return;
+        }
+        self.version = file.version;
+        let text = match &file.text {
+            Some(text) => text,
+            None => return,
+        };
+        match self.engine.compile(text) {
+            Ok(ast) => {
+                if self.ast.is_some() {
+                    log!(format!("reloaded script {}", self.file_name));
+                }
+                self.ast = Some(ast);
+                self.scope = Scope::new();
+                self.this = Map::new();
+                self.needs_start = true;
+            }
+            Err(e) => log::warn!("Couldn't compile script {}: {}", self.file_name, e),
+        }
+    }
+
+    fn has_fn(&self, name: &str, params: usize) -> bool {
+        match &self.ast {
+            Some(ast) => ast.iter_functions().any(|f| f.name == name && f.params.len() == params),
+            None => false,
+        }
+    }
+
+    // Calls an entry point if the script defines it, with `this` synced to the body
+    fn call(&mut self, name: &str, args: impl rhai::FuncArgs, params: usize, ctx: &mut Context) {
+        if !self.has_fn(name, params) {
+            return;
+        }
+        let ast = match &self.ast {
+            Some(ast) => ast,
+            None => return,
+        };
+
+        let body = ctx.body();
+        let rotation = euler_degrees(body.rotation);
+        self.this.insert("position".into(), Dynamic::from(body.position));
+        self.this.insert("rotation".into(), Dynamic::from(rotation));
+        self.this.insert("velocity".into(), Dynamic::from(body.velocity));
+        self.this.insert("angular_velocity".into(), Dynamic::from(body.angular_velocity));
+        self.this.insert("mass".into(), Dynamic::from(body.mass as FLOAT));
+        self.this.insert("body".into(), Dynamic::from(ctx.body_index as INT));
+
+        let mut this = Dynamic::from_map(std::mem::take(&mut self.this));
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut this);
+        if let Err(e) = self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, ast, name, args) {
+            log::warn!("Script {} failed in {}: {}", self.loaded_file, name, e);
+        }
+        self.this = this.try_cast::<Map>().unwrap_or_default();
+
+        let body = ctx.body();
+        if let Some(position) = get_vec3(&self.this, "position") {
+            body.position = position;
+        }
+        // Only touch the rotation if the script did, euler round trips aren't exact
+        if let Some(new_rotation) = get_vec3(&self.this, "rotation") {
+            if new_rotation != rotation {
+                body.rotation = Quaternion::from(Euler::new(Deg(new_rotation.x), Deg(new_rotation.y), Deg(new_rotation.z)));
+            }
+        }
+        if let Some(velocity) = get_vec3(&self.this, "velocity") {
+            body.velocity = velocity;
+        }
+        if let Some(angular_velocity) = get_vec3(&self.this, "angular_velocity") {
+            body.angular_velocity = angular_velocity;
+        }
+        if let Some(mass) = self.this.get("mass").and_then(|m| m.as_float().ok()) {
+            body.mass = mass as f32;
+        }
+
+        // Spawning needs the whole engine, so it waits for the scheduler this frame
+        for command in self.commands.borrow_mut().drain(..) {
+            ctx.scheduler.after(0.0, command);
+        }
+    }
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl Component for Script {
+    fn name(&self) -> &'static str {
+        "Script"
+    }
+    fn start(&mut self, _ctx: &mut Context) {
+        self.poll_source();
+    }
+    fn update(&mut self, dt: f32, ctx: &mut Context) {
+        self.poll_source();
+        if self.needs_start {
+            self.needs_start = false;
+            self.call("start", (), 0, ctx);
+        }
+        self.call("update", (dt as FLOAT,), 1, ctx);
+    }
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state,
+                    virtual_keycode: Some(keycode),
+                    ..
+                },
+                ..
+            } => {
+                let key = format!("{:?}", keycode);
+                let is_pressed = *state == ElementState::Pressed;
+                if is_pressed {
+                    self.keys_down.borrow_mut().insert(key.clone());
+                } else {
+                    self.keys_down.borrow_mut().remove(&key);
+                }
+                // No body to hand the script here, so key presses go through the event bus
+                false
+            }
+            _ => false,
+        }
+    }
+    fn on_event(&mut self, event: &Event, ctx: &mut Context) {
+        if let Some(input) = event.get::<crate::events::KeyInput>() {
+            let key = format!("{:?}", input.key);
+            self.call("input", (key, input.pressed), 2, ctx);
+        } else if let Some(collision) = event.get::<CollisionStarted>() {
+            if let Some(other) = other_body(collision.a, collision.b, ctx.body_index) {
+                self.call("on_collision", (other as INT,), 1, ctx);
+            }
+        } else if let Some(collision) = event.get::<CollisionEnded>() {
+            if let Some(other) = other_body(collision.a, collision.b, ctx.body_index) {
+                self.call("on_collision_end", (other as INT,), 1, ctx);
+            }
+        }
+    }
+    fn fields(&mut self) -> Vec<(&'static str, Field<'_>)> {
+        vec![("script", Field::String(&mut self.file_name))]
+    }
+}
+
+fn other_body(a: usize, b: usize, body_index: usize) -> Option<usize> {
+    if a == body_index {
+        Some(b)
+    } else if b == body_index {
+        Some(a)
+    } else {
+        None
+    }
+}
+
+fn euler_degrees(rotation: Quaternion<f32>) -> Vector3<f32> {
+    let euler = Euler::from(rotation);
+    Vector3::new(Deg::from(euler.x).0, Deg::from(euler.y).0, Deg::from(euler.z).0)
+}
+
+fn get_vec3(map: &Map, name: &str) -> Option<Vector3<f32>> {
+    map.get(name).and_then(|v| v.clone().try_cast::<Vector3<f32>>())
+}
+
+// Every script gets its own engine so the bindings can reach that script's input
+// and command queue
+fn create_engine(keys_down: Rc<RefCell<HashSet<String>>>, commands: Commands) -> Engine {
+    let mut engine = Engine::new();
+
+    engine.on_print(|text| log!(text));
+    engine.on_debug(|text, source, pos| log!(format!("{} {:?} {}", source.unwrap_or(""), pos, text)));
+
+    engine
+        .register_type_with_name::<Vector3<f32>>("Vec3")
+        .register_fn("vec3", |x: FLOAT, y: FLOAT, z: FLOAT| Vector3::new(x as f32, y as f32, z as f32))
+        .register_get_set("x", |v: &mut Vector3<f32>| v.x as FLOAT, |v: &mut Vector3<f32>, x: FLOAT| v.x = x as f32)
+        .register_get_set("y", |v: &mut Vector3<f32>| v.y as FLOAT, |v: &mut Vector3<f32>, y: FLOAT| v.y = y as f32)
+        .register_get_set("z", |v: &mut Vector3<f32>| v.z as FLOAT, |v: &mut Vector3<f32>, z: FLOAT| v.z = z as f32)
+        .register_fn("+", |a: Vector3<f32>, b: Vector3<f32>| a + b)
+        .register_fn("-", |a: Vector3<f32>, b: Vector3<f32>| a - b)
+        .register_fn("-", |a: Vector3<f32>| -a)
+        .register_fn("*", |a: Vector3<f32>, b: FLOAT| a * b as f32)
+        .register_fn("*", |a: FLOAT, b: Vector3<f32>| b * a as f32)
+        .register_fn("/", |a: Vector3<f32>, b: FLOAT| a / b as f32)
+        .register_fn("length", |v: &mut Vector3<f32>| v.magnitude() as FLOAT)
+        .register_fn("normalized", |v: &mut Vector3<f32>| v.normalize())
+        .register_fn("dot", |a: Vector3<f32>, b: Vector3<f32>| a.dot(b) as FLOAT)
+        .register_fn("to_string", |v: &mut Vector3<f32>| format!("({}, {}, {})", v.x, v.y, v.z))
+        .register_fn("to_debug", |v: &mut Vector3<f32>| format!("vec3({}, {}, {})", v.x, v.y, v.z));
+
+    // Keys are named like winit's VirtualKeyCode, "A", "Space", "Left"...
+    engine.register_fn("is_key_down", move |key: &str| keys_down.borrow().contains(key));
+
+    let spawn_commands = commands.clone();
+    engine.register_fn("spawn", move |model: &str, position: Vector3<f32>| {
+        let model = model.to_string();
+        spawn_commands.borrow_mut().push(Box::new(move |state: &mut State| {
+            state.spawn(&model, position.into(), [0.0; 3], [1.0; 3], None);
+        }));
+    });
+    engine.register_fn("spawn_prefab", move |file_name: &str, position: Vector3<f32>| {
+        let file_name = file_name.to_string();
+        commands.borrow_mut().push(Box::new(move |state: &mut State| {
+            state.spawn_prefab(&file_name, position.into(), [0.0; 3], Default::default());
+        }));
+    });
+
+    engine
+}
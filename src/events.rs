@@ -0,0 +1,117 @@
+use std::any::{Any, TypeId};
+
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use crate::State;
+
+// Two rigid bodies started overlapping this frame
+pub struct CollisionStarted {
+    pub a: usize,
+    pub b: usize,
+}
+
+// Two rigid bodies that were overlapping have separated
+pub struct CollisionEnded {
+    pub a: usize,
+    pub b: usize,
+}
+
+// An instance of `model` was spawned around rigid body `body`
+pub struct Spawned {
+    pub model: String,
+    pub body: usize,
+}
+
+pub struct KeyInput {
+    pub key: VirtualKeyCode,
+    pub pressed: bool,
+}
+
+pub struct MouseInput {
+    pub button: MouseButton,
+    pub pressed: bool,
+}
+
+// A published event of any type. Handlers pick out the ones they care about
+// with `get`.
+pub struct Event {
+    type_id: TypeId,
+    payload: Box<dyn Any>,
+}
+
+impl Event {
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.payload.downcast_ref::<T>()
+    }
+
+    pub fn is<T: 'static>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+}
+
+// Events published during a frame are held here and delivered together at the
+// end of `State::update`, after components and systems have run. Anything
+// published while handling an event goes out the following frame.
+pub struct Events {
+    queued: Vec<Event>,
+    delivered: Vec<Event>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self {
+            queued: vec![],
+            delivered: vec![],
+        }
+    }
+
+    pub fn publish<T: 'static>(&mut self, event: T) {
+        self.queued.push(Event {
+            type_id: TypeId::of::<T>(),
+            payload: Box::new(event),
+        });
+    }
+
+    // Events of type `T` from the last delivery, for code that would rather poll
+    // than subscribe
+    pub fn read<T: 'static>(&self) -> impl Iterator<Item = &T> {
+        self.delivered.iter().filter_map(|e| e.get::<T>())
+    }
+
+    pub(crate) fn take_queued(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.queued)
+    }
+
+    pub(crate) fn set_delivered(&mut self, events: Vec<Event>) {
+        self.delivered = events;
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Handler = Box<dyn FnMut(&mut State, &Event)>;
+
+// A handler registered on the engine for one event type
+pub(crate) struct Subscriber {
+    pub handler: Handler,
+}
+
+impl Subscriber {
+    pub fn new<T, F>(mut handler: F) -> Self
+    where
+        T: 'static,
+        F: FnMut(&mut State, &T) + 'static,
+    {
+        Self {
+            handler: Box::new(move |state, event| {
+                if let Some(event) = event.get::<T>() {
+                    handler(state, event);
+                }
+            }),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::component::Context;
+use crate::events::Event;
+
+type Hook<T> = Box<dyn FnMut(&mut T, &mut Context)>;
+type UpdateHook<T> = Box<dyn FnMut(&mut T, f32, &mut Context)>;
+type EventHook<T> = Box<dyn FnMut(&mut T, &Event, &mut Context)>;
+type ConditionGuard<T> = Box<dyn FnMut(&T, f32, &mut Context) -> bool>;
+type EventGuard<T> = Box<dyn FnMut(&T, &Event) -> bool>;
+
+// One state of a `StateMachine`, with optional hooks for entering, each frame,
+// events and leaving
+pub struct FsmState<T> {
+    name: &'static str,
+    enter: Option<Hook<T>>,
+    update: Option<UpdateHook<T>>,
+    event: Option<EventHook<T>>,
+    exit: Option<Hook<T>>,
+}
+
+impl<T> FsmState<T> {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            enter: None,
+            update: None,
+            event: None,
+            exit: None,
+        }
+    }
+
+    pub fn on_enter<F>(mut self, enter: F) -> Self
+    where
+        F: FnMut(&mut T, &mut Context) + 'static,
+    {
+        self.enter = Some(Box::new(enter));
+        self
+    }
+
+    pub fn on_update<F>(mut self, update: F) -> Self
+    where
+        F: FnMut(&mut T, f32, &mut Context) + 'static,
+    {
+        self.update = Some(Box::new(update));
+        self
+    }
+
+    // Called for every event delivered while this is the current state
+    pub fn on_event<F>(mut self, event: F) -> Self
+    where
+        F: FnMut(&mut T, &Event, &mut Context) + 'static,
+    {
+        self.event = Some(Box::new(event));
+        self
+    }
+
+    pub fn on_exit<F>(mut self, exit: F) -> Self
+    where
+        F: FnMut(&mut T, &mut Context) + 'static,
+    {
+        self.exit = Some(Box::new(exit));
+        self
+    }
+}
+
+enum Guard<T> {
+    // Checked every frame, gets the time spent in the current state
+    Condition(ConditionGuard<T>),
+    // Checked for each delivered event
+    Event(EventGuard<T>),
+}
+
+struct Transition<T> {
+    // None means from any state
+    from: Option<usize>,
+    to: usize,
+    guard: Guard<T>,
+}
+
+// A finite state machine driving some data `T`, normally a component's
+// settings. The component owns both and forwards its update and events:
+//
+//     let fsm = StateMachine::new()
+//         .state(FsmState::new("Idle"))
+//         .state(FsmState::new("Moving").on_enter(|data: &mut Data, ctx| ctx.body().velocity = data.velocity))
+//         .transition_after("Idle", "Moving", 1.0)
+//         .transition_on::<Stop>("*", "Idle");
+//
+//     fn update(&mut self, dt: f32, ctx: &mut Context) { self.fsm.update(&mut self.data, dt, ctx) }
+//     fn on_event(&mut self, event: &Event, ctx: &mut Context) { self.fsm.handle_event(&mut self.data, event, ctx) }
+//
+// The first state added is the initial one, it's entered on the first update.
+// Transitions are checked in the order they were added and the first one that
+// passes wins. "*" as the from state matches any state.
+pub struct StateMachine<T> {
+    states: Vec<FsmState<T>>,
+    transitions: Vec<Transition<T>>,
+    current: usize,
+    time_in_state: f32,
+    entered: bool,
+}
+
+impl<T> StateMachine<T> {
+    pub fn new() -> Self {
+        Self {
+            states: vec![],
+            transitions: vec![],
+            current: 0,
+            time_in_state: 0.0,
+            entered: false,
+        }
+    }
+
+    pub fn state(mut self, state: FsmState<T>) -> Self {
+        self.states.push(state);
+        self
+    }
+
+    fn index(&self, name: &str) -> usize {
+        self.states
+            .iter()
+            .position(|s| s.name == name)
+            .unwrap_or_else(|| panic!("State machine has no state {}", name))
+    }
+
+    fn source_index(&self, name: &str) -> Option<usize> {
+        if name == "*" {
+            None
+        } else {
+            Some(self.index(name))
+        }
+    }
+
+    // Moves to `to` once `guard` returns true. The guard gets the seconds spent
+    // in the current state.
+    pub fn transition<F>(mut self, from: &str, to: &str, guard: F) -> Self
+    where
+        F: FnMut(&T, f32, &mut Context) -> bool + 'static,
+    {
+        let transition = Transition {
+            from: self.source_index(from),
+            to: self.index(to),
+            guard: Guard::Condition(Box::new(guard)),
+        };
+        self.transitions.push(transition);
+        self
+    }
+
+    pub fn transition_after(self, from: &str, to: &str, seconds: f32) -> Self {
+        self.transition(from, to, move |_, time_in_state, _| time_in_state >= seconds)
+    }
+
+    // Moves to `to` when an event of type `E` is delivered
+    pub fn transition_on<E: 'static>(self, from: &str, to: &str) -> Self {
+        self.transition_on_event::<E, _>(from, to, |_, _| true)
+    }
+
+    // Moves to `to` when an event of type `E` passing `filter` is delivered
+    pub fn transition_on_event<E, F>(mut self, from: &str, to: &str, mut filter: F) -> Self
+    where
+        E: 'static,
+        F: FnMut(&T, &E) -> bool + 'static,
+    {
+        let transition = Transition {
+            from: self.source_index(from),
+            to: self.index(to),
+            guard: Guard::Event(Box::new(move |data, event| match event.get::<E>() {
+                Some(event) => filter(data, event),
+                None => false,
+            })),
+        };
+        self.transitions.push(transition);
+        self
+    }
+
+    pub fn current(&self) -> &'static str {
+        self.states[self.current].name
+    }
+
+    pub fn time_in_state(&self) -> f32 {
+        self.time_in_state
+    }
+
+    // Leaves the current state for `name` straight away, even if it's the same state
+    pub fn go_to(&mut self, data: &mut T, name: &str, ctx: &mut Context) {
+        let to = self.index(name);
+        self.change(data, to, ctx);
+    }
+
+    fn change(&mut self, data: &mut T, to: usize, ctx: &mut Context) {
+        if let Some(exit) = &mut self.states[self.current].exit {
+            exit(data, ctx);
+        }
+        self.current = to;
+        self.time_in_state = 0.0;
+        if let Some(enter) = &mut self.states[self.current].enter {
+            enter(data, ctx);
+        }
+    }
+
+    fn enter_initial(&mut self, data: &mut T, ctx: &mut Context) {
+        if !self.entered {
+            self.entered = true;
+            if let Some(enter) = &mut self.states[self.current].enter {
+                enter(data, ctx);
+            }
+        }
+    }
+
+    pub fn update(&mut self, data: &mut T, dt: f32, ctx: &mut Context) {
+        if self.states.is_empty() {
+            return;
+        }
+        self.enter_initial(data, ctx);
+        self.time_in_state += dt;
+        if let Some(update) = &mut self.states[self.current].update {
+            update(data, dt, ctx);
+        }
+
+        let mut next = None;
+        for transition in self.transitions.iter_mut() {
+            if transition.from.is_some_and(|from| from != self.current) {
+                continue;
+            }
+            if let Guard::Condition(guard) = &mut transition.guard {
+                if guard(data, self.time_in_state, ctx) {
+                    next = Some(transition.to);
+                    break;
+                }
+            }
+        }
+        if let Some(to) = next {
+            self.change(data, to, ctx);
+        }
+    }
+
+    pub fn handle_event(&mut self, data: &mut T, event: &Event, ctx: &mut Context) {
+        if !self.entered {
+            return;
+        }
+        if let Some(on_event) = &mut self.states[self.current].event {
+            on_event(data, event, ctx);
+        }
+
+        let mut next = None;
+        for transition in self.transitions.iter_mut() {
+            if transition.from.is_some_and(|from| from != self.current) {
+                continue;
+            }
+            if let Guard::Event(guard) = &mut transition.guard {
+                if guard(data, event) {
+                    next = Some(transition.to);
+                    break;
+                }
+            }
+        }
+        if let Some(to) = next {
+            self.change(data, to, ctx);
+        }
+    }
+}
+
+impl<T> Default for StateMachine<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
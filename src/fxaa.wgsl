@@ -0,0 +1,156 @@
+// FXAA, finds edges by their brightness and blends across them
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+
+// Contrast below this isn't treated as an edge, the first is for dark areas
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const EDGE_THRESHOLD_MAX: f32 = 0.125;
+// How much single pixel detail gets softened
+const SUBPIXEL_QUALITY: f32 = 0.75;
+// How far along an edge to look for its end
+const SEARCH_STEPS: i32 = 12;
+
+fn sample_scene(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(t_scene, s_scene, uv, 0.0);
+}
+
+// The scene holds linear colour, the square root is close enough to gamma for finding edges
+fn luma(uv: vec2<f32>) -> f32 {
+    return sqrt(dot(sample_scene(uv).rgb, vec3<f32>(0.299, 0.587, 0.114)));
+}
+
+// Longer strides the further the search has gone
+fn search_stride(step: i32) -> f32 {
+    if step < 5 {
+        return 1.0;
+    }
+    if step < 9 {
+        return 2.0;
+    }
+    return 4.0;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_scene));
+    let uv = in.uv;
+    let center = sample_scene(uv);
+
+    let luma_center = sqrt(dot(center.rgb, vec3<f32>(0.299, 0.587, 0.114)));
+    let luma_up = luma(uv + vec2<f32>(0.0, -texel.y));
+    let luma_down = luma(uv + vec2<f32>(0.0, texel.y));
+    let luma_left = luma(uv + vec2<f32>(-texel.x, 0.0));
+    let luma_right = luma(uv + vec2<f32>(texel.x, 0.0));
+
+    let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+    let range = luma_max - luma_min;
+    if range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX) {
+        return center;
+    }
+
+    let luma_up_left = luma(uv + vec2<f32>(-texel.x, -texel.y));
+    let luma_up_right = luma(uv + vec2<f32>(texel.x, -texel.y));
+    let luma_down_left = luma(uv + vec2<f32>(-texel.x, texel.y));
+    let luma_down_right = luma(uv + vec2<f32>(texel.x, texel.y));
+
+    let up_down = luma_up + luma_down;
+    let left_right = luma_left + luma_right;
+    let left_corners = luma_up_left + luma_down_left;
+    let right_corners = luma_up_right + luma_down_right;
+    let up_corners = luma_up_left + luma_up_right;
+    let down_corners = luma_down_left + luma_down_right;
+
+    // Which way the edge runs, from how sharply brightness changes across each axis
+    let edge_horizontal = abs(-2.0 * luma_left + left_corners) + abs(-2.0 * luma_center + up_down) * 2.0 + abs(-2.0 * luma_right + right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + up_corners) + abs(-2.0 * luma_center + left_right) * 2.0 + abs(-2.0 * luma_down + down_corners);
+    let horizontal = edge_horizontal >= edge_vertical;
+
+    // The neighbours either side of the edge, before and after the pixel
+    let luma_before = select(luma_left, luma_up, horizontal);
+    let luma_after = select(luma_right, luma_down, horizontal);
+    let gradient_before = luma_before - luma_center;
+    let gradient_after = luma_after - luma_center;
+    let before_steepest = abs(gradient_before) >= abs(gradient_after);
+    let gradient_scaled = 0.25 * max(abs(gradient_before), abs(gradient_after));
+
+    var step_length = select(texel.x, texel.y, horizontal);
+    var luma_local = 0.5 * (luma_after + luma_center);
+    if before_steepest {
+        step_length = -step_length;
+        luma_local = 0.5 * (luma_before + luma_center);
+    }
+
+    // Halfway onto the edge, then walk along it both ways until the contrast runs out
+    var edge_uv = uv;
+    if horizontal {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let along = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), horizontal);
+
+    var uv_back = edge_uv - along;
+    var uv_forward = edge_uv + along;
+    var end_back = luma(uv_back) - luma_local;
+    var end_forward = luma(uv_forward) - luma_local;
+    var reached_back = abs(end_back) >= gradient_scaled;
+    var reached_forward = abs(end_forward) >= gradient_scaled;
+    if !reached_back {
+        uv_back -= along;
+    }
+    if !reached_forward {
+        uv_forward += along;
+    }
+
+    for (var i = 1; i < SEARCH_STEPS; i++) {
+        if reached_back && reached_forward {
+            break;
+        }
+        if !reached_back {
+            end_back = luma(uv_back) - luma_local;
+            reached_back = abs(end_back) >= gradient_scaled;
+        }
+        if !reached_forward {
+            end_forward = luma(uv_forward) - luma_local;
+            reached_forward = abs(end_forward) >= gradient_scaled;
+        }
+        if !reached_back {
+            uv_back -= along * search_stride(i);
+        }
+        if !reached_forward {
+            uv_forward += along * search_stride(i);
+        }
+    }
+
+    let distance_back = select(uv.y - uv_back.y, uv.x - uv_back.x, horizontal);
+    let distance_forward = select(uv_forward.y - uv.y, uv_forward.x - uv.x, horizontal);
+    let back_closer = distance_back < distance_forward;
+    let distance = min(distance_back, distance_forward);
+    let edge_length = distance_back + distance_forward;
+
+    // Only blend if the nearer end goes the same way as the pixel, otherwise it's past the edge
+    let end_luma = select(end_forward, end_back, back_closer);
+    let center_darker = luma_center < luma_local;
+    var offset = 0.0;
+    if (end_luma < 0.0) != center_darker {
+        offset = 0.5 - distance / edge_length;
+    }
+
+    // Lone bright or dark pixels don't make a long edge, soften those too
+    let luma_average = (2.0 * (up_down + left_right) + left_corners + right_corners) / 12.0;
+    let subpixel = clamp(abs(luma_average - luma_center) / range, 0.0, 1.0);
+    let subpixel_smooth = (-2.0 * subpixel + 3.0) * subpixel * subpixel;
+    offset = max(offset, subpixel_smooth * subpixel_smooth * SUBPIXEL_QUALITY);
+
+    var final_uv = uv;
+    if horizontal {
+        final_uv.y += offset * step_length;
+    } else {
+        final_uv.x += offset * step_length;
+    }
+    return sample_scene(final_uv);
+}
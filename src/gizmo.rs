@@ -0,0 +1,299 @@
+use cgmath::{InnerSpace, Quaternion, Rotation3, Vector3, Vector4};
+
+use crate::camera::Camera;
+use crate::history::Transform;
+use crate::physics::Ray;
+
+// How close the mouse has to be to a handle to grab it, in points
+const GRAB_DISTANCE: f32 = 8.0;
+// Handles are this fraction of the distance to the camera, so they stay the same size on screen
+const SCREEN_SIZE: f32 = 0.2;
+const RING_SEGMENTS: usize = 48;
+
+const AXIS_COLORS: [egui::Color32; 3] = [
+    egui::Color32::from_rgb(230, 60, 60),
+    egui::Color32::from_rgb(80, 200, 80),
+    egui::Color32::from_rgb(70, 110, 240),
+];
+const ACTIVE_COLOR: egui::Color32 = egui::Color32::from_rgb(250, 210, 60);
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+// Which way the handles point. Scale always works along the object's own axes.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GizmoSpace {
+    World,
+    Local,
+}
+
+struct Drag {
+    axis: usize,
+    start: Transform,
+    direction: Vector3<f32>,
+    // Where the handle was grabbed, a distance along the axis or a point on the ring's plane
+    grab: Vector3<f32>,
+}
+
+// Handles for moving, turning and scaling the selected object in the viewport.
+// Drawn straight onto egui's background layer, under every window, and driven
+// by the pointer directly so clicks that miss the handles still reach picking.
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub space: GizmoSpace,
+    pub snap: bool,
+    // Snapping steps, in world units, degrees and scale units
+    pub grid: f32,
+    pub angle: f32,
+    pub scale_step: f32,
+    hovered: Option<usize>,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    pub fn new() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            space: GizmoSpace::World,
+            snap: false,
+            grid: 0.5,
+            angle: 15.0,
+            scale_step: 0.1,
+            hovered: None,
+            drag: None,
+        }
+    }
+
+    // True while the mouse is over a handle or dragging one, the click belongs to the gizmo then
+    pub fn is_active(&self) -> bool {
+        self.hovered.is_some() || self.drag.is_some()
+    }
+
+    // Called when nothing is selected
+    pub fn release(&mut self) {
+        self.hovered = None;
+        self.drag = None;
+    }
+
+    // Draws the handles around `transform` and returns where they've dragged it to
+    pub fn show(&mut self, ctx: &egui::Context, camera: &Camera, transform: Transform) -> Option<Transform> {
+        let view = View::new(ctx, camera);
+        let origin = transform.position;
+        let size = (camera.eye - cgmath::Point3::new(origin.x, origin.y, origin.z)).magnitude() * SCREEN_SIZE;
+        let axes = self.axes(&transform);
+
+        let (pointer, pressed, down) = ctx.input(|i| (i.pointer.hover_pos(), i.pointer.primary_pressed(), i.pointer.primary_down()));
+        let over_window = ctx.is_pointer_over_area();
+
+        if self.drag.is_none() {
+            self.hovered = match pointer {
+                Some(pointer) if !over_window => self.handle_at(&view, origin, &axes, size, pointer),
+                _ => None,
+            };
+            if pressed {
+                if let (Some(axis), Some(pointer)) = (self.hovered, pointer) {
+                    let ray = view.ray(pointer);
+                    let grab = match self.mode {
+                        GizmoMode::Rotate => ray_plane(&ray, origin, axes[axis]),
+                        _ => ray_axis(&ray, origin, axes[axis]).map(|t| Vector3::new(t, 0.0, 0.0)),
+                    };
+                    if let Some(grab) = grab {
+                        self.drag = Some(Drag { axis, start: transform, direction: axes[axis], grab });
+                    }
+                }
+            }
+        }
+
+        let mut result = None;
+        if let Some(drag) = &self.drag {
+            if !down {
+                self.drag = None;
+            } else if let Some(pointer) = pointer {
+                result = self.dragged(drag, &view.ray(pointer));
+            }
+        }
+
+        let active = self.drag.as_ref().map(|d| d.axis).or(self.hovered);
+        self.draw(ctx, &view, origin, &axes, size, active);
+        result
+    }
+
+    fn axes(&self, transform: &Transform) -> [Vector3<f32>; 3] {
+        let local = self.mode == GizmoMode::Scale || self.space == GizmoSpace::Local;
+        let rotation = if local { transform.rotation } else { Quaternion::new(1.0, 0.0, 0.0, 0.0) };
+        [rotation * Vector3::unit_x(), rotation * Vector3::unit_y(), rotation * Vector3::unit_z()]
+    }
+
+    fn dragged(&self, drag: &Drag, ray: &Ray) -> Option<Transform> {
+        let start = drag.start;
+        let mut transform = start;
+        match self.mode {
+            GizmoMode::Translate => {
+                let mut distance = ray_axis(ray, start.position, drag.direction)? - drag.grab.x;
+                if self.snap && self.grid > 0.0 {
+                    distance = (distance / self.grid).round() * self.grid;
+                }
+                transform.position = start.position + drag.direction * distance;
+            }
+            GizmoMode::Rotate => {
+                let from = drag.grab - start.position;
+                let to = ray_plane(ray, start.position, drag.direction)? - start.position;
+                let mut angle = drag.direction.dot(from.cross(to)).atan2(from.dot(to)).to_degrees();
+                if self.snap && self.angle > 0.0 {
+                    angle = (angle / self.angle).round() * self.angle;
+                }
+                transform.rotation = Quaternion::from_axis_angle(drag.direction, cgmath::Deg(angle)) * start.rotation;
+            }
+            GizmoMode::Scale => {
+                if drag.grab.x.abs() < f32::EPSILON {
+                    return None;
+                }
+                let factor = ray_axis(ray, start.position, drag.direction)? / drag.grab.x;
+                let mut scale = start.scale[drag.axis] * factor;
+                if self.snap && self.scale_step > 0.0 {
+                    scale = (scale / self.scale_step).round() * self.scale_step;
+                }
+                transform.scale[drag.axis] = scale.max(0.01);
+            }
+        }
+        Some(transform)
+    }
+
+    // The handle nearest the pointer, if it's close enough to grab
+    fn handle_at(&self, view: &View, origin: Vector3<f32>, axes: &[Vector3<f32>; 3], size: f32, pointer: egui::Pos2) -> Option<usize> {
+        let mut nearest: Option<(f32, usize)> = None;
+        for (axis, direction) in axes.iter().enumerate() {
+            let points = match self.mode {
+                GizmoMode::Rotate => ring(origin, *direction, size),
+                _ => vec![origin, origin + direction * size],
+            };
+            let screen: Vec<egui::Pos2> = points.iter().filter_map(|p| view.project(*p)).collect();
+            for segment in screen.windows(2) {
+                let distance = segment_distance(pointer, segment[0], segment[1]);
+                if distance < GRAB_DISTANCE && nearest.is_none_or(|(d, _)| distance < d) {
+                    nearest = Some((distance, axis));
+                }
+            }
+        }
+        nearest.map(|(_, axis)| axis)
+    }
+
+    fn draw(&self, ctx: &egui::Context, view: &View, origin: Vector3<f32>, axes: &[Vector3<f32>; 3], size: f32, active: Option<usize>) {
+        let painter = ctx.layer_painter(egui::LayerId::background());
+        for (axis, direction) in axes.iter().enumerate() {
+            let color = if active == Some(axis) { ACTIVE_COLOR } else { AXIS_COLORS[axis] };
+            let stroke = egui::Stroke::new(3.0, color);
+            if self.mode == GizmoMode::Rotate {
+                let points: Vec<egui::Pos2> = ring(origin, *direction, size).into_iter().filter_map(|p| view.project(p)).collect();
+                painter.add(egui::Shape::line(points, egui::Stroke::new(2.5, color)));
+                continue;
+            }
+            let (start, end) = match (view.project(origin), view.project(origin + direction * size)) {
+                (Some(start), Some(end)) => (start, end),
+                _ => continue,
+            };
+            painter.line_segment([start, end], stroke);
+            if self.mode == GizmoMode::Scale {
+                painter.rect_filled(egui::Rect::from_center_size(end, egui::vec2(10.0, 10.0)), 0.0, color);
+            } else {
+                // Arrow head pointing away from the object
+                let along = (end - start).normalized();
+                let across = egui::vec2(-along.y, along.x);
+                let tip = end + along * 12.0;
+                painter.add(egui::Shape::convex_polygon(vec![tip, end + across * 6.0, end - across * 6.0], color, egui::Stroke::NONE));
+            }
+        }
+    }
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Maps between the world and egui's screen coordinates
+struct View {
+    view_proj: cgmath::Matrix4<f32>,
+    rect: egui::Rect,
+    camera: Camera,
+}
+
+impl View {
+    fn new(ctx: &egui::Context, camera: &Camera) -> Self {
+        Self {
+            view_proj: camera.build_view_projection_matrix(),
+            rect: ctx.screen_rect(),
+            camera: camera.clone(),
+        }
+    }
+
+    // None for points behind the camera
+    fn project(&self, point: Vector3<f32>) -> Option<egui::Pos2> {
+        let clip = self.view_proj * Vector4::new(point.x, point.y, point.z, 1.0);
+        if clip.w <= f32::EPSILON {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(egui::pos2(
+            self.rect.left() + (ndc.x + 1.0) / 2.0 * self.rect.width(),
+            self.rect.top() + (1.0 - ndc.y) / 2.0 * self.rect.height(),
+        ))
+    }
+
+    fn ray(&self, pointer: egui::Pos2) -> Ray {
+        self.camera.screen_ray(pointer.x - self.rect.left(), pointer.y - self.rect.top(), self.rect.width(), self.rect.height())
+    }
+}
+
+// Distance along the axis line through `origin` to the point closest to the ray
+fn ray_axis(ray: &Ray, origin: Vector3<f32>, axis: Vector3<f32>) -> Option<f32> {
+    let along = axis.dot(ray.direction);
+    let denominator = 1.0 - along * along;
+    // Looking straight down the axis, there's nothing sensible to drag along
+    if denominator < 1e-4 {
+        return None;
+    }
+    let offset = origin - ray.origin;
+    Some((along * ray.direction.dot(offset) - axis.dot(offset)) / denominator)
+}
+
+// Where the ray crosses the plane through `origin` facing `normal`
+fn ray_plane(ray: &Ray, origin: Vector3<f32>, normal: Vector3<f32>) -> Option<Vector3<f32>> {
+    let facing = ray.direction.dot(normal);
+    if facing.abs() < 1e-4 {
+        return None;
+    }
+    let distance = (origin - ray.origin).dot(normal) / facing;
+    if distance < 0.0 {
+        return None;
+    }
+    Some(ray.at(distance))
+}
+
+// Points round a circle facing `axis`, the first repeated at the end to close it
+fn ring(origin: Vector3<f32>, axis: Vector3<f32>, radius: f32) -> Vec<Vector3<f32>> {
+    let helper = if axis.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    let u = axis.cross(helper).normalize();
+    let v = axis.cross(u);
+    (0..=RING_SEGMENTS)
+        .map(|i| {
+            let angle = i as f32 / RING_SEGMENTS as f32 * std::f32::consts::TAU;
+            origin + (u * angle.cos() + v * angle.sin()) * radius
+        })
+        .collect()
+}
+
+fn segment_distance(point: egui::Pos2, a: egui::Pos2, b: egui::Pos2) -> f32 {
+    let ab = b - a;
+    let length = ab.length_sq();
+    if length <= f32::EPSILON {
+        return (point - a).length();
+    }
+    let t = ((point - a).dot(ab) / length).clamp(0.0, 1.0);
+    (point - (a + ab * t)).length()
+}
@@ -0,0 +1,201 @@
+use wgpu::util::DeviceExt;
+
+use crate::bloom::Bloom;
+use crate::texture;
+
+// The scene is lit into this, so bright lights and emissive surfaces can go past 1
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// How HDR colour is squeezed into what the screen can show
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tonemapper {
+    // Filmic, brights roll off gently and saturated colours desaturate towards white
+    Aces,
+    // Simple x / (1 + x), keeps hue but looks flatter
+    Reinhard,
+    // Anything over 1 is cut off, how it looked before HDR
+    Clamp,
+}
+
+impl Tonemapper {
+    fn index(&self) -> u32 {
+        match self {
+            Tonemapper::Aces => 0,
+            Tonemapper::Reinhard => 1,
+            Tonemapper::Clamp => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HdrSettings {
+    pub tonemapper: Tonemapper,
+    // In stops, each one doubles the brightness
+    pub exposure: f32,
+    pub bloom: bool,
+    // Brightness where bloom starts, and how far below it it fades in
+    pub bloom_threshold: f32,
+    pub bloom_knee: f32,
+    pub bloom_intensity: f32,
+    // How far each blur step reaches, in texels
+    pub bloom_radius: f32,
+}
+
+impl Default for HdrSettings {
+    fn default() -> Self {
+        Self {
+            tonemapper: Tonemapper::Aces,
+            exposure: 0.0,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_knee: 0.5,
+            bloom_intensity: 0.3,
+            bloom_radius: 1.0,
+        }
+    }
+}
+
+// Matches `Tonemap` in tonemap.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    tonemapper: u32,
+    bloom_intensity: f32,
+    _padding: f32,
+}
+
+// The HDR scene target and the passes that bring it down to the screen
+pub struct Hdr {
+    pub settings: HdrSettings,
+    target: texture::Texture,
+    bloom: Bloom,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Hdr {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("tonemap_bind_group_layout"),
+        });
+        let settings = HdrSettings::default();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap Buffer"),
+            contents: bytemuck::cast_slice(&[Self::uniform(&settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let shader = crate::create_fullscreen_shader(device, "Tonemap Shader", include_str!("tonemap.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = crate::create_fullscreen_pipeline(device, &pipeline_layout, &shader, "fs_main", config.format, wgpu::BlendState::REPLACE, "Tonemap Pipeline");
+
+        let target = texture::Texture::create_render_target(device, config, HDR_FORMAT, 1, "hdr_texture");
+        let bloom = Bloom::new(device, config, &target);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, &target, &bloom);
+
+        Self {
+            settings,
+            target,
+            bloom,
+            bind_group_layout,
+            uniform_buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn uniform(settings: &HdrSettings) -> TonemapUniform {
+        TonemapUniform {
+            exposure: settings.exposure.exp2(),
+            tonemapper: settings.tonemapper.index(),
+            bloom_intensity: if settings.bloom { settings.bloom_intensity } else { 0.0 },
+            _padding: 0.0,
+        }
+    }
+
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, uniform_buffer: &wgpu::Buffer, target: &texture::Texture, bloom: &Bloom) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&target.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&bloom.output().view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&target.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.target = texture::Texture::create_render_target(device, config, HDR_FORMAT, 1, "hdr_texture");
+        self.bloom.resize(device, config, &self.target);
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.uniform_buffer, &self.target, &self.bloom);
+    }
+
+    // Where the scene is drawn, or resolved into with MSAA on
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.target.view
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[Self::uniform(&self.settings)]));
+        self.bloom.update(queue, self.settings.bloom_threshold, self.settings.bloom_knee, self.settings.bloom_radius);
+    }
+
+    // Bloom, then tonemapping into `target`, which is in the surface's format
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        if self.settings.bloom {
+            self.bloom.render(encoder);
+        }
+        crate::draw_fullscreen(encoder, "Tonemap Pass", target, wgpu::LoadOp::Clear(wgpu::Color::BLACK), &self.pipeline, &[&self.bind_group]);
+    }
+}
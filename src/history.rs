@@ -0,0 +1,253 @@
+use std::collections::HashSet;
+
+use cgmath::{Quaternion, Vector3};
+
+use crate::component::ComponentData;
+use crate::prefab::Prefab;
+use crate::scene::SceneInstance;
+use crate::{ModelInstances, State};
+
+// Where an object is and how big it is, the part of it the editor moves around
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+// Whether an object casts and receives shadows
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) struct Shadows {
+    pub cast: bool,
+    pub receive: bool,
+}
+
+// An editor action that can be taken back. Objects are found by their body index,
+// which stays right as long as commands are undone in the order they were done.
+pub(crate) enum Command {
+    // The instance is kept here while it's undone
+    Spawn { model: String, body: usize, removed: Option<SceneInstance> },
+    Delete { model: String, body: usize, removed: Option<SceneInstance> },
+    Transform { body: usize, before: Transform, after: Transform },
+    Component { body: usize, before: ComponentData, after: ComponentData },
+    Shadows { body: usize, before: Shadows, after: Shadows },
+    // The model and its GPU buffers are kept here while it's undone
+    LoadModel { file_name: String, unloaded: Option<ModelInstances> },
+    Prefab { file_name: String, before: Prefab, after: Prefab },
+}
+
+impl Command {
+    pub fn describe(&self) -> String {
+        match self {
+            Command::Spawn { model, body, .. } => format!("Spawn {} #{}", model, body),
+            Command::Delete { model, body, .. } => format!("Delete {} #{}", model, body),
+            Command::Transform { body, .. } => format!("Transform #{}", body),
+            Command::Component { body, after, .. } => format!("Edit {} #{}", after.name, body),
+            Command::Shadows { body, .. } => format!("Shadows #{}", body),
+            Command::LoadModel { file_name, .. } => format!("Load {}", file_name),
+            Command::Prefab { file_name, .. } => format!("Edit {}", file_name),
+        }
+    }
+
+    // Does or undoes the command, false if the world doesn't allow it right now
+    fn apply(&mut self, state: &mut State, undo: bool) -> bool {
+        match self {
+            Command::Spawn { body, removed, .. } if undo => take_out(state, *body, removed),
+            Command::Spawn { body, removed, .. } => put_back(state, *body, removed),
+            Command::Delete { body, removed, .. } if undo => put_back(state, *body, removed),
+            Command::Delete { body, removed, .. } => take_out(state, *body, removed),
+            Command::Transform { body, before, after } => {
+                state.set_transform(*body, if undo { *before } else { *after })
+            }
+            Command::Component { body, before, after } => {
+                state.set_component_data(*body, if undo { before } else { after })
+            }
+            Command::Shadows { body, before, after } => {
+                state.set_shadows(*body, if undo { *before } else { *after })
+            }
+            Command::LoadModel { file_name, unloaded } if undo => {
+                match state.unload_model(file_name) {
+                    Some(model) => {
+                        *unloaded = Some(model);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Command::LoadModel { unloaded, .. } => match unloaded.take() {
+                Some(model) => {
+                    state.model_instances.push(model);
+                    true
+                }
+                None => false,
+            },
+            Command::Prefab { file_name, before, after } => {
+                state.set_prefab(file_name, if undo { before } else { after }.clone())
+            }
+        }
+    }
+}
+
+fn take_out(state: &mut State, body: usize, removed: &mut Option<SceneInstance>) -> bool {
+    *removed = state.remove_instance(body);
+    removed.is_some()
+}
+
+fn put_back(state: &mut State, body: usize, removed: &mut Option<SceneInstance>) -> bool {
+    match removed.take() {
+        Some(instance) => state.insert_instance(body, instance),
+        None => false,
+    }
+}
+
+// An edit that's still being dragged or typed, it becomes a command once the
+// mouse is let go and nothing has focus
+pub(crate) enum PendingEdit {
+    Transform { body: usize, before: Transform },
+    Component { body: usize, before: ComponentData },
+    Prefab { file_name: String, before: Prefab },
+}
+
+impl PendingEdit {
+    fn same_target(&self, other: &PendingEdit) -> bool {
+        match (self, other) {
+            (PendingEdit::Transform { body: a, .. }, PendingEdit::Transform { body: b, .. }) => a == b,
+            (PendingEdit::Component { body: a, .. }, PendingEdit::Component { body: b, .. }) => a == b,
+            (PendingEdit::Prefab { file_name: a, .. }, PendingEdit::Prefab { file_name: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+pub(crate) struct History {
+    done: Vec<Command>,
+    undone: Vec<Command>,
+    pending: Option<PendingEdit>,
+    // Models the editor asked for, their load is recorded when they arrive
+    requested_models: HashSet<String>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            done: vec![],
+            undone: vec![],
+            pending: None,
+            requested_models: HashSet::new(),
+        }
+    }
+
+    // Records something that was just done, anything that was undone is dropped
+    pub fn push(&mut self, command: Command) {
+        self.done.push(command);
+        self.undone.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+        self.pending = None;
+    }
+
+    pub fn done(&self) -> &[Command] {
+        &self.done
+    }
+
+    pub fn undone(&self) -> &[Command] {
+        &self.undone
+    }
+
+    pub fn request_model(&mut self, file_name: &str) {
+        self.requested_models.insert(file_name.to_string());
+    }
+
+    // True if the editor asked for this model, the caller records the load
+    pub fn model_arrived(&mut self, file_name: &str) -> bool {
+        self.requested_models.remove(file_name)
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Starts an edit, or carries on with the one going if it's on the same thing. The
+// first before value is the one kept.
+pub(crate) fn begin_edit(state: &mut State, edit: PendingEdit) {
+    match &state.history.pending {
+        Some(pending) if pending.same_target(&edit) => {}
+        Some(_) => {
+            finish_edit(state);
+            state.history.pending = Some(edit);
+        }
+        None => state.history.pending = Some(edit),
+    }
+}
+
+// Turns the edit in progress into a command, reading the after value off the object
+pub(crate) fn finish_edit(state: &mut State) {
+    let command = match state.history.pending.take() {
+        Some(PendingEdit::Transform { body, before }) => state
+            .transform(body)
+            .filter(|after| *after != before)
+            .map(|after| Command::Transform { body, before, after }),
+        Some(PendingEdit::Component { body, before }) => state
+            .component_data(body)
+            .filter(|after| *after != before)
+            .map(|after| Command::Component { body, before, after }),
+        Some(PendingEdit::Prefab { file_name, before }) => state
+            .find_prefab(&file_name)
+            .map(|index| state.prefabs[index].1.clone())
+            .filter(|after| *after != before)
+            .map(|after| Command::Prefab { file_name, before, after }),
+        None => None,
+    };
+    if let Some(command) = command {
+        state.history.push(command);
+    }
+}
+
+pub(crate) fn undo(state: &mut State) {
+    finish_edit(state);
+    if let Some(mut command) = state.history.done.pop() {
+        if command.apply(state, true) {
+            state.history.undone.push(command);
+        } else {
+            log::warn!("Couldn't undo {}", command.describe());
+            state.history.done.push(command);
+        }
+    }
+}
+
+pub(crate) fn redo(state: &mut State) {
+    finish_edit(state);
+    if let Some(mut command) = state.history.undone.pop() {
+        if command.apply(state, false) {
+            state.history.done.push(command);
+        } else {
+            log::warn!("Couldn't redo {}", command.describe());
+            state.history.undone.push(command);
+        }
+    }
+}
+
+// Switches an object's shadows as an undoable command. It's a single click, so
+// there's nothing pending to wait on.
+pub(crate) fn set_shadows(state: &mut State, body: usize, shadows: Shadows) {
+    finish_edit(state);
+    if let Some(before) = state.shadows(body).filter(|before| *before != shadows) {
+        state.set_shadows(body, shadows);
+        state.history.push(Command::Shadows { body, before, after: shadows });
+    }
+}
+
+// Deletes an object as an undoable command
+pub(crate) fn delete(state: &mut State, body: usize) {
+    finish_edit(state);
+    if let Some(removed) = state.remove_instance(body) {
+        let model = removed.model.clone();
+        state.history.push(Command::Delete { model, body, removed: Some(removed) });
+    }
+}
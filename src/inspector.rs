@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use winit::event::VirtualKeyCode;
+
+use crate::component::{ComponentData, ComponentRegistry, Field};
+
+// Keys offered when binding a component's input
+const KEYS: [VirtualKeyCode; 30] = [
+    VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D,
+    VirtualKeyCode::E, VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H,
+    VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::L,
+    VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::P,
+    VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+    VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X,
+    VirtualKeyCode::Y, VirtualKeyCode::Z,
+    VirtualKeyCode::Up, VirtualKeyCode::Down, VirtualKeyCode::Left, VirtualKeyCode::Right,
+];
+
+// Draws a widget for each reflected field, returns true if any of them changed
+pub fn fields_ui(ui: &mut egui::Ui, id: &str, fields: Vec<(&str, Field<'_>)>) -> bool {
+    let mut changed = false;
+    for (name, field) in fields {
+        changed |= match field {
+            Field::F32(value) => ui.add(egui::DragValue::new(value).prefix(format!("{}: ", name)).speed(0.05)).changed(),
+            Field::Bool(value) => ui.checkbox(value, name).changed(),
+            Field::Key(value) => {
+                let before = *value;
+                egui::ComboBox::from_id_source(format!("{} {}", id, name))
+                    .selected_text(format!("{}: {:?}", name, value))
+                    .show_ui(ui, |ui| {
+                        for key in KEYS {
+                            ui.selectable_value(&mut *value, key, format!("{:?}", key));
+                        }
+                    });
+                *value != before
+            }
+            Field::Vec3(value) => {
+                ui.add(egui::Label::new(name));
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut value.x).prefix("x: ").speed(0.05)).changed()
+                        | ui.add(egui::DragValue::new(&mut value.y).prefix("y: ").speed(0.05)).changed()
+                        | ui.add(egui::DragValue::new(&mut value.z).prefix("z: ").speed(0.05)).changed()
+                }).inner
+            }
+            Field::String(value) => {
+                ui.horizontal(|ui| {
+                    ui.add(egui::Label::new(name));
+                    ui.add(egui::TextEdit::singleline(value)).changed()
+                }).inner
+            }
+            Field::Choice(value, options) => {
+                let before = value.clone();
+                egui::ComboBox::from_id_source(format!("{} {}", id, name))
+                    .selected_text(format!("{}: {}", name, value))
+                    .show_ui(ui, |ui| {
+                        for option in options {
+                            ui.selectable_value(&mut *value, option.to_string(), *option);
+                        }
+                    });
+                *value != before
+            }
+        };
+    }
+    changed
+}
+
+// Picks a registered component, or none, and edits its settings
+pub fn component_ui(ui: &mut egui::Ui, id: &str, registry: &ComponentRegistry, component: &mut Option<ComponentData>) -> bool {
+    let mut changed = false;
+    let selected = component.as_ref().map(|c| c.name.clone()).unwrap_or(String::from("None"));
+    egui::ComboBox::from_id_source(format!("{} component", id))
+        .selected_text(format!("Component: {}", selected))
+        .show_ui(ui, |ui| {
+            if ui.selectable_label(component.is_none(), "None").clicked() && component.is_some() {
+                *component = None;
+                changed = true;
+            }
+            for name in registry.names() {
+                if ui.selectable_label(selected == name, name).clicked() && selected != name {
+                    *component = registry.default_data(name);
+                    changed = true;
+                }
+            }
+        });
+    if let Some(component) = component {
+        // Saved data only knows choices are strings, ask a fresh component for the options
+        let choices: HashMap<&str, &'static [&'static str]> = match registry.create(&component.name) {
+            Some(mut fresh) => fresh
+                .fields()
+                .into_iter()
+                .filter_map(|(name, field)| match field {
+                    Field::Choice(_, options) => Some((name, options)),
+                    _ => None,
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+        let fields = component
+            .fields()
+            .into_iter()
+            .map(|(name, field)| match (field, choices.get(name)) {
+                (Field::String(value), Some(options)) => (name, Field::Choice(value, options)),
+                (field, _) => (name, field),
+            })
+            .collect();
+        changed |= fields_ui(ui, id, fields);
+    }
+    changed
+}
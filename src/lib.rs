@@ -5,30 +5,50 @@ use physics::RigidBody;
 #[cfg(target_arch="wasm32")]
 use wasm_bindgen::prelude::*;
 
-use std::{collections::HashMap, iter};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, iter, rc::Rc};
 use wgpu::util::DeviceExt;
 
 mod texture;
-mod camera;
+pub mod camera;
 mod gui;
 mod model;
 mod resources;
 //mod thing;
-mod physics;
-mod component;
-
-mod components {
+pub mod physics;
+pub mod component;
+mod scene;
+pub mod prefab;
+mod inspector;
+pub mod app;
+pub mod events;
+pub mod scheduler;
+pub mod fsm;
+pub mod behaviour;
+pub mod pong;
+pub mod light;
+mod shadow;
+pub mod antialias;
+mod bloom;
+pub mod hdr;
+pub mod post;
+mod ssao;
+mod history;
+mod gizmo;
+
+pub use app::{App, System};
+
+pub mod components {
     pub mod paddle;
+    pub mod ai_paddle;
+    pub mod goal_zone;
     pub mod ball;
+    pub mod script;
+    pub mod behaviour_tree;
 }
 
 use crate::components::paddle;
 
-use winit::{
-    event::{self, *},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
-};
+use winit::event::{self, *};
 
 use winit::window::Window;
 
@@ -41,19 +61,6 @@ use model::{DrawModel,Vertex, Model};
 
 use web_sys::{HtmlInputElement, FileList, File};
 
-#[derive(Debug, PartialEq, Clone, Copy,Eq, Hash)]
-enum Models {
-    French_Bulldog,
-    Cube,
-    Wall,
-}
-
-#[derive(Debug, PartialEq, Clone, Copy,Eq, Hash)]
-enum ComponentSelection{
-    None,
-    Paddle,
-    Ball,
-}
  
 struct ModelInstances {
     model : model::Model,
@@ -80,26 +87,57 @@ impl ModelInstances {
         }
     }
 
-    pub fn add_instance(&mut self ,device : &wgpu::Device,rigidbodys : &mut Vec<physics::RigidBody>,position : [f32; 3],euler : [f32;3],component : Option<Box<dyn component::Component>>){
-        rigidbodys.push(RigidBody{
+    pub fn add_instance(&mut self ,device : &wgpu::Device,rigidbodys : &mut Vec<physics::RigidBody>,position : [f32; 3],euler : [f32;3],scale : [f32;3],component : Option<Box<dyn component::Component>>){
+        let scale = cgmath::Vector3{x:scale[0],y:scale[1],z:scale[2]};
+        let rigid_body = RigidBody{
             position: cgmath::Vector3{x:position[0],y:position[1],z:position[2]},
             rotation: cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Deg(euler[0]),cgmath::Deg(euler[1]),cgmath::Deg(euler[2]))),
             velocity: cgmath::Vector3{x: 0.0, y: 0.0, z: 0.0},
             acceleration: cgmath::Vector3{x: 0.0, y: 0.0, z: 0.0},
             angular_velocity: cgmath::Vector3{x: 0.0, y: 0.0, z: 0.0},
             mass: 1.0,
-            shape: physics::Shape::Box(cgmath::Vector3::new(1.0, 1.0, 1.0)),
+            // A box around the model, sized and placed with it
+            shape: physics::Shape::Box(self.model.bounds.size()).scaled(scale),
+            offset: self.model.bounds.centre().mul_element_wise(scale),
+            trigger: false,
+            removed: false,
         
-        });
+        };
+        self.push_instance(device, rigidbodys, rigid_body, scale, component, None);
+    }
+
+    // Adds an instance around an already built rigid body, the instance takes its
+    // position and rotation from the body.
+    pub fn push_instance(&mut self ,device : &wgpu::Device,rigidbodys : &mut Vec<physics::RigidBody>,rigid_body : RigidBody,scale : cgmath::Vector3<f32>,component : Option<Box<dyn component::Component>>,prefab : Option<prefab::PrefabLink>){
         self.instances.push(Instance{
-            position: cgmath::Vector3{x:position[0],y:position[1],z:position[2]},
-            rotation: cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Deg(euler[0]),cgmath::Deg(euler[1]),cgmath::Deg(euler[2]))),
-            rigid_body: rigidbodys.len() - 1,
+            position: rigid_body.position,
+            rotation: rigid_body.rotation,
+            scale,
+            rigid_body: rigidbodys.len(),
             component,
             started: false,
+            prefab,
+            selected: false,
+            cast_shadows: true,
+            receive_shadows: true,
         });
-        
+        rigidbodys.push(rigid_body);
+
+        self.rebuild_instance_buffer(device);
+    }
+
+    pub fn remove_instance(&mut self, device : &wgpu::Device, index : usize) -> Instance {
+        let instance = self.instances.remove(index);
+        self.rebuild_instance_buffer(device);
+        instance
+    }
+
+    pub fn clear_instances(&mut self, device : &wgpu::Device){
+        self.instances.clear();
+        self.rebuild_instance_buffer(device);
+    }
 
+    fn rebuild_instance_buffer(&mut self, device : &wgpu::Device){
         let instance_data = self.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
 
         self.instance_buffer = device.create_buffer_init(
@@ -116,28 +154,36 @@ impl ModelInstances {
 
 pub struct Data{
     clear_color: [f32; 4],
-    model_selected: Models,
+    model_selected: usize,
     position: [f32; 3],
     euler: [f32; 3],
-    component_selected: ComponentSelection,
-    input1:VirtualKeyCode,
-    input2:VirtualKeyCode,
-    input3:VirtualKeyCode,
-    input4:VirtualKeyCode,
+    scale: [f32; 3],
+    // Component given to spawned objects, edited through the inspector
+    component: Option<component::ComponentData>,
+    scene_path: String,
+    model_path: String,
+    lut_path: String,
+    // How models loaded from here on fill in normals and texture coordinates they're missing
+    import_settings: resources::ImportSettings,
+    prefab_selected: usize,
+    override_scale: bool,
 }
 
 impl Data {
     pub fn new() -> Self {
         Self {
             clear_color: [0.1, 0.2, 0.3, 1.0],
-            model_selected: Models::French_Bulldog,
+            model_selected: 0,
             position: [0.0, 0.0, 0.0],
             euler: [0.0, 0.0, 0.0],
-            component_selected: ComponentSelection::None,
-            input1: VirtualKeyCode::I,
-            input2: VirtualKeyCode::K,
-            input3: VirtualKeyCode::J,
-            input4: VirtualKeyCode::L,
+            scale: [1.0, 1.0, 1.0],
+            component: None,
+            scene_path: String::from("scene.ron"),
+            model_path: String::new(),
+            lut_path: String::new(),
+            import_settings: resources::ImportSettings::default(),
+            prefab_selected: 0,
+            override_scale: false,
         }
     }
 }
@@ -167,31 +213,51 @@ use crate::component::Component;
 pub struct Instance {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
+    scale: cgmath::Vector3<f32>,
     rigid_body: usize,
     component : Option<Box<dyn Component>>,
-    started : bool
+    started : bool,
+    prefab : Option<prefab::PrefabLink>,
+    // Picked in the editor, drawn highlighted
+    selected : bool,
+    cast_shadows : bool,
+    receive_shadows : bool,
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct InstanceRaw {
     model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+    highlight: f32,
+    receive_shadows: f32,
+    cast_shadows: f32,
 }
 
 impl Instance {
     fn to_raw(&self) -> InstanceRaw {
+        // Normals need the inverse transpose of the model matrix. With no shear that is
+        // just the rotation with the reciprocal of the scale applied.
+        let inverse_scale = cgmath::Vector3::new(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z);
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
+            model: (cgmath::Matrix4::from_translation(self.position)
+                * cgmath::Matrix4::from(self.rotation)
+                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)).into(),
+            normal: (cgmath::Matrix3::from(self.rotation) * cgmath::Matrix3::from_diagonal(inverse_scale)).into(),
+            highlight: if self.selected { 1.0 } else { 0.0 },
+            receive_shadows: if self.receive_shadows { 1.0 } else { 0.0 },
+            cast_shadows: if self.cast_shadows { 1.0 } else { 0.0 },
         }
     }
 
-    fn update(&mut self, delta_time: f32,rigidbodys : &mut Vec<physics::RigidBody>) {
+    fn update(&mut self, delta_time: f32,rigidbodys : &mut Vec<physics::RigidBody>,events : &mut events::Events,scheduler : &mut scheduler::Scheduler) {
         if let Some(component) = &mut self.component {
+            let mut ctx = component::Context { rigidbodys, body_index: self.rigid_body, events, scheduler };
             if !self.started {
-                component.start(rigidbodys, self.rigid_body);
+                component.start(&mut ctx);
                 self.started = true;
             }
-            component.update( delta_time,rigidbodys, self.rigid_body);
+            component.update(delta_time, &mut ctx);
         }
 
         rigidbodys[self.rigid_body].update(delta_time);
@@ -204,6 +270,15 @@ impl Instance {
             component.input(event);
         }
     }
+
+    fn on_event(&mut self, event: &events::Event, rigidbodys : &mut Vec<physics::RigidBody>, events : &mut events::Events, scheduler : &mut scheduler::Scheduler){
+        if let Some(component) = &mut self.component {
+            if self.started {
+                let mut ctx = component::Context { rigidbodys, body_index: self.rigid_body, events, scheduler };
+                component.on_event(event, &mut ctx);
+            }
+        }
+    }
 }
 
 impl InstanceRaw {
@@ -240,6 +315,38 @@ impl InstanceRaw {
                     shader_location: 8,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // The normal matrix is a mat3, so it takes another 3 slots.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                // Shadow flags, read by the main shader and the shadow pass
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 26]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 27]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32,
+                },
             ],
         }
     }
@@ -253,6 +360,8 @@ impl InstanceRaw {
 // This is so we can store this in a buffer
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct CameraUniform {
+    // Eye position for specular lighting, w is unused but uniforms need 16 byte alignment
+    view_position: [f32; 4],
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
@@ -262,36 +371,233 @@ impl CameraUniform {
     fn new() -> Self {
         use cgmath::SquareMatrix;
         Self {
+            view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
     fn update_view_proj(&mut self, camera: &camera::Camera) {
+        self.view_position = camera.eye.to_homogeneous().into();
         self.view_proj = camera.build_view_projection_matrix().into();
     }
 }
 
-struct State{
+// What can differ between pipelines drawing models, the rest is the same for all of them
+struct ScenePipelineDescriptor<'a> {
+    label: &'a str,
+    layout: &'a wgpu::PipelineLayout,
+    shader: &'a wgpu::ShaderModule,
+    // None only draws depth, with `fs_depth`
+    format: Option<wgpu::TextureFormat>,
+    blend: wgpu::BlendState,
+    depth_write_enabled: bool,
+    depth_compare: wgpu::CompareFunction,
+    sample_count: u32,
+}
+
+fn create_render_pipeline(device: &wgpu::Device, desc: &ScenePipelineDescriptor) -> wgpu::RenderPipeline {
+    let ScenePipelineDescriptor { label, layout, shader, format, blend, depth_write_enabled, depth_compare, sample_count } = *desc;
+    let targets = [format.map(|format| wgpu::ColorTargetState {
+        format,
+        blend: Some(blend),
+        write_mask: wgpu::ColorWrites::ALL,
+    })];
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState { 
+            module: shader,
+            entry_point: if format.is_some() { "fs_main" } else { "fs_depth" },
+            targets: if format.is_some() { &targets } else { &[] },
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList, 
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled,
+            depth_compare,
+            stencil: wgpu::StencilState::default(), 
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// The opaque, see-through and depth only pipelines, remade whenever the sample
+// count changes. See-through surfaces blend over what's behind them and don't
+// hide what's drawn after. The depth only one fills in the depth buffer ahead of
+// the main pass for SSAO, so opaque surfaces pass where their depth is equal.
+fn create_scene_pipelines(device: &wgpu::Device, layout: &wgpu::PipelineLayout, shader: &wgpu::ShaderModule, sample_count: u32) -> (wgpu::RenderPipeline, wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let opaque = ScenePipelineDescriptor {
+        label: "Render Pipeline",
+        layout,
+        shader,
+        format: Some(hdr::HDR_FORMAT),
+        blend: wgpu::BlendState::REPLACE,
+        depth_write_enabled: true,
+        depth_compare: wgpu::CompareFunction::LessEqual,
+        sample_count,
+    };
+    let blend = ScenePipelineDescriptor {
+        label: "Blend Pipeline",
+        blend: wgpu::BlendState::ALPHA_BLENDING,
+        depth_write_enabled: false,
+        depth_compare: wgpu::CompareFunction::Less,
+        ..opaque
+    };
+    let depth = ScenePipelineDescriptor {
+        label: "Depth Pipeline",
+        format: None,
+        depth_compare: wgpu::CompareFunction::Less,
+        ..opaque
+    };
+    (create_render_pipeline(device, &opaque), create_render_pipeline(device, &blend), create_render_pipeline(device, &depth))
+}
+
+// A full screen pass's shader with fullscreen.wgsl, and so `vs_main` and
+// `VertexOutput`, added on the front
+fn create_fullscreen_shader(device: &wgpu::Device, label: &str, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(format!("{}\n{}", include_str!("fullscreen.wgsl"), source).into()),
+    })
+}
+
+// Full screen passes draw one triangle made up in `vs_main`, so there are no
+// vertex buffers and no depth
+fn create_fullscreen_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: wgpu::BlendState,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// Runs a full screen pipeline from above over the whole of `target`
+fn draw_fullscreen(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+    pipeline: &wgpu::RenderPipeline,
+    bind_groups: &[&wgpu::BindGroup],
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    for (i, bind_group) in bind_groups.iter().enumerate() {
+        render_pass.set_bind_group(i as u32, bind_group, &[]);
+    }
+    render_pass.draw(0..3, 0..1);
+}
+
+// Filled in by an async load and emptied by the next update
+type Inbox<T> = Rc<RefCell<Option<T>>>;
+
+// Everything the engine owns. Games get at it through the `App` callbacks.
+pub struct State{
     #[allow(dead_code)]
     instance: wgpu::Instance,
     #[allow(dead_code)]
     adapter: wgpu::Adapter,
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    // Shared so that assets can keep loading after the frame that asked for them
+    device: Rc<wgpu::Device>,
+    queue: Rc<wgpu::Queue>,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     clear_color: wgpu::Color,
     window: Window,
     render_pipeline: wgpu::RenderPipeline,
-    diffuse_bind_group: wgpu::BindGroup,
+    // Alpha blended materials are drawn after the rest with this
+    blend_pipeline: wgpu::RenderPipeline,
+    // Draws the opaque meshes' depth before the main pass when SSAO needs it
+    depth_pipeline: wgpu::RenderPipeline,
+    // Kept to rebuild the pipelines above when the sample count changes
+    render_pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    anti_aliasing: antialias::AntiAliasing,
+    // MSAA sample counts this adapter can do, 1 is always there
+    sample_counts: Vec<u32>,
+    // The scene is drawn into this with MSAA on and resolved into the frame
+    msaa_target: Option<texture::Texture>,
+    fxaa: Option<antialias::Fxaa>,
+    // The scene is lit into this, then bloomed and tonemapped into the frame
+    hdr: hdr::Hdr,
+    // Ambient occlusion, worked out before the main pass and read by it
+    ssao: ssao::Ssao,
+    // Full screen effects run after tonemapping
+    post: post::PostStack,
     #[allow(dead_code)]
-    diffuse_texture: texture::Texture,
+    default_material: model::Material,
     camera: camera::Camera,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
     camera_controller: camera::CameraController,
+    // Lights in the scene, the first MAX_LIGHTS of them are sent to the shader each frame
+    lights: Vec<light::Light>,
+    ambient: [f32; 3],
+    light_buffer: wgpu::Buffer,
+    light_bind_group: wgpu::BindGroup,
+    shadows: shadow::ShadowMaps,
     //instances: Vec<Instance>,
     //instance_buffer: wgpu::Buffer,
     depth_texture: texture::Texture,
@@ -302,8 +608,36 @@ struct State{
     frame_times : Vec<u128>,
     //obj_model: model::Model,
     model_instances: Vec<ModelInstances>,
-    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_bind_group_layout: Rc<wgpu::BindGroupLayout>,
     rigidbodys: Vec<physics::RigidBody>,
+    registry: component::ComponentRegistry,
+    systems: Vec<Box<dyn app::System>>,
+    events: events::Events,
+    subscribers: Vec<events::Subscriber>,
+    // Overlapping body pairs from the last update, to spot collisions starting and ending
+    contacts: HashSet<(usize, usize)>,
+    scheduler: scheduler::Scheduler,
+    // The Pong match being played, if one was started
+    pong: Option<pong::PongMatch>,
+    // Simulation clock, wall clock frame time is scaled by this and stopped while paused
+    paused: bool,
+    time_scale: f32,
+    show_editor: bool,
+    // Undo and redo for editor actions
+    history: history::History,
+    modifiers: ModifiersState,
+    // Last place the mouse was seen, in pixels from the top left of the window
+    cursor: winit::dpi::PhysicalPosition<f64>,
+    gizmo: gizmo::Gizmo,
+    loaded_models: Rc<RefCell<Vec<model::Model>>>,
+    requested_models: HashSet<String>,
+    // Loaded prefabs along with the file they came from
+    prefabs: Vec<(String, prefab::Prefab)>,
+    loaded_prefabs: Rc<RefCell<Vec<(String, prefab::Prefab)>>>,
+    scene_inbox: Inbox<String>,
+    // A colour grading LUT file that's finished loading, with its name
+    lut_inbox: Inbox<(String, Vec<u8>)>,
+    pending_scene: Option<scene::Scene>,
 }
 
 impl State {
@@ -334,7 +668,8 @@ impl State {
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
+                // Lets MSAA use every sample count the adapter has, not just 4x
+                features: adapter.features() & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
                 // WebGL doesn't support all of wgpu's features, so if
                 // we're building for the web we'll have to disable some.
                 limits: if cfg!(target_arch = "wasm32") {
@@ -346,6 +681,8 @@ impl State {
             },
             None, // Trace path
         ).await.unwrap();
+        let device = Rc::new(device);
+        let queue = Rc::new(queue);
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -366,11 +703,24 @@ impl State {
         };
         surface.configure(&device, &config);
 
-        let depth_texture = texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+        let sample_counts = antialias::supported_sample_counts(&adapter, device.features(), hdr::HDR_FORMAT, texture::Texture::DEPTH_FORMAT);
+        let anti_aliasing = antialias::AntiAliasing::best(&sample_counts);
+        let sample_count = anti_aliasing.sample_count();
+        let depth_texture = texture::Texture::create_depth_texture(&device, &config, sample_count, "depth_texture");
+        let msaa_target = if sample_count > 1 {
+            Some(texture::Texture::create_render_target(&device, &config, hdr::HDR_FORMAT, sample_count, "msaa_texture"))
+        } else {
+            None
+        };
+        let fxaa = if anti_aliasing == antialias::AntiAliasing::Fxaa { Some(antialias::Fxaa::new(&device, &config)) } else { None };
+        let hdr = hdr::Hdr::new(&device, &config);
+        let mut ssao = ssao::Ssao::new(&device, &queue, &config);
+        ssao.set_depth(&device, texture::Texture::depth_readable(sample_count).then_some(&depth_texture), sample_count);
+        let post = post::PostStack::new(&device, &queue, &config);
 
 
         let diffuse_bytes = include_bytes!("allmyfellas.png"); // CHANGED!
-        let diffuse_texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "allmyfellas.png").unwrap(); // CHANGED!
+        let diffuse_texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "allmyfellas.png", texture::TextureKind::Color, texture::SamplerSettings::clamped()).unwrap(); // CHANGED!
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -393,25 +743,95 @@ impl State {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // Normal map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // Metallic and roughness
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // Emissive
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // Occlusion
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // Material factors
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
         
-        let diffuse_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    }
-                ],
-                label: Some("diffuse_bind_group"),
-            }
+        let default_material = model::Material::new(
+            &device,
+            "default_material",
+            model::MaterialFactors::default(),
+            model::MaterialTextures { base_color: diffuse_texture, ..model::MaterialTextures::plain(&device, &queue) },
+            &texture_bind_group_layout,
         );
         
         let camera = camera::Camera {
@@ -441,7 +861,7 @@ impl State {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -463,6 +883,82 @@ impl State {
             ],
             label: Some("camera_bind_group"),
         });
+
+        let lights = light::default_lights();
+        let ambient = light::default_ambient();
+        let shadows = shadow::ShadowMaps::new(&device);
+        let light_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Light Buffer"),
+                contents: bytemuck::cast_slice(&[light::LightsUniform::new(&lights, ambient, &[])]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                }
+            ],
+            label: Some("light_bind_group_layout"),
+        });
+
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &light_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: shadows.uniform_buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(shadows.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(shadows.sampler()),
+                }
+            ],
+            label: Some("light_bind_group"),
+        });
         
         let gui = gui::Gui::new(&window, &device);
 
@@ -477,56 +973,12 @@ impl State {
 
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout,&camera_bind_group_layout],
+                bind_group_layouts: &[&texture_bind_group_layout,&camera_bind_group_layout,&light_bind_group_layout,ssao.bind_group_layout()],
                 push_constant_ranges: &[],
         });
 
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
-            },
-            fragment: Some(wgpu::FragmentState { 
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList, 
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less, 
-                stencil: wgpu::StencilState::default(), 
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-
-
-        });
+        let (render_pipeline, blend_pipeline, depth_pipeline) = create_scene_pipelines(&device, &render_pipeline_layout, &shader, sample_count);
 
         let camera_controller = camera::CameraController::new(0.2);
 
@@ -543,13 +995,28 @@ impl State {
             size,
             window,
             render_pipeline,
-            diffuse_bind_group,
-            diffuse_texture,
+            blend_pipeline,
+            depth_pipeline,
+            render_pipeline_layout,
+            shader,
+            anti_aliasing,
+            sample_counts,
+            msaa_target,
+            fxaa,
+            hdr,
+            ssao,
+            post,
+            default_material,
             camera,
             camera_uniform,
             camera_buffer,
             camera_bind_group,
             camera_controller,
+            lights,
+            ambient,
+            light_buffer,
+            light_bind_group,
+            shadows,
             depth_texture,
             gui,
             start_time: Instant::now(),
@@ -557,8 +1024,29 @@ impl State {
             last_frame_time: Instant::now(),
             frame_times: vec![],
             model_instances:vec![],
-            texture_bind_group_layout,
+            texture_bind_group_layout: Rc::new(texture_bind_group_layout),
             rigidbodys: vec![],
+            registry: component::ComponentRegistry::default(),
+            systems: vec![],
+            events: events::Events::new(),
+            subscribers: vec![],
+            contacts: HashSet::new(),
+            scheduler: scheduler::Scheduler::new(),
+            pong: None,
+            paused: false,
+            time_scale: 1.0,
+            show_editor: true,
+            history: history::History::new(),
+            modifiers: ModifiersState::empty(),
+            cursor: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            gizmo: gizmo::Gizmo::new(),
+            loaded_models: Rc::new(RefCell::new(vec![])),
+            requested_models: HashSet::new(),
+            prefabs: vec![],
+            loaded_prefabs: Rc::new(RefCell::new(vec![])),
+            scene_inbox: Rc::new(RefCell::new(None)),
+            lut_inbox: Rc::new(RefCell::new(None)),
+            pending_scene: None,
         }
     }
 
@@ -573,13 +1061,80 @@ impl State {
             self.config.width = new_size.width;
             
             self.surface.configure(&self.device, &self.config);
-            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.hdr.resize(&self.device, &self.config);
+            self.ssao.resize(&self.device, &self.config);
+            self.post.resize(&self.device, &self.config);
+            self.create_frame_targets();
+        }
+    }
+
+    // The depth and anti-aliasing targets follow the surface size and sample count
+    fn create_frame_targets(&mut self) {
+        let sample_count = self.anti_aliasing.sample_count();
+        self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, sample_count, "depth_texture");
+        let readable_depth = texture::Texture::depth_readable(sample_count).then_some(&self.depth_texture);
+        self.ssao.set_depth(&self.device, readable_depth, sample_count);
+        self.msaa_target = if sample_count > 1 {
+            Some(texture::Texture::create_render_target(&self.device, &self.config, hdr::HDR_FORMAT, sample_count, "msaa_texture"))
+        } else {
+            None
+        };
+        match (&mut self.fxaa, self.anti_aliasing) {
+            (Some(fxaa), antialias::AntiAliasing::Fxaa) => fxaa.resize(&self.device, &self.config),
+            (None, antialias::AntiAliasing::Fxaa) => self.fxaa = Some(antialias::Fxaa::new(&self.device, &self.config)),
+            _ => self.fxaa = None,
+        }
+    }
+
+    // Sample counts the adapter can't do are turned down to the nearest one it can
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: antialias::AntiAliasing) {
+        let anti_aliasing = match anti_aliasing {
+            antialias::AntiAliasing::Msaa(count) if !self.sample_counts.contains(&count) => {
+                let fallback = self.sample_counts.iter().copied().filter(|c| *c < count).max().unwrap_or(1);
+                log::warn!("{}x MSAA isn't supported here, using {}x", count, fallback);
+                if fallback > 1 { antialias::AntiAliasing::Msaa(fallback) } else { antialias::AntiAliasing::Off }
+            }
+            antialias::AntiAliasing::Msaa(1) => antialias::AntiAliasing::Off,
+            other => other,
+        };
+        if anti_aliasing == self.anti_aliasing {
+            return;
+        }
+        let sample_count = anti_aliasing.sample_count();
+        if sample_count != self.anti_aliasing.sample_count() {
+            (self.render_pipeline, self.blend_pipeline, self.depth_pipeline) = create_scene_pipelines(&self.device, &self.render_pipeline_layout, &self.shader, sample_count);
         }
+        self.anti_aliasing = anti_aliasing;
+        self.create_frame_targets();
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = *position;
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state,
+                    virtual_keycode: Some(key),
+                    ..
+                },
+                ..
+            } => {
+                self.events.publish(events::KeyInput { key: *key, pressed: *state == ElementState::Pressed });
+                if *state == ElementState::Pressed && self.editor_shortcut(*key) {
+                    return true;
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.events.publish(events::MouseInput { button: *button, pressed: *state == ElementState::Pressed });
+                if *state == ElementState::Pressed && *button == MouseButton::Left && self.show_editor
+                    && !self.gizmo.is_active() && !self.gui.platform_mut().context().wants_pointer_input() {
+                    self.pick(self.modifiers.shift());
+                }
             }
             _ => {},
         }
@@ -591,6 +1146,61 @@ impl State {
         self.camera_controller.process_events(event)
     }
 
+    // The nearest object under the mouse. Each instance is hit against its model's
+    // bounds and its collider, whichever is closer, so triggers can be picked too.
+    fn object_under_cursor(&self) -> Option<usize> {
+        let ray = self.camera.screen_ray(self.cursor.x as f32, self.cursor.y as f32, self.size.width as f32, self.size.height as f32);
+        let mut nearest: Option<(f32, usize)> = None;
+        for model_instance in self.model_instances.iter() {
+            let bounds = model_instance.model.bounds;
+            for instance in model_instance.instances.iter() {
+                let rigid_body = &self.rigidbodys[instance.rigid_body];
+                let mesh_hit = ray
+                    .to_local(rigid_body.position, rigid_body.rotation, instance.scale)
+                    .intersect_box(bounds.min, bounds.max);
+                let hit = match (mesh_hit, rigid_body.raycast(&ray)) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                if let Some(distance) = hit {
+                    if nearest.is_none_or(|(nearest, _)| distance < nearest) {
+                        nearest = Some((distance, instance.rigid_body));
+                    }
+                }
+            }
+        }
+        nearest.map(|(_, body)| body)
+    }
+
+    // Clicking picks the object under the mouse, shift-clicking adds it to the
+    // selection or takes it back out
+    fn pick(&mut self, add: bool) {
+        let picked = self.object_under_cursor();
+        for instance in self.model_instances.iter_mut().flat_map(|m| m.instances.iter_mut()) {
+            let under_cursor = Some(instance.rigid_body) == picked;
+            if add {
+                instance.selected ^= under_cursor;
+            } else {
+                instance.selected = under_cursor;
+            }
+        }
+    }
+
+    // Ctrl+Z undoes, Ctrl+Y or Ctrl+Shift+Z redoes. Text boxes keep their own undo.
+    fn editor_shortcut(&mut self, key: VirtualKeyCode) -> bool {
+        let command = self.modifiers.ctrl() || self.modifiers.logo();
+        if !self.show_editor || !command || self.gui.platform_mut().context().wants_keyboard_input() {
+            return false;
+        }
+        match key {
+            VirtualKeyCode::Z if self.modifiers.shift() => history::redo(self),
+            VirtualKeyCode::Z => history::undo(self),
+            VirtualKeyCode::Y => history::redo(self),
+            _ => return false,
+        }
+        true
+    }
+
     fn update(&mut self) {
         let now = Instant::now();
         let delta = now - self.last_frame_time;
@@ -600,12 +1210,45 @@ impl State {
         if self.frame_times.len() > 10 {
             self.frame_times.remove(0);
         }
+
+        let loaded_models = self.loaded_models.borrow_mut().drain(..).collect::<Vec<_>>();
+        for model in loaded_models {
+            self.requested_models.remove(&model.name);
+            if self.history.model_arrived(&model.name) {
+                self.history.push(history::Command::LoadModel { file_name: model.name.clone(), unloaded: None });
+            }
+            self.add_model(model);
+        }
+        let loaded_prefabs = self.loaded_prefabs.borrow_mut().drain(..).collect::<Vec<_>>();
+        for (file_name, prefab) in loaded_prefabs {
+            self.add_prefab(file_name, prefab);
+        }
+        let lut = self.lut_inbox.borrow_mut().take();
+        if let Some((file_name, bytes)) = lut {
+            if let Err(e) = self.post.load_lut(&self.device, &self.queue, &file_name, &bytes) {
+                log::warn!("Couldn't use LUT {}: {}", file_name, e);
+            }
+        }
+        let scene_text = self.scene_inbox.borrow_mut().take();
+        if let Some(text) = scene_text {
+            match scene::Scene::from_ron(&text) {
+                Ok(scene) => self.load_scene(scene),
+                Err(e) => log::warn!("Couldn't parse scene: {}", e),
+            }
+        }
+        self.spawn_pending_scene();
         self.camera_controller.update_camera(&mut self.camera);
         self.camera_uniform.update_view_proj(&self.camera);
         self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        let shadows = self.shadows.update(&self.queue, &self.lights, &self.camera);
+        self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[light::LightsUniform::new(&self.lights, self.ambient, &shadows)]));
 
         //let model_instances_to_update: Vec<&ModelInstances> = vec![]; // List of model instances that have been changed this frame, write the buffer for these
-        let last_delta = self.get_last_delta() as f32 / 1000.0;
+        let last_delta = if self.paused {
+            0.0
+        } else {
+            self.get_last_delta() as f32 / 1000.0 * self.time_scale
+        };
 
 
         //Do all processing
@@ -614,9 +1257,40 @@ impl State {
         for i in 0..self.model_instances.len() {
             // Accessing each instance mutably
             for instance in self.model_instances[i].instances.iter_mut() {
-                instance.update(last_delta,self.rigidbodys.as_mut());
+                instance.update(last_delta,self.rigidbodys.as_mut(),&mut self.events,&mut self.scheduler);
             }
-        
+        }
+
+        let contacts = physics::find_contacts(&self.rigidbodys);
+        for &(a, b) in contacts.difference(&self.contacts) {
+            self.events.publish(events::CollisionStarted { a, b });
+        }
+        for &(a, b) in self.contacts.difference(&contacts) {
+            self.events.publish(events::CollisionEnded { a, b });
+        }
+        self.contacts = contacts;
+
+        // Systems can add to the list while running, so keep anything new
+        let mut systems = std::mem::take(&mut self.systems);
+        for system in systems.iter_mut() {
+            system.update(self, last_delta);
+        }
+        systems.append(&mut self.systems);
+        self.systems = systems;
+
+        pong::tick(self);
+        scheduler::tick(self, last_delta);
+
+        self.clear_color = wgpu::Color {
+            r: self.data.clear_color[0] as f64,
+            g: self.data.clear_color[1] as f64,
+            b: self.data.clear_color[2] as f64,
+            a: self.data.clear_color[3] as f64,
+        };
+
+        self.deliver_events();
+
+        for i in 0..self.model_instances.len() {
             // Preparing data for the buffer
             let instance_data = self.model_instances[i]
                 .instances
@@ -638,39 +1312,638 @@ impl State {
     fn add_model(&mut self, model: model::Model){
         self.model_instances.push(ModelInstances::new(model, &self.device, vec![]));
     }
-    
 
+    // Everything published this frame goes to the engine's subscribers and then
+    // every started component. This is the last step of the update.
+    fn deliver_events(&mut self) {
+        let delivered = self.events.take_queued();
+        let mut subscribers = std::mem::take(&mut self.subscribers);
+        for event in delivered.iter() {
+            for subscriber in subscribers.iter_mut() {
+                (subscriber.handler)(self, event);
+            }
+            for model_instance in self.model_instances.iter_mut() {
+                for instance in model_instance.instances.iter_mut() {
+                    instance.on_event(event, &mut self.rigidbodys, &mut self.events, &mut self.scheduler);
+                }
+            }
+        }
+        subscribers.append(&mut self.subscribers);
+        self.subscribers = subscribers;
+        self.events.set_delivered(delivered);
+    }
 
+    pub fn publish<T: 'static>(&mut self, event: T) {
+        self.events.publish(event);
+    }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+    pub fn events(&self) -> &events::Events {
+        &self.events
+    }
 
-        self.setup_gui();
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    pub fn subscribe<T, F>(&mut self, handler: F)
+    where
+        T: 'static,
+        F: FnMut(&mut State, &T) + 'static,
+    {
+        self.subscribers.push(events::Subscriber::new(handler));
+    }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
+    fn publish_spawned(&mut self, index: usize) {
+        let model = self.model_instances[index].model.name.clone();
+        self.events.publish(events::Spawned { model, body: self.rigidbodys.len() - 1 });
+    }
+
+    pub fn camera_mut(&mut self) -> &mut camera::Camera {
+        &mut self.camera
+    }
+
+    pub fn rigidbodys_mut(&mut self) -> &mut Vec<physics::RigidBody> {
+        &mut self.rigidbodys
+    }
+
+    pub fn registry_mut(&mut self) -> &mut component::ComponentRegistry {
+        &mut self.registry
+    }
+
+    pub fn set_clear_color(&mut self, color: [f32; 4]) {
+        self.data.clear_color = color;
+    }
+
+    pub fn set_editor_visible(&mut self, show_editor: bool) {
+        self.show_editor = show_editor;
+        if !show_editor {
+            for instance in self.model_instances.iter_mut().flat_map(|m| m.instances.iter_mut()) {
+                instance.selected = false;
+            }
+        }
+    }
+
+    // Adds a light to the scene, only the first MAX_LIGHTS are drawn
+    pub fn add_light(&mut self, light: light::Light) -> usize {
+        self.lights.push(light);
+        self.lights.len() - 1
+    }
+
+    pub fn lights_mut(&mut self) -> &mut Vec<light::Light> {
+        &mut self.lights
+    }
+
+    pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+        self.ambient = ambient;
+    }
+
+    pub fn scheduler_mut(&mut self) -> &mut scheduler::Scheduler {
+        &mut self.scheduler
+    }
+
+    // Stops the simulation clock: components, physics, systems and timers all see
+    // a delta time of zero
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.max(0.0);
+    }
+
+    pub fn load_model(&mut self, file_name: &str) {
+        self.request_model(file_name.to_string());
+    }
+
+    pub fn load_prefab(&mut self, file_name: &str) {
+        self.request_prefab(file_name.to_string());
+    }
+
+    // Opens a scene shipped in the res folder
+    pub fn load_scene_resource(&self, file_name: &str) {
+        let file_name = file_name.to_string();
+        let scene_inbox = self.scene_inbox.clone();
+        resources::spawn(async move {
+            match resources::load_string(&file_name).await {
+                Ok(text) => *scene_inbox.borrow_mut() = Some(text),
+                Err(e) => log::warn!("Couldn't load scene {}: {}", file_name, e),
+            }
+        });
+    }
+
+    // Spawns an instance of an already loaded model, returns false if it isn't loaded
+    pub fn spawn(&mut self, model: &str, position: [f32; 3], euler: [f32; 3], scale: [f32; 3], component: Option<Box<dyn Component>>) -> bool {
+        match self.find_model(model) {
+            Some(index) => {
+                self.model_instances[index].add_instance(&self.device, &mut self.rigidbodys, position, euler, scale, component);
+                self.publish_spawned(index);
+                true
+            }
+            None => {
+                log::warn!("Model {} isn't loaded", model);
+                false
+            }
+        }
+    }
+
+    // Loads a model in the background, it gets added on the next update after it finishes
+    fn request_model(&mut self, file_name: String){
+        if self.find_model(&file_name).is_some() || !self.requested_models.insert(file_name.clone()) {
+            return;
+        }
+        let device = self.device.clone();
+        let queue = self.queue.clone();
+        let layout = self.texture_bind_group_layout.clone();
+        let loaded_models = self.loaded_models.clone();
+        let settings = self.data.import_settings;
+        resources::spawn(async move {
+            match resources::load_model(&file_name, settings, &device, &queue, &layout).await {
+                Ok(model) => loaded_models.borrow_mut().push(model),
+                Err(e) => log::warn!("Couldn't load model {}: {}", file_name, e),
+            }
+        });
+    }
+
+    fn request_lut(&self, file_name: String){
+        let lut_inbox = self.lut_inbox.clone();
+        resources::spawn(async move {
+            match resources::load_binary(&file_name).await {
+                Ok(bytes) => *lut_inbox.borrow_mut() = Some((file_name, bytes)),
+                Err(e) => log::warn!("Couldn't load LUT {}: {}", file_name, e),
+            }
+        });
+    }
+
+    fn find_model(&self, file_name: &str) -> Option<usize> {
+        self.model_instances.iter().position(|m| m.model.name == file_name)
+    }
+
+    fn request_prefab(&self, file_name: String){
+        let loaded_prefabs = self.loaded_prefabs.clone();
+        resources::spawn(async move {
+            match prefab::load_prefab(&file_name).await {
+                Ok(prefab) => loaded_prefabs.borrow_mut().push((file_name, prefab)),
+                Err(e) => log::warn!("Couldn't load prefab {}: {}", file_name, e),
+            }
+        });
+    }
+
+    fn find_prefab(&self, file_name: &str) -> Option<usize> {
+        self.prefabs.iter().position(|(name, _)| name == file_name)
+    }
+
+    // Reloading a prefab that's already loaded replaces it and updates its instances
+    fn add_prefab(&mut self, file_name: String, prefab: prefab::Prefab){
+        self.request_model(prefab.model.clone());
+        match self.find_prefab(&file_name) {
+            Some(index) => {
+                self.prefabs[index].1 = prefab;
+                self.apply_prefab(&file_name, true);
+            }
+            None => self.prefabs.push((file_name, prefab)),
+        }
+    }
+
+    pub fn spawn_prefab(&mut self, file_name: &str, position: [f32; 3], euler: [f32; 3], overrides: prefab::PrefabOverrides) -> bool {
+        let prefab = match self.find_prefab(file_name) {
+            Some(index) => self.prefabs[index].1.clone(),
+            None => {
+                log::warn!("Prefab {} isn't loaded", file_name);
+                return false;
+            }
+        };
+        let index = match self.find_model(&prefab.model) {
+            Some(index) => index,
+            None => {
+                log::warn!("Model {} for prefab {} isn't loaded", prefab.model, file_name);
+                return false;
+            }
+        };
+        let mut rigid_body = RigidBody::new(
+            cgmath::Vector3::from(position),
+            cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Deg(euler[0]),cgmath::Deg(euler[1]),cgmath::Deg(euler[2]))),
+            cgmath::Vector3::new(0.0, 0.0, 0.0),
+            cgmath::Vector3::new(0.0, 0.0, 0.0),
+            prefab.mass(&overrides),
+        );
+        let bounds = self.model_instances[index].model.bounds;
+        let scale = prefab.scale(&overrides);
+        rigid_body.shape = prefab.collider(&overrides, &bounds);
+        rigid_body.offset = bounds.centre().mul_element_wise(scale);
+        let component = prefab.component(&overrides).and_then(|c| self.registry.build(&c));
+        let link = prefab::PrefabLink { prefab: file_name.to_string(), overrides };
+        self.model_instances[index].push_instance(&self.device, &mut self.rigidbodys, rigid_body, scale, component, Some(link));
+        self.publish_spawned(index);
+        true
+    }
+
+    // Pushes the prefab's current values onto every instance spawned from it,
+    // anything the instance overrides stays as it is. Components are only
+    // rebuilt when asked, since that resets their state. Instances without one
+    // get the prefab's.
+    fn apply_prefab(&mut self, file_name: &str, rebuild_components: bool){
+        let prefab = match self.find_prefab(file_name) {
+            Some(index) => self.prefabs[index].1.clone(),
+            None => return,
+        };
+        for model_instance in self.model_instances.iter_mut() {
+            let bounds = model_instance.model.bounds;
+            for instance in model_instance.instances.iter_mut() {
+                let overrides = match &instance.prefab {
+                    Some(link) if link.prefab == file_name => link.overrides.clone(),
+                    _ => continue,
+                };
+                instance.scale = prefab.scale(&overrides);
+                let rigid_body = &mut self.rigidbodys[instance.rigid_body];
+                rigid_body.mass = prefab.mass(&overrides);
+                rigid_body.shape = prefab.collider(&overrides, &bounds);
+                rigid_body.offset = bounds.centre().mul_element_wise(instance.scale);
+                if rebuild_components || instance.component.is_none() {
+                    instance.component = prefab.component(&overrides).and_then(|c| self.registry.build(&c));
+                    instance.started = false;
+                }
+            }
+        }
+    }
+
+    fn capture_scene(&mut self) -> scene::Scene {
+        let mut instances = vec![];
+        for model_instance in self.model_instances.iter_mut() {
+            for instance in model_instance.instances.iter_mut() {
+                instances.push(scene::SceneInstance {
+                    model: model_instance.model.name.clone(),
+                    scale: instance.scale,
+                    rigid_body: self.rigidbodys[instance.rigid_body].clone(),
+                    component: instance.component.as_mut().map(|c| component::save(c.as_mut())),
+                    prefab: instance.prefab.clone(),
+                    cast_shadows: instance.cast_shadows,
+                    receive_shadows: instance.receive_shadows,
+                });
+            }
+        }
+        scene::Scene {
+            clear_color: self.data.clear_color,
+            camera: self.camera.clone(),
+            models: self.model_instances.iter().map(|m| m.model.name.clone()).collect(),
+            prefabs: self.prefabs.iter().map(|(name, _)| name.clone()).collect(),
+            instances,
+            lights: self.lights.clone(),
+            ambient: self.ambient,
+        }
+    }
+
+    fn save_scene(&mut self){
+        let scene = self.capture_scene();
+        if let Err(e) = scene::save(&scene, &self.data.scene_path) {
+            log::warn!("Couldn't save scene: {}", e);
+        }
+    }
+
+    fn open_scene(&self){
+        if let Err(e) = scene::open(&self.data.scene_path, self.scene_inbox.clone()) {
+            log::warn!("Couldn't open scene: {}", e);
+        }
+    }
+
+    // Removes every instance and body, along with anything scheduled for them
+    fn clear_world(&mut self){
+        for model_instance in self.model_instances.iter_mut() {
+            model_instance.clear_instances(&self.device);
+        }
+        self.rigidbodys.clear();
+        self.contacts.clear();
+        self.scheduler.clear();
+        self.pong = None;
+        // Commands point at bodies by index, they'd hit the wrong objects now
+        self.history.clear();
+    }
+
+    // Which model and slot in its list the instance driving `body` is at
+    fn locate_instance(&self, body: usize) -> Option<(usize, usize)> {
+        self.model_instances.iter().enumerate().find_map(|(m, model_instance)| {
+            model_instance.instances.iter().position(|i| i.rigid_body == body).map(|i| (m, i))
+        })
+    }
+
+    fn instance_mut(&mut self, body: usize) -> Option<&mut Instance> {
+        let (m, i) = self.locate_instance(body)?;
+        Some(&mut self.model_instances[m].instances[i])
+    }
+
+    // Takes an object out of the world and returns what's needed to put it back.
+    // Its body is only marked removed, not taken out of the list. Components,
+    // queued events and scheduled actions hold on to body indices, and shifting
+    // the later bodies down would leave them pointing at the wrong objects.
+    // The empty slots go when the world is next cleared.
+    fn remove_instance(&mut self, body: usize) -> Option<scene::SceneInstance> {
+        let (m, i) = self.locate_instance(body)?;
+        let mut instance = self.model_instances[m].remove_instance(&self.device, i);
+        let rigid_body = self.rigidbodys[body].clone();
+        self.rigidbodys[body].removed = true;
+        self.contacts.retain(|(a, b)| *a != body && *b != body);
+        Some(scene::SceneInstance {
+            model: self.model_instances[m].model.name.clone(),
+            scale: instance.scale,
+            rigid_body,
+            component: instance.component.as_mut().map(|c| component::save(c.as_mut())),
+            prefab: instance.prefab,
+            cast_shadows: instance.cast_shadows,
+            receive_shadows: instance.receive_shadows,
+        })
+    }
+
+    // Puts a removed object back into the body slot it left behind
+    fn insert_instance(&mut self, body: usize, instance: scene::SceneInstance) -> bool {
+        let index = match self.find_model(&instance.model) {
+            Some(index) => index,
+            None => {
+                log::warn!("Model {} isn't loaded", instance.model);
+                return false;
+            }
+        };
+        let component = instance.component.as_ref().and_then(|c| self.registry.build(c));
+        self.model_instances[index].push_instance(&self.device, &mut self.rigidbodys, instance.rigid_body, instance.scale, component, instance.prefab);
+        // It was pushed on the end, move it back to its old slot if that's still free
+        let body = if body < self.rigidbodys.len() - 1 && self.rigidbodys[body].removed {
+            self.rigidbodys.swap_remove(body);
+            body
+        } else {
+            self.rigidbodys.len() - 1
+        };
+        if let Some(inserted) = self.model_instances[index].instances.last_mut() {
+            inserted.rigid_body = body;
+            inserted.cast_shadows = instance.cast_shadows;
+            inserted.receive_shadows = instance.receive_shadows;
+        }
+        self.events.publish(events::Spawned { model: instance.model, body });
+        true
+    }
+
+    fn transform(&self, body: usize) -> Option<history::Transform> {
+        let (m, i) = self.locate_instance(body)?;
+        let rigid_body = &self.rigidbodys[body];
+        Some(history::Transform {
+            position: rigid_body.position,
+            rotation: rigid_body.rotation,
+            scale: self.model_instances[m].instances[i].scale,
+        })
+    }
+
+    // Moves an object, its collider is stretched along with any change of scale
+    fn set_transform(&mut self, body: usize, transform: history::Transform) -> bool {
+        let instance = match self.instance_mut(body) {
+            Some(instance) => instance,
+            None => return false,
+        };
+        let old_scale = instance.scale;
+        instance.position = transform.position;
+        instance.rotation = transform.rotation;
+        instance.scale = transform.scale;
+        let rigid_body = &mut self.rigidbodys[body];
+        rigid_body.position = transform.position;
+        rigid_body.rotation = transform.rotation;
+        if old_scale != transform.scale {
+            let ratio = Vector3::new(transform.scale.x / old_scale.x, transform.scale.y / old_scale.y, transform.scale.z / old_scale.z);
+            rigid_body.rescale(ratio);
+            self.override_prefab_scale(body);
+        }
+        true
+    }
+
+    // Replaces a loaded prefab and pushes it onto its instances. Their components
+    // are only rebuilt if the prefab's changed.
+    fn set_prefab(&mut self, file_name: &str, prefab: prefab::Prefab) -> bool {
+        let index = match self.find_prefab(file_name) {
+            Some(index) => index,
+            None => return false,
+        };
+        let rebuild_components = prefab.component != self.prefabs[index].1.component;
+        self.prefabs[index].1 = prefab;
+        self.apply_prefab(file_name, rebuild_components);
+        true
+    }
+
+    // The prefab an instance was spawned from, if it's loaded
+    fn linked_prefab(&self, body: usize) -> Option<prefab::Prefab> {
+        let (m, i) = self.locate_instance(body)?;
+        let link = self.model_instances[m].instances[i].prefab.as_ref()?;
+        self.find_prefab(&link.prefab).map(|index| self.prefabs[index].1.clone())
+    }
+
+    // Prefab instances keep their own scale when the scene is reloaded or the
+    // prefab changes. Going back to the prefab's scale follows it again.
+    fn override_prefab_scale(&mut self, body: usize) {
+        let prefab = match self.linked_prefab(body) {
+            Some(prefab) => prefab,
+            None => return,
+        };
+        if let Some(instance) = self.instance_mut(body) {
+            let scale = instance.scale;
+            if let Some(link) = &mut instance.prefab {
+                link.overrides.scale = Some(scale).filter(|scale| *scale != prefab.scale);
+            }
+        }
+    }
+
+    // Same as above for the component's settings. They're compared with a
+    // component built from the prefab, which lists every field.
+    fn override_prefab_component(&mut self, body: usize) {
+        let prefab = match self.linked_prefab(body) {
+            Some(prefab) => prefab,
+            None => return,
+        };
+        let from_prefab = prefab.component.as_ref().and_then(|c| self.registry.build(c)).map(|mut c| component::save(c.as_mut()));
+        let data = self.component_data(body);
+        if let Some(link) = self.instance_mut(body).and_then(|i| i.prefab.as_mut()) {
+            link.overrides.component = data.filter(|data| Some(data) != from_prefab.as_ref());
+        }
+    }
+
+    fn component_data(&mut self, body: usize) -> Option<component::ComponentData> {
+        let component = self.instance_mut(body)?.component.as_mut()?;
+        Some(component::save(component.as_mut()))
+    }
+
+    fn shadows(&mut self, body: usize) -> Option<history::Shadows> {
+        let instance = self.instance_mut(body)?;
+        Some(history::Shadows { cast: instance.cast_shadows, receive: instance.receive_shadows })
+    }
+
+    fn set_shadows(&mut self, body: usize, shadows: history::Shadows) -> bool {
+        match self.instance_mut(body) {
+            Some(instance) => {
+                instance.cast_shadows = shadows.cast;
+                instance.receive_shadows = shadows.receive;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Sets a component's parameters without rebuilding it
+    fn set_component_data(&mut self, body: usize, data: &component::ComponentData) -> bool {
+        match self.instance_mut(body).and_then(|i| i.component.as_mut()) {
+            Some(component) if component.name() == data.name => {
+                component::load(component.as_mut(), data);
+                self.override_prefab_component(body);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Takes a model out of the list, only if nothing is using it
+    fn unload_model(&mut self, file_name: &str) -> Option<ModelInstances> {
+        let index = self.find_model(file_name)?;
+        if !self.model_instances[index].instances.is_empty() {
+            log::warn!("Model {} still has instances", file_name);
+            return None;
+        }
+        Some(self.model_instances.remove(index))
+    }
+
+    // Name of the component driving a body, if it has one
+    fn component_name(&self, body: usize) -> Option<&'static str> {
+        self.model_instances
+            .iter()
+            .flat_map(|m| m.instances.iter())
+            .find(|i| i.rigid_body == body)
+            .and_then(|i| i.component.as_ref())
+            .map(|c| c.name())
+    }
+
+    // Swaps the current scene out. Any models it needs that aren't loaded yet get
+    // requested, the instances are spawned once they've all arrived.
+    fn load_scene(&mut self, scene: scene::Scene){
+        self.clear_world();
+
+        self.data.clear_color = scene.clear_color;
+        self.lights = scene.lights.clone();
+        self.ambient = scene.ambient;
+        self.camera = scene.camera.clone();
+        self.camera.aspect = self.config.width as f32 / self.config.height as f32;
+
+        for file_name in scene.models.iter() {
+            self.request_model(file_name.clone());
+        }
+        for file_name in scene.prefabs.iter() {
+            if self.find_prefab(file_name).is_none() {
+                self.request_prefab(file_name.clone());
+            }
+        }
+        self.pending_scene = Some(scene);
+    }
+
+    fn spawn_pending_scene(&mut self){
+        let ready = match &self.pending_scene {
+            Some(scene) => scene.models.iter().all(|m| self.find_model(m).is_some())
+                && scene.prefabs.iter().all(|p| self.find_prefab(p).is_some()),
+            None => false,
+        };
+        if !ready {
+            return;
+        }
+        let scene = self.pending_scene.take().unwrap();
+        for instance in scene.instances {
+            let index = match self.find_model(&instance.model) {
+                Some(index) => index,
+                None => {
+                    log::warn!("Scene instance uses {} which isn't in its model list", instance.model);
+                    continue;
+                }
+            };
+            let component = instance.component.as_ref().and_then(|c| self.registry.build(c));
+            self.model_instances[index].push_instance(&self.device, &mut self.rigidbodys, instance.rigid_body, instance.scale, component, instance.prefab);
+            if let Some(spawned) = self.model_instances[index].instances.last_mut() {
+                spawned.cast_shadows = instance.cast_shadows;
+                spawned.receive_shadows = instance.receive_shadows;
+            }
+            self.publish_spawned(index);
+        }
+        // Pick up any changes made to the prefabs since the scene was saved. The
+        // components were saved with the scene, so they're kept.
+        for file_name in scene.prefabs.iter() {
+            self.apply_prefab(file_name, false);
+        }
+    }
+    
+
+
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+
+        self.setup_gui();
+        let output = self.surface.get_current_texture()?;
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        self.shadows.render(&mut encoder, &self.model_instances);
+
+        // SSAO needs the scene's depth before the main pass, so the opaque meshes
+        // are drawn into the depth buffer first and the main pass keeps it
+        let depth_prepass = self.ssao.is_active();
+        if depth_prepass {
+            let mut depth_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            depth_pass.set_pipeline(&self.depth_pipeline);
+            depth_pass.set_bind_group(2, &self.light_bind_group, &[]);
+            depth_pass.set_bind_group(3, self.ssao.bind_group(), &[]);
+            for model_instance in self.model_instances.iter() {
+                depth_pass.set_vertex_buffer(1, model_instance.instance_buffer.slice(..));
+                depth_pass.draw_model_instanced_blended(
+                    &model_instance.model,
+                    0..model_instance.instances.len() as u32,
+                    &self.camera_bind_group,
+                    false,
+                );
+            }
+        }
+        self.ssao.update(&self.queue, &self.camera, &self.config);
+        self.ssao.render(&mut encoder);
+
+        // The scene goes into the HDR target, through the multisampled one and
+        // resolved with MSAA on. Tonemapping writes to the frame, or to FXAA's
+        // texture first with that on.
+        let (color_view, resolve_target) = match &self.msaa_target {
+            Some(target) => (&target.view, Some(self.hdr.view())),
+            None => (self.hdr.view(), None),
+        };
 
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: color_view,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(self.clear_color),
-                        store: wgpu::StoreOp::Store,
+                        // The samples aren't needed once they're resolved
+                        store: if resolve_target.is_some() { wgpu::StoreOp::Discard } else { wgpu::StoreOp::Store },
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: if depth_prepass { wgpu::LoadOp::Load } else { wgpu::LoadOp::Clear(1.0) },
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -682,20 +1955,54 @@ impl State {
             // render()
 
             render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(2, &self.light_bind_group, &[]);
+            render_pass.set_bind_group(3, self.ssao.bind_group(), &[]);
 
 
             for model_instance in self.model_instances.iter() {
                 render_pass.set_vertex_buffer(1, model_instance.instance_buffer.slice(..));
-                render_pass.draw_model_instanced(
+                render_pass.draw_model_instanced_blended(
                     &model_instance.model,
                     0..model_instance.instances.len() as u32,
                     &self.camera_bind_group,
+                    false,
+                );
+            }
+
+            // Blended meshes go last so what's behind them is already drawn. They
+            // aren't sorted between themselves, so overlapping ones can come out wrong.
+            render_pass.set_pipeline(&self.blend_pipeline);
+            for model_instance in self.model_instances.iter() {
+                render_pass.set_vertex_buffer(1, model_instance.instance_buffer.slice(..));
+                render_pass.draw_model_instanced_blended(
+                    &model_instance.model,
+                    0..model_instance.instances.len() as u32,
+                    &self.camera_bind_group,
+                    true,
                 );
             }
 
 
             
         }
+        self.hdr.update(&self.queue);
+        self.post.update(&self.device, &self.queue, &self.config);
+        // Tonemapping, then any post passes, then FXAA, each into the next one's
+        // input and the last into the frame
+        let ldr_view = match &self.fxaa {
+            Some(fxaa) => fxaa.view(),
+            None => &view,
+        };
+        if self.post.is_active() {
+            self.hdr.render(&mut encoder, self.post.view());
+            self.post.render(&mut encoder, ldr_view);
+        } else {
+            self.hdr.render(&mut encoder, ldr_view);
+        }
+        if let Some(fxaa) = &self.fxaa {
+            fxaa.render(&mut encoder, &view);
+        }
+        // egui is drawn straight onto the frame, after tonemapping, so it keeps its colours
         self.gui.render(&mut encoder, &output, &self.window, &self.device, &self.queue);
 
         self.queue.submit(iter::once(encoder.finish()));
@@ -705,18 +2012,24 @@ impl State {
     }
 
     fn add_instance(&mut self, index : usize){
-        let component = match self.data.component_selected {
-            ComponentSelection::None => None,
-            ComponentSelection::Paddle => Some(Box::new(components::paddle::Paddle::new(
-                vec![self.data.input1,self.data.input2,self.data.input3,self.data.input4]
-            )) as Box<dyn Component>),
-            ComponentSelection::Ball => Some(Box::new(components::ball::Ball::new()) as Box<dyn Component>),
-        };
-        self.model_instances[index].add_instance(&self.device, &mut self.rigidbodys,self.data.position,self.data.euler,component);
+        let component = self.data.component.as_ref().and_then(|c| self.registry.build(c));
+        self.model_instances[index].add_instance(&self.device, &mut self.rigidbodys,self.data.position,self.data.euler,self.data.scale,component);
+        self.publish_spawned(index);
+        self.record_spawn(index);
+    }
+
+    // Spawning from the editor goes on the undo stack
+    fn record_spawn(&mut self, index : usize){
+        let model = self.model_instances[index].model.name.clone();
+        self.history.push(history::Command::Spawn { model, body: self.rigidbodys.len() - 1, removed: None });
     }
 
     fn setup_gui(&mut self){
         self.gui.begin_new_frame(self.start_time.elapsed().as_secs_f64());
+        self.pong_ui();
+        if !self.show_editor {
+            return;
+        }
 
         let platform = self.gui.platform_mut();
         let avg_frame_time = self.frame_times.iter().sum::<u128>() / (self.frame_times.len() + 1) as u128;
@@ -728,6 +2041,55 @@ impl State {
                 avg_frame_time,
             )));
             ui.color_edit_button_rgba_premultiplied(&mut self.data.clear_color);
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.paused, "Paused");
+                ui.add(egui::DragValue::new(&mut self.time_scale).prefix("Time scale: ").speed(0.01).clamp_range(0.0..=10.0));
+            });
+            let mut anti_aliasing = self.anti_aliasing;
+            egui::ComboBox::from_label("Anti-aliasing")
+                .selected_text(anti_aliasing.label())
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut anti_aliasing, antialias::AntiAliasing::Off, "Off");
+                    for count in self.sample_counts.iter().copied().filter(|c| *c > 1) {
+                        let option = antialias::AntiAliasing::Msaa(count);
+                        ui.selectable_value(&mut anti_aliasing, option, option.label());
+                    }
+                    ui.selectable_value(&mut anti_aliasing, antialias::AntiAliasing::Fxaa, "FXAA");
+                });
+            if anti_aliasing != self.anti_aliasing {
+                self.set_anti_aliasing(anti_aliasing);
+            }
+            ui.collapsing("HDR", |ui| {
+                let settings = &mut self.hdr.settings;
+                egui::ComboBox::from_label("Tonemapping")
+                    .selected_text(format!("{:?}", settings.tonemapper))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut settings.tonemapper, hdr::Tonemapper::Aces, "ACES");
+                        ui.selectable_value(&mut settings.tonemapper, hdr::Tonemapper::Reinhard, "Reinhard");
+                        ui.selectable_value(&mut settings.tonemapper, hdr::Tonemapper::Clamp, "Clamp");
+                    });
+                ui.add(egui::DragValue::new(&mut settings.exposure).prefix("Exposure: ").suffix(" EV").speed(0.05).clamp_range(-8.0..=8.0));
+                ui.checkbox(&mut settings.bloom, "Bloom");
+                ui.add_enabled_ui(settings.bloom, |ui| {
+                    ui.add(egui::DragValue::new(&mut settings.bloom_threshold).prefix("Threshold: ").speed(0.05).clamp_range(0.0..=20.0));
+                    ui.add(egui::DragValue::new(&mut settings.bloom_knee).prefix("Knee: ").speed(0.05).clamp_range(0.0..=10.0));
+                    ui.add(egui::DragValue::new(&mut settings.bloom_intensity).prefix("Intensity: ").speed(0.01).clamp_range(0.0..=5.0));
+                    ui.add(egui::DragValue::new(&mut settings.bloom_radius).prefix("Radius: ").speed(0.05).clamp_range(0.0..=4.0));
+                });
+            });
+            ui.collapsing("SSAO", |ui| {
+                if !self.ssao.can_run() {
+                    ui.add(egui::Label::new("Needs the depth buffer, turn MSAA off"));
+                }
+                let settings = &mut self.ssao.settings;
+                ui.checkbox(&mut settings.enabled, "Ambient occlusion");
+                ui.add_enabled_ui(settings.enabled, |ui| {
+                    ui.add(egui::DragValue::new(&mut settings.radius).prefix("Radius: ").speed(0.01).clamp_range(0.05..=5.0));
+                    ui.add(egui::DragValue::new(&mut settings.intensity).prefix("Intensity: ").speed(0.01).clamp_range(0.0..=4.0));
+                    ui.add(egui::DragValue::new(&mut settings.bias).prefix("Bias: ").speed(0.001).clamp_range(0.0..=0.5));
+                    ui.add(egui::DragValue::new(&mut settings.samples).prefix("Samples: ").clamp_range(4..=64));
+                });
+            });
             ui.add(egui::Label::new("Create!"));
             ui.add(egui::DragValue::new(
                 &mut self.data.position[0],
@@ -748,76 +2110,467 @@ impl State {
             ui.add(egui::DragValue::new(
                 &mut self.data.euler[2],
             ).prefix("z: ").clamp_range(0.0..=360.0));
-            ui.add(egui::Label::new("Controls"));
-            input_combo!(ui,"Input 1", self.data.input1,
-                VirtualKeyCode::I => "I",
-                VirtualKeyCode::K => "K",
-                VirtualKeyCode::J => "J",
-                VirtualKeyCode::L => "L",
-                VirtualKeyCode::T => "T",
-                VirtualKeyCode::G => "G",
-                VirtualKeyCode::F => "F",
-                VirtualKeyCode::H => "H"
-            );
-            input_combo!(ui,"Input 2", self.data.input2,
-                VirtualKeyCode::I => "I",
-                VirtualKeyCode::K => "K",
-                VirtualKeyCode::J => "J",
-                VirtualKeyCode::L => "L",
-                VirtualKeyCode::T => "T",
-                VirtualKeyCode::G => "G",
-                VirtualKeyCode::F => "F",
-                VirtualKeyCode::H => "H"
-            );
-            input_combo!(ui,"Input 3", self.data.input3,
-                VirtualKeyCode::I => "I",
-                VirtualKeyCode::K => "K",
-                VirtualKeyCode::J => "J",
-                VirtualKeyCode::L => "L",
-                VirtualKeyCode::T => "T",
-                VirtualKeyCode::G => "G",
-                VirtualKeyCode::F => "F",
-                VirtualKeyCode::H => "H"
-            );
-            input_combo!(ui,"Input 4", self.data.input4,
-                VirtualKeyCode::I => "I",
-                VirtualKeyCode::K => "K",
-                VirtualKeyCode::J => "J",
-                VirtualKeyCode::L => "L",
-                VirtualKeyCode::T => "T",
-                VirtualKeyCode::G => "G",
-                VirtualKeyCode::F => "F",
-                VirtualKeyCode::H => "H"
-            );
-            
+            ui.add(egui::Label::new("Scale!"));
+            ui.add(egui::DragValue::new(
+                &mut self.data.scale[0],
+            ).prefix("x: ").speed(0.05).clamp_range(0.01..=100.0));
+            ui.add(egui::DragValue::new(
+                &mut self.data.scale[1],
+            ).prefix("y: ").speed(0.05).clamp_range(0.01..=100.0));
+            ui.add(egui::DragValue::new(
+                &mut self.data.scale[2],
+            ).prefix("z: ").speed(0.05).clamp_range(0.01..=100.0));
             ui.add(egui::Label::new("Properties!"));
-            egui::ComboBox::from_label("Component!")
-                .selected_text(format!("{:?}", self.data.component_selected))
+            inspector::component_ui(ui, "spawn", &self.registry, &mut self.data.component);
+            let model_name = self.model_instances.get(self.data.model_selected).map(|m| m.model.name.clone()).unwrap_or_default();
+            egui::ComboBox::from_label("Model!")
+                .selected_text(model_name)
                 .show_ui(ui, |ui| {
-                    ui.selectable_value(&mut self.data.component_selected, ComponentSelection::None, "None");
-                    ui.selectable_value(&mut self.data.component_selected, ComponentSelection::Paddle, "Paddle");
-                    ui.selectable_value(&mut self.data.component_selected, ComponentSelection::Ball, "Ball");
+                    for (i, model_instance) in self.model_instances.iter().enumerate() {
+                        ui.selectable_value(&mut self.data.model_selected, i, model_instance.model.name.clone());
+                    }
                 }
             );
-            egui::ComboBox::from_label("Model!")
-                .selected_text(format!("{:?}", self.data.model_selected))
+            if ui.add(egui::Button::new("Spawn Object!")).clicked() && self.data.model_selected < self.model_instances.len(){
+                self.add_instance(self.data.model_selected);
+            }
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.data.model_path).hint_text("model.obj"));
+                if ui.add(egui::Button::new("Load Model")).clicked() && !self.data.model_path.is_empty(){
+                    let file_name = self.data.model_path.clone();
+                    self.history.request_model(&file_name);
+                    self.request_model(file_name);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut self.data.import_settings.smoothing_angle).prefix("Smoothing: ").suffix("°").clamp_range(0.0..=180.0));
+                egui::ComboBox::from_label("UVs if missing")
+                    .selected_text(format!("{:?}", self.data.import_settings.uv_projection))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.data.import_settings.uv_projection, resources::UvProjection::None, "None");
+                        ui.selectable_value(&mut self.data.import_settings.uv_projection, resources::UvProjection::Box, "Box");
+                    });
+            });
+            ui.add(egui::Label::new("Prefabs"));
+            let prefab_name = self.prefabs.get(self.data.prefab_selected).map(|(_, p)| p.name.clone()).unwrap_or_default();
+            egui::ComboBox::from_label("Prefab!")
+                .selected_text(prefab_name)
                 .show_ui(ui, |ui| {
-                    ui.selectable_value(&mut self.data.model_selected, Models::French_Bulldog, "French Bulldog");
-                    ui.selectable_value(&mut self.data.model_selected, Models::Cube, "Cube");
-                    ui.selectable_value(&mut self.data.model_selected, Models::Wall, "Wall");
+                    for (i, (_, prefab)) in self.prefabs.iter().enumerate() {
+                        ui.selectable_value(&mut self.data.prefab_selected, i, prefab.name.clone());
+                    }
                 }
             );
-            if ui.add(egui::Button::new("Spawn Object!")).clicked(){
-                self.add_instance(self.data.model_selected as usize);
+            ui.checkbox(&mut self.data.override_scale, "Override scale");
+            if ui.add(egui::Button::new("Spawn Prefab!")).clicked(){
+                if let Some((file_name, _)) = self.prefabs.get(self.data.prefab_selected) {
+                    let file_name = file_name.clone();
+                    let overrides = prefab::PrefabOverrides {
+                        scale: if self.data.override_scale { Some(self.data.scale.into()) } else { None },
+                        ..Default::default()
+                    };
+                    let model = self.prefabs[self.data.prefab_selected].1.model.clone();
+                    if self.spawn_prefab(&file_name, self.data.position, self.data.euler, overrides) {
+                        if let Some(index) = self.find_model(&model) {
+                            self.record_spawn(index);
+                        }
+                    }
+                }
+            }
+            ui.add(egui::Label::new("Scene"));
+            ui.add(egui::TextEdit::singleline(&mut self.data.scene_path));
+            ui.horizontal(|ui| {
+                if ui.add(egui::Button::new("Save Scene")).clicked(){
+                    self.save_scene();
+                }
+                if ui.add(egui::Button::new("Load Scene")).clicked(){
+                    self.open_scene();
+                }
+            });
+            if ui.add(egui::Button::new("New Pong match")).clicked(){
+                pong::new_match(self);
             }
 
         });
-        self.clear_color = wgpu::Color {
-            r: self.data.clear_color[0] as f64,
-            g: self.data.clear_color[1] as f64,
-            b: self.data.clear_color[2] as f64,
-            a: self.data.clear_color[3] as f64,
+        if let Some((file_name, prefab)) = self.prefabs.get(self.data.prefab_selected) {
+            let file_name = file_name.clone();
+            let mut edited = prefab.clone();
+            let bounds = self.find_model(&prefab.model).map(|index| self.model_instances[index].model.bounds);
+            egui::Window::new("Prefab Editor")
+            .resizable(true)
+            .show(&self.gui.platform_mut().context(), |ui| {
+                ui.add(egui::Label::new(format!("{} ({})", edited.name, file_name)));
+                ui.add(egui::Label::new(format!("Model: {}", edited.model)));
+                ui.add(egui::Label::new("Scale"));
+                ui.add(egui::DragValue::new(&mut edited.scale.x).prefix("x: ").speed(0.05).clamp_range(0.01..=100.0));
+                ui.add(egui::DragValue::new(&mut edited.scale.y).prefix("y: ").speed(0.05).clamp_range(0.01..=100.0));
+                ui.add(egui::DragValue::new(&mut edited.scale.z).prefix("z: ").speed(0.05).clamp_range(0.01..=100.0));
+                ui.add(egui::DragValue::new(&mut edited.mass).prefix("Mass: ").speed(0.05).clamp_range(0.01..=1000.0));
+                ui.add(egui::Label::new("Collider"));
+                let mut fitted = edited.collider.is_none();
+                ui.checkbox(&mut fitted, "Fit to the model");
+                if fitted {
+                    edited.collider = None;
+                } else {
+                    let size = bounds.map_or(Vector3::new(1.0, 1.0, 1.0), |bounds| bounds.size());
+                    shape_ui(ui, edited.collider.get_or_insert(physics::Shape::Box(size)));
+                }
+                inspector::component_ui(ui, "prefab", &self.registry, &mut edited.component);
+                if ui.add(egui::Button::new("Save Prefab")).clicked() {
+                    if let Err(e) = prefab::save(&edited, &file_name) {
+                        log::warn!("Couldn't save {}: {}", file_name, e);
+                    }
+                }
+            });
+            let before = &self.prefabs[self.data.prefab_selected].1;
+            if edited != *before {
+                history::begin_edit(self, history::PendingEdit::Prefab { file_name: file_name.clone(), before: before.clone() });
+                self.set_prefab(&file_name, edited);
+            }
+        }
+
+        self.lights_ui();
+        self.materials_ui();
+        self.post_ui();
+
+        let mut edits = vec![];
+        egui::Window::new("Objects")
+        .resizable(true)
+        .show(&self.gui.platform_mut().context(), |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for model_instance in self.model_instances.iter_mut() {
+                    for instance in model_instance.instances.iter_mut() {
+                        let body = instance.rigid_body;
+                        let title = format!("{} #{}", model_instance.model.name, body);
+                        egui::CollapsingHeader::new(title).show(ui, |ui| {
+                            ui.checkbox(&mut instance.selected, "Selected");
+                            edits.push((body, instance_ui(ui, &format!("object {}", body), instance, &self.rigidbodys[body])));
+                        });
+                    }
+                }
+            });
+        });
+        // Everything picked in the viewport, ready to edit
+        if self.model_instances.iter().any(|m| m.instances.iter().any(|i| i.selected)) {
+            egui::Window::new("Inspector")
+            .resizable(true)
+            .show(&self.gui.platform_mut().context(), |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.gizmo.mode, gizmo::GizmoMode::Translate, "Move");
+                    ui.selectable_value(&mut self.gizmo.mode, gizmo::GizmoMode::Rotate, "Rotate");
+                    ui.selectable_value(&mut self.gizmo.mode, gizmo::GizmoMode::Scale, "Scale");
+                    ui.separator();
+                    ui.selectable_value(&mut self.gizmo.space, gizmo::GizmoSpace::World, "World");
+                    ui.selectable_value(&mut self.gizmo.space, gizmo::GizmoSpace::Local, "Local");
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.gizmo.snap, "Snap");
+                    ui.add(egui::DragValue::new(&mut self.gizmo.grid).prefix("Grid: ").speed(0.05).clamp_range(0.01..=100.0));
+                    ui.add(egui::DragValue::new(&mut self.gizmo.angle).prefix("Angle: ").suffix("°").clamp_range(1.0..=180.0));
+                    ui.add(egui::DragValue::new(&mut self.gizmo.scale_step).prefix("Scale: ").speed(0.01).clamp_range(0.01..=10.0));
+                });
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for model_instance in self.model_instances.iter_mut() {
+                        for instance in model_instance.instances.iter_mut().filter(|i| i.selected) {
+                            let body = instance.rigid_body;
+                            ui.add(egui::Label::new(egui::RichText::new(format!("{} #{}", model_instance.model.name, body)).strong()));
+                            edits.push((body, instance_ui(ui, &format!("inspector {}", body), instance, &self.rigidbodys[body])));
+                            ui.separator();
+                        }
+                    }
+                });
+            });
+        }
+        // The gizmo works on the first selected object
+        let selected = self.model_instances.iter().flat_map(|m| m.instances.iter()).find(|i| i.selected).map(|i| i.rigid_body);
+        match selected.and_then(|body| self.transform(body).map(|transform| (body, transform))) {
+            Some((body, before)) => {
+                let context = self.gui.platform_mut().context();
+                if let Some(after) = self.gizmo.show(&context, &self.camera, before) {
+                    edits.push((body, InstanceEdit { transform: Some((before, after)), component: None, shadows: None, delete: false }));
+                }
+            }
+            None => self.gizmo.release(),
+        }
+        // Component fields were edited in place, transforms still need applying
+        let mut delete = vec![];
+        for (body, edit) in edits {
+            if let Some((before, after)) = edit.transform {
+                history::begin_edit(self, history::PendingEdit::Transform { body, before });
+                self.set_transform(body, after);
+            }
+            if let Some(before) = edit.component {
+                history::begin_edit(self, history::PendingEdit::Component { body, before });
+                self.override_prefab_component(body);
+            }
+            if let Some(shadows) = edit.shadows {
+                history::set_shadows(self, body, shadows);
+            }
+            if edit.delete {
+                delete.push(body);
+            }
+        }
+        // A drag or a bit of typing is one command, finished once it's let go
+        let context = self.gui.platform_mut().context();
+        if !context.input(|i| i.pointer.any_down()) && context.memory(|m| m.focus().is_none()) {
+            history::finish_edit(self);
+        }
+        // Highest first so the other indices don't move
+        delete.sort_unstable();
+        delete.dedup();
+        for body in delete.into_iter().rev() {
+            history::delete(self, body);
+        }
+
+        let mut undo = false;
+        let mut redo = false;
+        egui::Window::new("History")
+        .resizable(true)
+        .show(&self.gui.platform_mut().context(), |ui| {
+            ui.horizontal(|ui| {
+                undo = ui.add_enabled(!self.history.done().is_empty(), egui::Button::new("Undo")).clicked();
+                redo = ui.add_enabled(!self.history.undone().is_empty(), egui::Button::new("Redo")).clicked();
+            });
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for command in self.history.done() {
+                    ui.add(egui::Label::new(command.describe()));
+                }
+                // Undone commands are greyed out, next to be redone first
+                for command in self.history.undone().iter().rev() {
+                    ui.add(egui::Label::new(egui::RichText::new(command.describe()).weak()));
+                }
+            });
+        });
+        if undo {
+            history::undo(self);
+        }
+        if redo {
+            history::redo(self);
+        }
+    }
+
+    fn lights_ui(&mut self){
+        let target = cgmath::Vector3::new(self.camera.target.x, self.camera.target.y, self.camera.target.z);
+        egui::Window::new("Lights")
+        .resizable(true)
+        .default_open(false)
+        .show(&self.gui.platform_mut().context(), |ui| {
+            ui.horizontal(|ui| {
+                ui.add(egui::Label::new("Ambient"));
+                ui.color_edit_button_rgb(&mut self.ambient);
+            });
+            ui.checkbox(&mut self.shadows.pcf, "Soft shadows (PCF)");
+            // New lights go above whatever the camera is looking at
+            let above = target + cgmath::Vector3::new(0.0, 3.0, 0.0);
+            let down = cgmath::Vector3::new(0.0, -1.0, 0.0);
+            ui.add_enabled_ui(self.lights.len() < light::MAX_LIGHTS, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new("Add Directional")).clicked(){
+                        self.lights.push(light::Light::directional(down, [1.0, 1.0, 1.0], 1.0));
+                    }
+                    if ui.add(egui::Button::new("Add Point")).clicked(){
+                        self.lights.push(light::Light::point(above, [1.0, 1.0, 1.0], 10.0, 10.0));
+                    }
+                    if ui.add(egui::Button::new("Add Spot")).clicked(){
+                        self.lights.push(light::Light::spot(above, down, [1.0, 1.0, 1.0], 10.0, 10.0));
+                    }
+                });
+            });
+            let mut remove = None;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (i, light) in self.lights.iter_mut().enumerate() {
+                    egui::CollapsingHeader::new(format!("{:?} light {}", light.kind, i)).show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(&mut light.kind, light::LightKind::Directional, "Directional");
+                            ui.selectable_value(&mut light.kind, light::LightKind::Point, "Point");
+                            ui.selectable_value(&mut light.kind, light::LightKind::Spot, "Spot");
+                        });
+                        ui.horizontal(|ui| {
+                            ui.color_edit_button_rgb(&mut light.color);
+                            ui.add(egui::DragValue::new(&mut light.intensity).prefix("Intensity: ").speed(0.05).clamp_range(0.0..=1000.0));
+                        });
+                        if light.kind != light::LightKind::Directional {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::Label::new("Position"));
+                                ui.add(egui::DragValue::new(&mut light.position.x).prefix("x: ").speed(0.05));
+                                ui.add(egui::DragValue::new(&mut light.position.y).prefix("y: ").speed(0.05));
+                                ui.add(egui::DragValue::new(&mut light.position.z).prefix("z: ").speed(0.05));
+                            });
+                            ui.add(egui::DragValue::new(&mut light.range).prefix("Range: ").speed(0.05).clamp_range(0.01..=1000.0));
+                        }
+                        if light.kind != light::LightKind::Point {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::Label::new("Direction"));
+                                ui.add(egui::DragValue::new(&mut light.direction.x).prefix("x: ").speed(0.01));
+                                ui.add(egui::DragValue::new(&mut light.direction.y).prefix("y: ").speed(0.01));
+                                ui.add(egui::DragValue::new(&mut light.direction.z).prefix("z: ").speed(0.01));
+                            });
+                        }
+                        if light.kind == light::LightKind::Spot {
+                            ui.add(egui::DragValue::new(&mut light.inner_angle).prefix("Inner angle: ").suffix("°").clamp_range(0.0..=89.0));
+                            ui.add(egui::DragValue::new(&mut light.outer_angle).prefix("Outer angle: ").suffix("°").clamp_range(0.0..=89.0));
+                        }
+                        if light.kind != light::LightKind::Point {
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut light.cast_shadows, "Cast shadows");
+                                if light.kind == light::LightKind::Directional {
+                                    ui.add_enabled(light.cast_shadows, egui::Checkbox::new(&mut light.cascaded, "Cascaded"));
+                                }
+                            });
+                        }
+                        if ui.add(egui::Button::new("Delete")).clicked(){
+                            remove = Some(i);
+                        }
+                    });
+                }
+            });
+            if let Some(i) = remove {
+                self.lights.remove(i);
+            }
+            if self.lights.len() > light::MAX_LIGHTS {
+                ui.add(egui::Label::new(format!("Only the first {} lights are drawn", light::MAX_LIGHTS)));
+            }
+        });
+    }
+
+    // Factors of every loaded model's materials, shared by all of its instances
+    fn materials_ui(&mut self){
+        let queue = self.queue.clone();
+        egui::Window::new("Materials")
+        .resizable(true)
+        .default_open(false)
+        .show(&self.gui.platform_mut().context(), |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for model_instance in self.model_instances.iter_mut() {
+                    for material in model_instance.model.materials.iter_mut() {
+                        let title = format!("{}: {}", model_instance.model.name, material.name);
+                        egui::CollapsingHeader::new(title).show(ui, |ui| {
+                            let factors = &mut material.factors;
+                            let mut changed = false;
+                            ui.horizontal(|ui| {
+                                ui.add(egui::Label::new("Base colour"));
+                                changed |= ui.color_edit_button_rgba_unmultiplied(&mut factors.base_color).changed();
+                            });
+                            changed |= ui.add(egui::Slider::new(&mut factors.metallic, 0.0..=1.0).text("Metallic")).changed();
+                            changed |= ui.add(egui::Slider::new(&mut factors.roughness, 0.0..=1.0).text("Roughness")).changed();
+                            changed |= ui.add(egui::Slider::new(&mut factors.specular, 0.0..=1.0).text("Specular")).changed();
+                            ui.horizontal(|ui| {
+                                ui.add(egui::Label::new("Emissive"));
+                                changed |= ui.color_edit_button_rgb(&mut factors.emissive).changed();
+                            });
+                            changed |= ui.add(egui::Slider::new(&mut factors.occlusion_strength, 0.0..=1.0).text("Occlusion")).changed();
+                            // Meshes pick their pipeline every frame, so this takes effect straight away
+                            ui.horizontal(|ui| {
+                                changed |= ui.selectable_value(&mut factors.alpha_mode, model::AlphaMode::Opaque, "Opaque").changed();
+                                changed |= ui.selectable_value(&mut factors.alpha_mode, model::AlphaMode::Mask, "Mask").changed();
+                                changed |= ui.selectable_value(&mut factors.alpha_mode, model::AlphaMode::Blend, "Blend").changed();
+                            });
+                            if factors.alpha_mode == model::AlphaMode::Mask {
+                                changed |= ui.add(egui::Slider::new(&mut factors.alpha_cutoff, 0.0..=1.0).text("Cutoff")).changed();
+                            }
+                            if changed {
+                                material.write_factors(&queue);
+                            }
+                        });
+                    }
+                }
+            });
+        });
+    }
+
+    // The post processing passes in the order they run
+    fn post_ui(&mut self){
+        let mut move_up = None;
+        let mut load_lut = None;
+        let mut use_sliders = false;
+        egui::Window::new("Post Processing")
+        .resizable(true)
+        .default_open(false)
+        .show(&self.gui.platform_mut().context(), |ui| {
+            let count = self.post.passes.len();
+            for (i, pass) in self.post.passes.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut pass.enabled, pass.effect.name());
+                    if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                        move_up = Some(i);
+                    }
+                    if ui.add_enabled(i + 1 < count, egui::Button::new("Down")).clicked() {
+                        move_up = Some(i + 1);
+                    }
+                });
+                if !pass.enabled {
+                    continue;
+                }
+                ui.indent(i, |ui| {
+                    for (p, param) in pass.params.iter_mut().enumerate() {
+                        // With a LUT file loaded only its strength does anything
+                        let enabled = pass.effect != post::PostEffect::ColorGrading || p == 0 || self.post.lut_file.is_none();
+                        ui.add_enabled(enabled, egui::DragValue::new(&mut param.value).prefix(format!("{}: ", param.name)).speed(param.speed).clamp_range(param.range.clone()));
+                    }
+                    if pass.effect == post::PostEffect::ColorGrading {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::TextEdit::singleline(&mut self.data.lut_path).hint_text("lut.png"));
+                            if ui.add(egui::Button::new("Load LUT")).clicked() && !self.data.lut_path.is_empty() {
+                                load_lut = Some(self.data.lut_path.clone());
+                            }
+                        });
+                        if let Some(file_name) = &self.post.lut_file {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::Label::new(format!("Using {}", file_name)));
+                                if ui.add(egui::Button::new("Use sliders")).clicked() {
+                                    use_sliders = true;
+                                }
+                            });
+                        }
+                    }
+                });
+            }
+        });
+        if let Some(i) = move_up {
+            self.post.passes.swap(i - 1, i);
+        }
+        if let Some(file_name) = load_lut {
+            self.request_lut(file_name);
+        }
+        if use_sliders {
+            self.post.use_baked_lut();
+        }
+    }
+
+    // Score board for the Pong match, shown even with the editor hidden
+    fn pong_ui(&mut self){
+        let pong = match &self.pong {
+            Some(pong) => pong,
+            None => return,
+        };
+        let side_name = |side: pong::Side| match side {
+            pong::Side::Bottom => "Bottom",
+            pong::Side::Top => "Top",
         };
+        let mut new_match = false;
+        egui::Window::new("Pong")
+        .resizable(false)
+        .show(&self.gui.platform_mut().context(), |ui| {
+            ui.add(egui::Label::new(egui::RichText::new(format!(
+                "Bottom {} - {} Top",
+                pong.score(pong::Side::Bottom),
+                pong.score(pong::Side::Top),
+            )).heading()));
+            ui.add(egui::Label::new(format!("First to {}, rally: {}", pong.target, pong.rally())));
+            if let Some(side) = pong.match_point() {
+                ui.add(egui::Label::new(format!("Match point {}!", side_name(side))));
+            }
+            if let Some(winner) = pong.winner() {
+                ui.add(egui::Label::new(egui::RichText::new(format!("{} wins!", side_name(winner))).heading()));
+                if ui.add(egui::Button::new("Play again")).clicked(){
+                    new_match = true;
+                }
+            }
+        });
+        if new_match {
+            pong::new_match(self);
+        }
     }
 
     fn get_last_delta(&self) -> u128 {
@@ -826,6 +2579,93 @@ impl State {
 
 }
 
+// What was done to an object through the editor this frame
+struct InstanceEdit {
+    // Before and after
+    transform: Option<(history::Transform, history::Transform)>,
+    // Settings from before the component was edited, the edit itself is already made
+    component: Option<component::ComponentData>,
+    // What the shadow checkboxes were switched to
+    shadows: Option<history::Shadows>,
+    delete: bool,
+}
+
+// Transform, component settings and status of one object
+fn instance_ui(ui: &mut egui::Ui, id: &str, instance: &mut Instance, rigid_body: &RigidBody) -> InstanceEdit {
+    let mut edit = InstanceEdit { transform: None, component: None, shadows: None, delete: false };
+    let before = history::Transform { position: rigid_body.position, rotation: rigid_body.rotation, scale: instance.scale };
+    let mut after = before;
+    if transform_ui(ui, &mut after) {
+        edit.transform = Some((before, after));
+    }
+    let mut shadows = history::Shadows { cast: instance.cast_shadows, receive: instance.receive_shadows };
+    let toggled = ui.horizontal(|ui| {
+        ui.checkbox(&mut shadows.cast, "Cast shadows").changed() | ui.checkbox(&mut shadows.receive, "Receive shadows").changed()
+    }).inner;
+    if toggled {
+        edit.shadows = Some(shadows);
+    }
+    if let Some(prefab) = &instance.prefab {
+        ui.add(egui::Label::new(format!("Prefab: {}", prefab.prefab)));
+    }
+    if let Some(component) = &mut instance.component {
+        ui.add(egui::Label::new(format!("Component: {}", component.name())));
+        let before = component::save(component.as_mut());
+        if inspector::fields_ui(ui, id, component.fields()) {
+            edit.component = Some(before);
+        }
+        for (name, value) in component.status() {
+            ui.add(egui::Label::new(egui::RichText::new(format!("{}: {}", name, value)).monospace()));
+        }
+    }
+    edit.delete = ui.add(egui::Button::new("Delete")).clicked();
+    edit
+}
+
+// Position, rotation in degrees and scale of an object, returns true if any changed
+fn transform_ui(ui: &mut egui::Ui, transform: &mut history::Transform) -> bool {
+    let euler = cgmath::Euler::from(transform.rotation);
+    let mut degrees = [cgmath::Deg::from(euler.x).0, cgmath::Deg::from(euler.y).0, cgmath::Deg::from(euler.z).0];
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.add(egui::Label::new("Position"));
+        changed |= ui.add(egui::DragValue::new(&mut transform.position.x).prefix("x: ").speed(0.05)).changed();
+        changed |= ui.add(egui::DragValue::new(&mut transform.position.y).prefix("y: ").speed(0.05)).changed();
+        changed |= ui.add(egui::DragValue::new(&mut transform.position.z).prefix("z: ").speed(0.05)).changed();
+    });
+    let rotated = ui.horizontal(|ui| {
+        ui.add(egui::Label::new("Rotation"));
+        ui.add(egui::DragValue::new(&mut degrees[0]).prefix("x: ")).changed()
+            | ui.add(egui::DragValue::new(&mut degrees[1]).prefix("y: ")).changed()
+            | ui.add(egui::DragValue::new(&mut degrees[2]).prefix("z: ")).changed()
+    }).inner;
+    // Only rebuild the rotation when it's touched, the round trip through euler angles isn't exact
+    if rotated {
+        transform.rotation = cgmath::Quaternion::from(cgmath::Euler::new(cgmath::Deg(degrees[0]), cgmath::Deg(degrees[1]), cgmath::Deg(degrees[2])));
+        changed = true;
+    }
+    ui.horizontal(|ui| {
+        ui.add(egui::Label::new("Scale"));
+        changed |= ui.add(egui::DragValue::new(&mut transform.scale.x).prefix("x: ").speed(0.05).clamp_range(0.01..=100.0)).changed();
+        changed |= ui.add(egui::DragValue::new(&mut transform.scale.y).prefix("y: ").speed(0.05).clamp_range(0.01..=100.0)).changed();
+        changed |= ui.add(egui::DragValue::new(&mut transform.scale.z).prefix("z: ").speed(0.05).clamp_range(0.01..=100.0)).changed();
+    });
+    changed
+}
+
+fn shape_ui(ui: &mut egui::Ui, shape: &mut physics::Shape) {
+    match shape {
+        physics::Shape::Box(size) => {
+            ui.add(egui::DragValue::new(&mut size.x).prefix("Box x: ").speed(0.05).clamp_range(0.01..=100.0));
+            ui.add(egui::DragValue::new(&mut size.y).prefix("Box y: ").speed(0.05).clamp_range(0.01..=100.0));
+            ui.add(egui::DragValue::new(&mut size.z).prefix("Box z: ").speed(0.05).clamp_range(0.01..=100.0));
+        }
+        physics::Shape::Sphere(radius) => {
+            ui.add(egui::DragValue::new(radius).prefix("Radius: ").speed(0.05).clamp_range(0.01..=100.0));
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! input_combo {
     ($ui:expr, $label:expr, $data:expr, $($key:expr => $value:expr),*) => {
@@ -839,111 +2679,15 @@ macro_rules! input_combo {
     };
 }
 
+// The default editor, with the models and prefabs the Pong scenes use
 #[cfg_attr(target_arch="wasm32", wasm_bindgen(start))]
 pub async fn run() {
-    cfg_if::cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-            console_log::init_with_level(log::Level::Warn).expect("Couldn't initialize logger");
-        } else {
-            env_logger::init();
-        }
-    }
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
-
-    #[cfg(target_arch = "wasm32")]
-    {
-        // Winit prevents sizing with CSS, so we have to set
-        // the size manually when on web.
-        use winit::dpi::PhysicalSize;
-        window.set_inner_size(PhysicalSize::new(750, 750));
-        
-        use winit::platform::web::WindowExtWebSys;
-        web_sys::window()
-            .and_then(|win| win.document())
-            .and_then(|doc| {
-                let dst = doc.get_element_by_id("wasm-example")?;
-                let canvas = web_sys::Element::from(window.canvas());
-                dst.append_child(&canvas).ok()?;
-                Some(())
-            })
-            .expect("Couldn't append canvas to document body.");
-    }
-
-    let mut state = State::new(window).await;
-
-    let obj_model = //Move this into a start function in state maybe
-    resources::load_model("french_bulldog.obj", &state.device, &state.queue, &state.texture_bind_group_layout)
-        .await
-        .unwrap();
-    state.add_model(obj_model);
-    
-    let cube_model = resources::load_model("cube.obj", &state.device, &state.queue, &state.texture_bind_group_layout)
-        .await
-        .unwrap();
-    state.add_model(cube_model);
-
-    let wall_model = resources::load_model("wall2.obj", &state.device, &state.queue, &state.texture_bind_group_layout)
-        .await
-        .unwrap();
-    state.add_model(wall_model);
-
-
-
-
-    event_loop.run(move |event, _, control_flow| {
-        
-        state.gui.handle_event(&event);
-
-        match event {
-            Event::WindowEvent {
-                ref event,
-                window_id,
-            } if window_id == state.window().id() => if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    },
-                    
-                    WindowEvent::ScaleFactorChanged {new_inner_size, ..} => {
-                        state.resize(**new_inner_size)
-                    },
-                    
-
-                    _ => {}
-                }
-            },
-        Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-            state.update();
-            match state.render() {
-                Ok(_) => {},
-
-                Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                Err(e) => eprintln!("{:?}", e),
-            }
-        }
-
-        Event::MainEventsCleared => {
-            state.window().request_redraw();
-        }
-
-
-        
-        _ => {}
-    }});
+    App::new()
+        .preload_model("french_bulldog.obj")
+        .preload_model("cube.obj")
+        .preload_model("wall2.obj")
+        .preload_prefab("paddle.prefab.ron")
+        .preload_prefab("ball.prefab.ron")
+        .run()
+        .await;
 }
-
-
-
@@ -0,0 +1,143 @@
+use cgmath::{InnerSpace, Vector3};
+use serde::{Deserialize, Serialize};
+
+// Most lights the shader looks at, any past this are ignored. It's a fixed size
+// uniform array since WebGL can't read storage buffers in fragment shaders.
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // Unused by directional lights
+    pub position: Vector3<f32>,
+    // Which way the light shines, unused by point lights
+    pub direction: Vector3<f32>,
+    // Point and spot lights fade out to nothing at this distance
+    pub range: f32,
+    // Spot lights are full brightness inside the inner angle and fade out by the
+    // outer one, both in degrees from the direction
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    // Directional and spot lights only, point lights never cast shadows. Lights
+    // saved before shadows existed get them, like new lights do.
+    #[serde(default = "crate::scene::default_true")]
+    pub cast_shadows: bool,
+    // Directional lights split their shadow into cascades, sharper close to the camera
+    #[serde(default = "crate::scene::default_true")]
+    pub cascaded: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vector3<f32>, color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            color,
+            intensity,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            direction,
+            range: 10.0,
+            inner_angle: 20.0,
+            outer_angle: 30.0,
+            cast_shadows: true,
+            cascaded: true,
+        }
+    }
+
+    pub fn point(position: Vector3<f32>, color: [f32; 3], intensity: f32, range: f32) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            range,
+            cast_shadows: false,
+            ..Self::directional(Vector3::new(0.0, -1.0, 0.0), color, intensity)
+        }
+    }
+
+    pub fn spot(position: Vector3<f32>, direction: Vector3<f32>, color: [f32; 3], intensity: f32, range: f32) -> Self {
+        Self {
+            kind: LightKind::Spot,
+            position,
+            range,
+            ..Self::directional(direction, color, intensity)
+        }
+    }
+
+    // Normalised direction, straight down if it's been zeroed in the editor
+    pub fn unit_direction(&self) -> Vector3<f32> {
+        if self.direction.magnitude2() > 0.0 { self.direction.normalize() } else { Vector3::new(0.0, -1.0, 0.0) }
+    }
+
+    fn to_raw(&self, shadow: [f32; 4]) -> LightRaw {
+        let kind = match self.kind {
+            LightKind::Directional => 0.0,
+            LightKind::Point => 1.0,
+            LightKind::Spot => 2.0,
+        };
+        let direction = self.unit_direction();
+        let outer = self.outer_angle.max(self.inner_angle);
+        LightRaw {
+            position: [self.position.x, self.position.y, self.position.z, 1.0],
+            direction: [direction.x, direction.y, direction.z, 0.0],
+            color: [self.color[0] * self.intensity, self.color[1] * self.intensity, self.color[2] * self.intensity, 1.0],
+            params: [kind, self.range.max(0.01), self.inner_angle.to_radians().cos(), outer.to_radians().cos()],
+            shadow,
+        }
+    }
+}
+
+// What a new scene is lit with, a white sun from above and in front
+pub fn default_lights() -> Vec<Light> {
+    vec![Light::directional(Vector3::new(-0.4, -1.0, -0.6), [1.0, 1.0, 1.0], 1.0)]
+}
+
+pub fn default_ambient() -> [f32; 3] {
+    [0.1, 0.1, 0.1]
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 4],
+    direction: [f32; 4],
+    // Already multiplied by the intensity
+    color: [f32; 4],
+    // Kind, range, cosine of the inner angle, cosine of the outer angle
+    params: [f32; 4],
+    // First shadow map layer or -1 for none, how many layers, zw unused
+    shadow: [f32; 4],
+}
+
+// Matches `Lights` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightsUniform {
+    lights: [LightRaw; MAX_LIGHTS],
+    ambient: [f32; 4],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+impl LightsUniform {
+    // `shadows` has each light's shadow map settings, from `ShadowMaps::update`
+    pub fn new(lights: &[Light], ambient: [f32; 3], shadows: &[[f32; 4]]) -> Self {
+        let mut uniform = Self {
+            lights: [LightRaw { position: [0.0; 4], direction: [0.0; 4], color: [0.0; 4], params: [0.0; 4], shadow: [-1.0, 0.0, 0.0, 0.0] }; MAX_LIGHTS],
+            ambient: [ambient[0], ambient[1], ambient[2], 1.0],
+            count: lights.len().min(MAX_LIGHTS) as u32,
+            _padding: [0; 3],
+        };
+        for (i, (raw, light)) in uniform.lights.iter_mut().zip(lights.iter()).enumerate() {
+            *raw = light.to_raw(shadows.get(i).copied().unwrap_or([-1.0, 0.0, 0.0, 0.0]));
+        }
+        uniform
+    }
+}
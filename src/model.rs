@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::ops::Range;
 
+use wgpu::util::DeviceExt;
+
 use crate::texture;
 
 pub trait Vertex {
@@ -12,6 +15,9 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    // Directions the normal map's x and y run in, along u and v
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
 }
 
 impl Vertex for ModelVertex {
@@ -36,17 +42,348 @@ impl Vertex for ModelVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
             ],
         }
     }
 }
 
+// How a material's alpha is used. Mask cuts out anything under the cutoff,
+// blended materials are drawn after everything else without writing depth.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+// The numbers a material is drawn with. Each texture is multiplied by its factor,
+// so a material without a texture is just its factor. Laid out the way glTF does it.
+#[derive(Clone, Debug)]
+pub struct MaterialFactors {
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    // How reflective non-metals are, 0.5 is the usual 4% at head on
+    pub specular: f32,
+    pub emissive: [f32; 3],
+    // 0 ignores the occlusion map, 1 applies it fully
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            emissive: [0.0, 0.0, 0.0],
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+        }
+    }
+}
+
+impl MaterialFactors {
+    fn to_raw(&self) -> MaterialUniform {
+        MaterialUniform {
+            base_color: self.base_color,
+            emissive: [self.emissive[0], self.emissive[1], self.emissive[2], self.specular],
+            params: [self.metallic, self.roughness, self.occlusion_strength, self.alpha_cutoff],
+            alpha_mode: match self.alpha_mode {
+                AlphaMode::Opaque => 0,
+                AlphaMode::Mask => 1,
+                AlphaMode::Blend => 2,
+            },
+            _padding: [0; 3],
+        }
+    }
+}
+
+// Matches `Material` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color: [f32; 4],
+    // Emissive colour, w is the specular factor
+    emissive: [f32; 4],
+    // Metallic, roughness, occlusion strength, alpha cutoff
+    params: [f32; 4],
+    alpha_mode: u32,
+    _padding: [u32; 3],
+}
+
+pub struct MaterialTextures {
+    pub base_color: texture::Texture,
+    pub normal: texture::Texture,
+    // Roughness in green and metallic in blue, like glTF
+    pub metallic_roughness: texture::Texture,
+    pub emissive: texture::Texture,
+    // Only red is read
+    pub occlusion: texture::Texture,
+}
+
+impl MaterialTextures {
+    // Textures that leave the factors as they are, for materials without maps
+    pub fn plain(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self {
+            base_color: texture::Texture::white(device, queue),
+            normal: texture::Texture::flat_normal_map(device, queue),
+            metallic_roughness: texture::Texture::solid(device, queue, [255, 255, 255, 255], "white_texture", texture::TextureKind::Data),
+            emissive: texture::Texture::white(device, queue),
+            occlusion: texture::Texture::solid(device, queue, [255, 255, 255, 255], "white_texture", texture::TextureKind::Data),
+        }
+    }
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
+    pub factors: MaterialFactors,
+    // The bind group holds on to the textures, nothing else needs them
+    pub uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        factors: MaterialFactors,
+        textures: MaterialTextures,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[factors.to_raw()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&textures.base_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&textures.base_color.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&textures.normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&textures.normal.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&textures.metallic_roughness.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(&textures.metallic_roughness.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&textures.emissive.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&textures.emissive.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&textures.occlusion.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(&textures.occlusion.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(name),
+        });
+
+        Self {
+            name: name.to_string(),
+            factors,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    // Sends changed factors to the GPU
+    pub fn write_factors(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.factors.to_raw()]));
+    }
+
+    pub fn is_blended(&self) -> bool {
+        self.factors.alpha_mode == AlphaMode::Blend
+    }
+}
+
+// Fills in each vertex's tangent and bitangent from the triangles around it, so
+// normal maps line up with the texture coordinates
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    use cgmath::Vector3;
+    let mut triangles_included = vec![0; vertices.len()];
+    for c in indices.chunks_exact(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: Vector3<f32> = v0.position.into();
+        let pos1: Vector3<f32> = v1.position.into();
+        let pos2: Vector3<f32> = v2.position.into();
+
+        let uv0: cgmath::Vector2<f32> = v0.tex_coords.into();
+        let uv1: cgmath::Vector2<f32> = v1.tex_coords.into();
+        let uv2: cgmath::Vector2<f32> = v2.tex_coords.into();
+
+        // Edges of the triangle, in space and in texture coordinates
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving
+        //     delta_pos1 = delta_uv1.x * T + delta_uv1.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // for T and B. Triangles with no texture area can't tell us anything.
+        let determinant = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / determinant;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // The texture coordinates were flipped on load, so flip the bitangent to match
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        for i in c {
+            let vertex = &mut vertices[*i as usize];
+            vertex.tangent = (tangent + Vector3::from(vertex.tangent)).into();
+            vertex.bitangent = (bitangent + Vector3::from(vertex.bitangent)).into();
+            triangles_included[*i as usize] += 1;
+        }
+    }
+
+    // Average the tangents of vertices shared between triangles
+    for (i, n) in triangles_included.into_iter().enumerate() {
+        if n == 0 {
+            continue;
+        }
+        let denominator = 1.0 / n as f32;
+        let vertex = &mut vertices[i];
+        vertex.tangent = (Vector3::from(vertex.tangent) * denominator).into();
+        vertex.bitangent = (Vector3::from(vertex.bitangent) * denominator).into();
+    }
+}
+
+// Cross product of each triangle's edges. It's as long as twice the triangle's
+// area, so adding them up weights bigger faces more.
+fn face_normals(vertices: &[ModelVertex], indices: &[u32]) -> Vec<cgmath::Vector3<f32>> {
+    use cgmath::Vector3;
+    indices
+        .chunks_exact(3)
+        .map(|c| {
+            let p0: Vector3<f32> = vertices[c[0] as usize].position.into();
+            let p1: Vector3<f32> = vertices[c[1] as usize].position.into();
+            let p2: Vector3<f32> = vertices[c[2] as usize].position.into();
+            (p1 - p0).cross(p2 - p0)
+        })
+        .collect()
+}
+
+// Gives each corner of each triangle its own copy of its vertex, changed by
+// `corner`, then merges copies that came out the same. Lets a vertex split
+// where its triangles need different normals or texture coordinates.
+fn rebuild_corners(vertices: &mut Vec<ModelVertex>, indices: &mut [u32], mut corner: impl FnMut(usize, &ModelVertex) -> ModelVertex) {
+    let mut merged: HashMap<[u32; 14], u32> = HashMap::new();
+    let mut rebuilt = vec![];
+    for (i, index) in indices.iter_mut().enumerate() {
+        let vertex = corner(i / 3, &vertices[*index as usize]);
+        let key: [u32; 14] = bytemuck::cast(vertex);
+        *index = *merged.entry(key).or_insert_with(|| {
+            rebuilt.push(vertex);
+            (rebuilt.len() - 1) as u32
+        });
+    }
+    *vertices = rebuilt;
+}
+
+// Makes normals for a mesh that came without them. Each corner averages the
+// faces around its position, weighted by area, leaving out any that meet its own
+// face at more than `smoothing_angle` degrees so hard edges stay hard. 0 gives
+// flat shading and 180 smooths everything.
+pub fn generate_normals(vertices: &mut Vec<ModelVertex>, indices: &mut [u32], smoothing_angle: f32) {
+    use cgmath::{InnerSpace, Vector3};
+    let faces = face_normals(vertices, indices);
+    // Found by position rather than index, as corners with different texture
+    // coordinates are separate vertices but should still be smoothed together
+    let mut around: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    for (face, c) in indices.chunks_exact(3).enumerate() {
+        for i in c {
+            let key: [u32; 3] = bytemuck::cast(vertices[*i as usize].position);
+            around.entry(key).or_default().push(face);
+        }
+    }
+    let threshold = smoothing_angle.clamp(0.0, 180.0).to_radians().cos() - 1e-4;
+    rebuild_corners(vertices, indices, |face, vertex| {
+        let own = faces[face];
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        if own.magnitude2() > 0.0 {
+            let own = own.normalize();
+            let key: [u32; 3] = bytemuck::cast(vertex.position);
+            for other in around[&key].iter().map(|f| faces[*f]) {
+                if other.magnitude2() > 0.0 && other.normalize().dot(own) >= threshold {
+                    normal += other;
+                }
+            }
+        }
+        // Triangles with no area have no direction, point them up rather than leave NaNs
+        let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { Vector3::unit_y() };
+        ModelVertex { normal: normal.into(), ..*vertex }
+    });
+}
+
+// Texture coordinates for a mesh that came without them, projected from
+// whichever side of a box around the model each face looks out of. One unit of
+// the model is one repeat of the texture.
+pub fn box_project_uvs(vertices: &mut Vec<ModelVertex>, indices: &mut [u32]) {
+    let faces = face_normals(vertices, indices);
+    rebuild_corners(vertices, indices, |face, vertex| {
+        let n = faces[face];
+        let p = vertex.position;
+        // Flipped by which way the face looks so the texture isn't mirrored, and v
+        // negated since images run top to bottom
+        let tex_coords = if n.x.abs() >= n.y.abs() && n.x.abs() >= n.z.abs() {
+            [-p[2] * n.x.signum(), -p[1]]
+        } else if n.y.abs() >= n.z.abs() {
+            [p[0], p[2] * n.y.signum()]
+        } else {
+            [p[0] * n.z.signum(), -p[1]]
+        };
+        ModelVertex { tex_coords, ..*vertex }
+    });
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
@@ -59,15 +396,44 @@ pub struct Model {
     pub name: String,
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    pub bounds: Bounds,
+}
+
+// Box around every vertex of a model, in the model's own space
+#[derive(Copy, Clone, Debug)]
+pub struct Bounds {
+    pub min: cgmath::Vector3<f32>,
+    pub max: cgmath::Vector3<f32>,
+}
+
+impl Bounds {
+    // Takes positions laid out x, y, z one after another like tobj gives them
+    pub fn from_positions<'a>(positions: impl Iterator<Item = &'a [f32]>) -> Self {
+        let mut min = cgmath::Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = cgmath::Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for position in positions {
+            for chunk in position.chunks_exact(3) {
+                min = cgmath::Vector3::new(min.x.min(chunk[0]), min.y.min(chunk[1]), min.z.min(chunk[2]));
+                max = cgmath::Vector3::new(max.x.max(chunk[0]), max.y.max(chunk[1]), max.z.max(chunk[2]));
+            }
+        }
+        if min.x > max.x {
+            // Nothing to go around
+            return Self { min: cgmath::Vector3::new(0.0, 0.0, 0.0), max: cgmath::Vector3::new(0.0, 0.0, 0.0) };
+        }
+        Self { min, max }
+    }
+
+    pub fn size(&self) -> cgmath::Vector3<f32> {
+        self.max - self.min
+    }
+
+    pub fn centre(&self) -> cgmath::Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
 }
 
 pub trait DrawModel<'a> {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'a Mesh,
-        material: &'a Material,
-        camera_bind_group: &'a wgpu::BindGroup,
-    );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
@@ -75,13 +441,13 @@ pub trait DrawModel<'a> {
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
     );
-
-    fn draw_model(&mut self, model: &'a Model, camera_bind_group: &'a wgpu::BindGroup);
-    fn draw_model_instanced(
+    // Only the meshes that are or aren't alpha blended, they go through different pipelines
+    fn draw_model_instanced_blended(
         &mut self,
         model: &'a Model,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
+        blended: bool,
     );
 }
 
@@ -89,15 +455,6 @@ impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        material: &'b Material,
-        camera_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group);
-    }
-
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
@@ -112,19 +469,122 @@ where
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
-    fn draw_model(&mut self, model: &'b Model, camera_bind_group: &'b wgpu::BindGroup) {
-        self.draw_model_instanced(model, 0..1, camera_bind_group);
-    }
-
-    fn draw_model_instanced(
+    fn draw_model_instanced_blended(
         &mut self,
         model: &'b Model,
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
+        blended: bool,
     ) {
         for mesh in &model.meshes {
             let material = &model.materials[mesh.material];
-            self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
+            if material.is_blended() == blended {
+                self.draw_mesh_instanced(mesh, material, instances.clone(), camera_bind_group);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Vector3};
+
+    fn vertex(position: [f32; 3]) -> ModelVertex {
+        ModelVertex { position, tex_coords: [0.0; 2], normal: [0.0; 3], tangent: [0.0; 3], bitangent: [0.0; 3] }
+    }
+
+    // A 2 unit cube sharing its 8 corners between faces, wound counter clockwise
+    // seen from outside
+    fn cube() -> (Vec<ModelVertex>, Vec<u32>) {
+        let (x, y, z) = (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z());
+        // (out, u, v) with u cross v pointing out
+        let faces = [(x, y, z), (-x, z, y), (y, z, x), (-y, x, z), (z, x, y), (-z, y, x)];
+        let mut vertices: Vec<ModelVertex> = vec![];
+        let mut indices = vec![];
+        for (out, u, v) in faces {
+            let corners: Vec<u32> = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                .iter()
+                .map(|(s, t)| {
+                    let position: [f32; 3] = (out + u * *s + v * *t).into();
+                    match vertices.iter().position(|v| v.position == position) {
+                        Some(index) => index as u32,
+                        None => {
+                            vertices.push(vertex(position));
+                            (vertices.len() - 1) as u32
+                        }
+                    }
+                })
+                .collect();
+            indices.extend([corners[0], corners[1], corners[2], corners[0], corners[2], corners[3]]);
+        }
+        (vertices, indices)
+    }
+
+    #[test]
+    fn corners_that_match_are_merged() {
+        let mut vertices = vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([1.0, 1.0, 0.0]), vertex([0.0, 1.0, 0.0])];
+        let mut indices = vec![0, 1, 2, 0, 2, 3];
+        rebuild_corners(&mut vertices, &mut indices, |_, v| *v);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+
+        // Giving each triangle its own normal splits the shared edge
+        rebuild_corners(&mut vertices, &mut indices, |face, v| ModelVertex { normal: [face as f32; 3], ..*v });
+        assert_eq!(vertices.len(), 6);
+    }
+
+    #[test]
+    fn cube_edges_stay_hard() {
+        let (mut vertices, mut indices) = cube();
+        generate_normals(&mut vertices, &mut indices, 60.0);
+        assert_eq!(vertices.len(), 24);
+        for triangle in indices.chunks_exact(3) {
+            let p: Vec<Vector3<f32>> = triangle.iter().map(|i| vertices[*i as usize].position.into()).collect();
+            let face = (p[1] - p[0]).cross(p[2] - p[0]).normalize();
+            for i in triangle {
+                let normal: Vector3<f32> = vertices[*i as usize].normal.into();
+                assert!((normal - face).magnitude() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn cube_corners_smooth_together() {
+        let (mut vertices, mut indices) = cube();
+        generate_normals(&mut vertices, &mut indices, 180.0);
+        assert_eq!(vertices.len(), 8);
+        // Corners on a face's diagonal are in both its triangles, so they lean
+        // towards it, but the normal still points out of the corner
+        for vertex in vertices.iter() {
+            let normal = Vector3::from(vertex.normal);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+            for axis in 0..3 {
+                assert!(normal[axis] * vertex.position[axis] > 0.1);
+            }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn flat_triangles_point_up() {
+        let mut vertices = vec![vertex([0.0, 0.0, 0.0]), vertex([1.0, 0.0, 0.0]), vertex([2.0, 0.0, 0.0])];
+        let mut indices = vec![0, 1, 2];
+        generate_normals(&mut vertices, &mut indices, 60.0);
+        for vertex in vertices.iter() {
+            assert_eq!(vertex.normal, [0.0, 1.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn box_projection_isnt_mirrored() {
+        let (mut vertices, mut indices) = cube();
+        box_project_uvs(&mut vertices, &mut indices);
+        for triangle in indices.chunks_exact(3) {
+            // Seen from outside u runs right and v runs down, so triangles wound
+            // counter clockwise come out clockwise in texture space
+            let uv: Vec<[f32; 2]> = triangle.iter().map(|i| vertices[*i as usize].tex_coords).collect();
+            let area = (uv[1][0] - uv[0][0]) * (uv[2][1] - uv[0][1]) - (uv[1][1] - uv[0][1]) * (uv[2][0] - uv[0][0]);
+            assert!(area < 0.0, "mirrored triangle {:?}", uv);
+        }
+    }
+}
@@ -1,12 +1,15 @@
+use std::collections::HashSet;
 use std::f32::EPSILON;
 
+use cgmath::ElementWise;
 use cgmath::InnerSpace;
 use cgmath::Vector3;
 use cgmath::SquareMatrix;
 use cgmath::Quaternion;
 use gloo::console::log;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RigidBody {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
@@ -15,15 +18,40 @@ pub struct RigidBody {
     pub angular_velocity: cgmath::Vector3<f32>,
     pub mass: f32,
     pub shape: Shape,
+    // Where the collider's centre is from `position`, before rotation. Models
+    // that aren't centred on their origin have one.
+    #[serde(default = "no_offset")]
+    pub offset: cgmath::Vector3<f32>,
+    // Triggers report collisions but nothing bounces off them
+    #[serde(default)]
+    pub trigger: bool,
+    // Set when the object is deleted. Its slot stays so indices to other bodies,
+    // and any still held to this one, keep pointing where they did.
+    #[serde(skip)]
+    pub removed: bool,
 }
 
+fn no_offset() -> Vector3<f32> {
+    Vector3::new(0.0, 0.0, 0.0)
+}
 
-#[derive(Copy,Clone)]
+#[derive(Copy,Clone, PartialEq, Serialize, Deserialize)]
 pub enum Shape {
     Sphere(f32),
     Box(cgmath::Vector3<f32>),
 }
 
+impl Shape {
+    // Scales the collider to match an instance's scale. Spheres can't stretch
+    // so they take the largest axis.
+    pub fn scaled(self, scale: Vector3<f32>) -> Shape {
+        match self {
+            Shape::Sphere(radius) => Shape::Sphere(radius * scale.x.max(scale.y).max(scale.z)),
+            Shape::Box(size) => Shape::Box(Vector3::new(size.x * scale.x, size.y * scale.y, size.z * scale.z)),
+        }
+    }
+}
+
 
 
 impl RigidBody {
@@ -36,9 +64,23 @@ impl RigidBody {
             mass,
             angular_velocity: Vector3::new(0.0, 0.0, 0.0),
             shape: Shape::Box(Vector3::new(1.0, 1.0, 1.0)),
+            offset: no_offset(),
+            trigger: false,
+            removed: false,
         }
     }
 
+    // The middle of the collider in the world
+    pub fn centre(&self) -> Vector3<f32> {
+        self.position + self.rotation * self.offset
+    }
+
+    // Stretches the collider, and where it sits, along with a change of scale
+    pub fn rescale(&mut self, ratio: Vector3<f32>) {
+        self.shape = self.shape.scaled(ratio);
+        self.offset = self.offset.mul_element_wise(ratio);
+    }
+
     pub fn update(&mut self, delta_time: f32) {
         let acceleration = self.acceleration;
         self.velocity += acceleration * delta_time;
@@ -61,24 +103,26 @@ impl RigidBody {
     pub fn is_intersecting(&self, other: &RigidBody) -> bool {
         match (self.shape, other.shape) {
             (Shape::Sphere(radius1), Shape::Sphere(radius2)) => {
-                let distance = (self.position - other.position).magnitude();
+                let distance = (self.centre() - other.centre()).magnitude();
                 distance < radius1 + radius2
             }
             (Shape::Box(size1), Shape::Box(size2)) => {
                 let half_size1 = size1 / 2.0;
                 let half_size2 = size2 / 2.0;
-                let distance = (self.position - other.position);
+                let distance = self.centre() - other.centre();
                 distance.x.abs() < half_size1.x + half_size2.x &&
                     distance.y.abs() < half_size1.y + half_size2.y &&
                     distance.z.abs() < half_size1.z + half_size2.z
             }
             (Shape::Sphere(radius), Shape::Box(size)) => {
                 let half_size = size / 2.0;
-                let mut distance = (self.position - other.position);
-                distance.x = distance.x.max(-half_size.x).min(half_size.x);
-                distance.y = distance.y.max(-half_size.y).min(half_size.y);
-                distance.z = distance.z.max(-half_size.z).min(half_size.z);
-                distance.magnitude() < radius
+                let distance = self.centre() - other.centre();
+                // Closest point of the box to the sphere's centre
+                let mut closest = distance;
+                closest.x = closest.x.max(-half_size.x).min(half_size.x);
+                closest.y = closest.y.max(-half_size.y).min(half_size.y);
+                closest.z = closest.z.max(-half_size.z).min(half_size.z);
+                (distance - closest).magnitude() < radius
             }
             (Shape::Box(size), Shape::Sphere(radius)) => other.is_intersecting(self),
 
@@ -86,6 +130,58 @@ impl RigidBody {
         }
     }
 
+    // Direction from `other` towards this body where they touch, for bouncing off
+    // it. Boxes use the face the centre is furthest past, rotation is ignored
+    // like it is for the overlap tests.
+    pub fn contact_normal(&self, other: &RigidBody) -> Vector3<f32> {
+        let distance = self.centre() - other.centre();
+        match other.shape {
+            Shape::Sphere(_) => {
+                if distance.magnitude() > f32::EPSILON {
+                    distance.normalize()
+                } else {
+                    Vector3::unit_y()
+                }
+            }
+            Shape::Box(size) => {
+                let half_size = size / 2.0 + match self.shape {
+                    Shape::Sphere(radius) => Vector3::new(radius, radius, radius),
+                    Shape::Box(size) => size / 2.0,
+                };
+                let past_x = distance.x.abs() - half_size.x;
+                let past_y = distance.y.abs() - half_size.y;
+                let past_z = distance.z.abs() - half_size.z;
+                if past_x >= past_y && past_x >= past_z {
+                    Vector3::new(distance.x.signum(), 0.0, 0.0)
+                } else if past_y >= past_z {
+                    Vector3::new(0.0, distance.y.signum(), 0.0)
+                } else {
+                    Vector3::new(0.0, 0.0, distance.z.signum())
+                }
+            }
+        }
+    }
+
+    // Distance along the ray to this body's collider. Boxes are turned with the
+    // body here, unlike in the overlap tests.
+    pub fn raycast(&self, ray: &Ray) -> Option<f32> {
+        match self.shape {
+            Shape::Sphere(radius) => ray.intersect_sphere(self.centre(), radius),
+            Shape::Box(size) => {
+                let local = ray.to_local(self.centre(), self.rotation, Vector3::new(1.0, 1.0, 1.0));
+                local.intersect_box(-size / 2.0, size / 2.0)
+            }
+        }
+    }
+
+    // Mirrors the velocity off a surface with this normal, unless it's already moving away
+    pub fn reflect(&mut self, normal: Vector3<f32>) {
+        let along_normal = self.velocity.dot(normal);
+        if along_normal < 0.0 {
+            self.velocity -= 2.0 * along_normal * normal;
+        }
+    }
+
     fn update_rotation(&mut self ,delta_time: f32) {
         let half_delta_rot = Quaternion::from_sv(0.0,0.5 * self.angular_velocity * delta_time);
         let new_orientation = self.rotation + (self.rotation * half_delta_rot);
@@ -124,3 +220,81 @@ impl RigidBody {
         }
     }
 }
+
+// A half line through the world, for picking things with the mouse
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    // The same ray in the space of something placed at position, rotation and
+    // scale. Distances along it still line up with the world ray.
+    pub fn to_local(&self, position: Vector3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>) -> Ray {
+        let inverse = rotation.conjugate();
+        let origin = inverse * (self.origin - position);
+        let direction = inverse * self.direction;
+        Ray {
+            origin: Vector3::new(origin.x / scale.x, origin.y / scale.y, origin.z / scale.z),
+            direction: Vector3::new(direction.x / scale.x, direction.y / scale.y, direction.z / scale.z),
+        }
+    }
+
+    // Distance to an axis aligned box, None if it misses or the box is behind.
+    // Starting inside counts as a hit straight away.
+    pub fn intersect_box(&self, min: Vector3<f32>, max: Vector3<f32>) -> Option<f32> {
+        let mut near = f32::MIN;
+        let mut far = f32::MAX;
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let mut t0 = (min[axis] - self.origin[axis]) * inverse;
+            let mut t1 = (max[axis] - self.origin[axis]) * inverse;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            near = near.max(t0);
+            far = far.min(t1);
+        }
+        if near > far || far < 0.0 {
+            return None;
+        }
+        Some(near.max(0.0))
+    }
+
+    pub fn intersect_sphere(&self, centre: Vector3<f32>, radius: f32) -> Option<f32> {
+        let offset = self.origin - centre;
+        let a = self.direction.dot(self.direction);
+        let b = offset.dot(self.direction);
+        let c = offset.dot(offset) - radius * radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 || a <= 0.0 {
+            return None;
+        }
+        let far = (-b + discriminant.sqrt()) / a;
+        if far < 0.0 {
+            return None;
+        }
+        Some(((-b - discriminant.sqrt()) / a).max(0.0))
+    }
+}
+
+// Every pair of overlapping bodies, lower index first
+pub fn find_contacts(rigidbodys: &[RigidBody]) -> HashSet<(usize, usize)> {
+    let mut contacts = HashSet::new();
+    for i in 0..rigidbodys.len() {
+        if rigidbodys[i].removed {
+            continue;
+        }
+        for j in (i + 1)..rigidbodys.len() {
+            if !rigidbodys[j].removed && rigidbodys[i].is_intersecting(&rigidbodys[j]) {
+                contacts.insert((i, j));
+            }
+        }
+    }
+    contacts
+}
@@ -0,0 +1,155 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::components::ball::{BallHit, Goal};
+use crate::physics::Shape;
+use crate::State;
+
+// Model the arena is built from. It spans -1 to 1, so colliders are twice the scale.
+const ARENA_MODEL: &str = "cube.obj";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Side {
+    Bottom,
+    Top,
+}
+
+impl Side {
+    fn index(self) -> usize {
+        match self {
+            Side::Bottom => 0,
+            Side::Top => 1,
+        }
+    }
+}
+
+// Published when one side reaches the winning score, balls stop serving
+pub struct MatchOver {
+    pub winner: Side,
+}
+
+// Score keeping for a Pong match. The balls run the points themselves, this
+// listens for their goals and paddle hits.
+pub struct PongMatch {
+    scores: [u32; 2],
+    pub target: u32,
+    // Paddle hits since the last serve
+    rally: u32,
+    winner: Option<Side>,
+    // Ball speed is multiplied by this on every paddle hit, up to max_speed
+    pub speed_up: f32,
+    pub max_speed: f32,
+}
+
+impl PongMatch {
+    pub fn new() -> Self {
+        Self {
+            scores: [0, 0],
+            target: 5,
+            rally: 0,
+            winner: None,
+            speed_up: 1.1,
+            max_speed: 15.0,
+        }
+    }
+
+    pub fn score(&self, side: Side) -> u32 {
+        self.scores[side.index()]
+    }
+
+    pub fn rally(&self) -> u32 {
+        self.rally
+    }
+
+    pub fn winner(&self) -> Option<Side> {
+        self.winner
+    }
+
+    // A side one point from winning, if the match is still going
+    pub fn match_point(&self) -> Option<Side> {
+        if self.winner.is_some() {
+            return None;
+        }
+        [Side::Bottom, Side::Top]
+            .into_iter()
+            .find(|side| self.score(*side) + 1 >= self.target)
+    }
+}
+
+impl Default for PongMatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Reads last frame's goals and hits. Runs once a frame while a match is on.
+pub(crate) fn tick(state: &mut State) {
+    let mut pong = match state.pong.take() {
+        Some(pong) => pong,
+        None => return,
+    };
+
+    let hits: Vec<(usize, usize)> = state.events.read::<BallHit>().map(|hit| (hit.ball, hit.other)).collect();
+    for (ball, other) in hits {
+        if !matches!(state.component_name(other), Some("Paddle") | Some("AI Paddle")) {
+            continue;
+        }
+        pong.rally += 1;
+        let rigidbody = &mut state.rigidbodys[ball];
+        let speed = rigidbody.velocity.magnitude();
+        if speed > 0.0 {
+            let new_speed = (speed * pong.speed_up).min(pong.max_speed).max(speed);
+            rigidbody.velocity *= new_speed / speed;
+        }
+    }
+
+    let goals: Vec<i32> = state.events.read::<Goal>().map(|goal| goal.side).collect();
+    for side in goals {
+        if pong.winner.is_some() {
+            break;
+        }
+        pong.rally = 0;
+        // Going out past the top edge is a point for the bottom player
+        let scorer = if side > 0 { Side::Bottom } else { Side::Top };
+        pong.scores[scorer.index()] += 1;
+        if pong.score(scorer) >= pong.target {
+            pong.winner = Some(scorer);
+            state.events.publish(MatchOver { winner: scorer });
+        }
+    }
+
+    state.pong = Some(pong);
+}
+
+// Clears the world and builds a court: walls down the sides, a player paddle
+// at the bottom on J and L, an AI paddle at the top, goal zones behind both and
+// the ball in the middle. Returns false if the cube model isn't loaded.
+pub fn new_match(state: &mut State) -> bool {
+    if state.find_model(ARENA_MODEL).is_none() {
+        log::warn!("A Pong match needs {} loaded", ARENA_MODEL);
+        return false;
+    }
+    state.clear_world();
+
+    spawn_box(state, [-5.5, 0.0, 0.0], [0.25, 7.5, 0.5], None);
+    spawn_box(state, [5.5, 0.0, 0.0], [0.25, 7.5, 0.5], None);
+    spawn_box(state, [0.0, -6.0, 0.0], [1.2, 0.2, 0.5], Some("Paddle"));
+    spawn_box(state, [0.0, 6.0, 0.0], [1.2, 0.2, 0.5], Some("AI Paddle"));
+    spawn_box(state, [0.0, -7.5, 0.0], [5.25, 0.05, 0.5], Some("Goal Zone"));
+    spawn_box(state, [0.0, 7.5, 0.0], [5.25, 0.05, 0.5], Some("Goal Zone"));
+    let ball = spawn_box(state, [0.0, 0.0, 0.0], [0.3, 0.3, 0.3], Some("Ball"));
+    state.rigidbodys[ball].shape = Shape::Sphere(0.3);
+
+    let camera = state.camera_mut();
+    camera.eye = (0.0, 0.0, 18.0).into();
+    camera.target = (0.0, 0.0, 0.0).into();
+    camera.up = Vector3::unit_y();
+
+    state.pong = Some(PongMatch::new());
+    true
+}
+
+fn spawn_box(state: &mut State, position: [f32; 3], scale: [f32; 3], component: Option<&str>) -> usize {
+    let component = component.and_then(|name| state.registry.create(name));
+    state.spawn(ARENA_MODEL, position, [0.0, 0.0, 0.0], scale, component);
+    state.rigidbodys.len() - 1
+}
@@ -0,0 +1,357 @@
+use std::ops::RangeInclusive;
+
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+// Side of the colour grading cube baked from the sliders
+const LUT_SIZE: u32 = 16;
+// Settings each pass can have, two vec4s in the shader
+const MAX_PARAMS: usize = 8;
+
+// The effects that come with the engine. Each is a full screen pass with its
+// own shader after post.wgsl, reading what the pass before it drew.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PostEffect {
+    ColorGrading,
+    ChromaticAberration,
+    Pixelate,
+    Vignette,
+}
+
+// A setting shown in the GUI and sent to the shader as `param(i)`
+#[derive(Clone, PartialEq, Debug)]
+pub struct PostParam {
+    pub name: &'static str,
+    pub value: f32,
+    pub range: RangeInclusive<f32>,
+    pub speed: f32,
+}
+
+impl PostParam {
+    fn new(name: &'static str, value: f32, range: RangeInclusive<f32>, speed: f32) -> Self {
+        Self { name, value, range, speed }
+    }
+}
+
+impl PostEffect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PostEffect::ColorGrading => "Colour grading",
+            PostEffect::ChromaticAberration => "Chromatic aberration",
+            PostEffect::Pixelate => "Pixelate",
+            PostEffect::Vignette => "Vignette",
+        }
+    }
+
+    fn shader(&self) -> &'static str {
+        match self {
+            PostEffect::ColorGrading => include_str!("post_grading.wgsl"),
+            PostEffect::ChromaticAberration => include_str!("post_chromatic.wgsl"),
+            PostEffect::Pixelate => include_str!("post_pixelate.wgsl"),
+            PostEffect::Vignette => include_str!("post_vignette.wgsl"),
+        }
+    }
+
+    // In the order the shader reads them
+    fn params(&self) -> Vec<PostParam> {
+        match self {
+            // Only strength goes to the shader, the rest are baked into the LUT
+            PostEffect::ColorGrading => vec![
+                PostParam::new("Strength", 1.0, 0.0..=1.0, 0.01),
+                PostParam::new("Contrast", 1.0, 0.0..=2.0, 0.01),
+                PostParam::new("Saturation", 1.0, 0.0..=2.0, 0.01),
+                PostParam::new("Temperature", 0.0, -1.0..=1.0, 0.01),
+                PostParam::new("Tint", 0.0, -1.0..=1.0, 0.01),
+            ],
+            PostEffect::ChromaticAberration => vec![PostParam::new("Strength", 2.0, 0.0..=20.0, 0.1)],
+            PostEffect::Pixelate => vec![PostParam::new("Pixel size", 4.0, 1.0..=32.0, 0.1)],
+            PostEffect::Vignette => vec![
+                PostParam::new("Intensity", 0.5, 0.0..=1.0, 0.01),
+                PostParam::new("Radius", 0.8, 0.0..=1.5, 0.01),
+                PostParam::new("Softness", 0.5, 0.01..=1.5, 0.01),
+            ],
+        }
+    }
+}
+
+// Matches `Post` in post.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    params: [f32; MAX_PARAMS],
+    resolution: [f32; 4],
+}
+
+pub struct PostPass {
+    pub effect: PostEffect,
+    pub enabled: bool,
+    pub params: Vec<PostParam>,
+    pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    // Reading each of the two ping-pong targets
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+// Full screen passes run in order after tonemapping. Each reads one of two
+// targets and draws into the other, the last draws into wherever the frame is
+// going. Passes can be reordered by moving them around in `passes`.
+pub struct PostStack {
+    pub passes: Vec<PostPass>,
+    targets: Vec<texture::Texture>,
+    format: wgpu::TextureFormat,
+    bind_group_layout: wgpu::BindGroupLayout,
+    lut_bind_group_layout: wgpu::BindGroupLayout,
+    lut: texture::Texture,
+    lut_bind_group: wgpu::BindGroup,
+    // Grading settings the LUT was baked with, cleared to bake it again
+    baked: Option<[f32; 4]>,
+    // Set while a LUT loaded from a file is used instead of the sliders
+    pub lut_file: Option<String>,
+}
+
+impl PostStack {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, config: &wgpu::SurfaceConfiguration) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                sampler_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_bind_group_layout"),
+        });
+        let lut_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), sampler_entry(1)],
+            label: Some("lut_bind_group_layout"),
+        });
+
+        let lut = Self::create_lut(device, queue, &bake_lut(1.0, 1.0, 0.0, 0.0), "baked_lut").unwrap();
+        let lut_bind_group = Self::create_lut_bind_group(device, &lut_bind_group_layout, &lut);
+        let targets = Self::create_targets(device, config);
+
+        let mut stack = Self {
+            passes: vec![],
+            targets,
+            format: config.format,
+            bind_group_layout,
+            lut_bind_group_layout,
+            lut,
+            lut_bind_group,
+            baked: Some([1.0, 1.0, 0.0, 0.0]),
+            lut_file: None,
+        };
+        for effect in [PostEffect::ColorGrading, PostEffect::ChromaticAberration, PostEffect::Pixelate, PostEffect::Vignette] {
+            let pass = stack.create_pass(device, effect);
+            stack.passes.push(pass);
+        }
+        stack
+    }
+
+    fn create_targets(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Vec<texture::Texture> {
+        (0..2).map(|_| texture::Texture::create_render_target(device, config, config.format, 1, "post_texture")).collect()
+    }
+
+    fn create_lut(device: &wgpu::Device, queue: &wgpu::Queue, image: &image::RgbaImage, label: &str) -> anyhow::Result<texture::Texture> {
+        let (width, height) = image.dimensions();
+        if height < 2 || width != height * height {
+            anyhow::bail!("a LUT should be {} squares of {}x{} side by side, this is {}x{}", height, height, height, width, height);
+        }
+        texture::Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(image.clone()), Some(label), texture::TextureKind::Color, texture::SamplerSettings::clamped())
+    }
+
+    fn create_lut_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, lut: &texture::Texture) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&lut.sampler),
+                },
+            ],
+            label: Some("lut_bind_group"),
+        })
+    }
+
+    fn create_pipeline(&self, device: &wgpu::Device, effect: PostEffect) -> wgpu::RenderPipeline {
+        let source = format!("{}\n{}", include_str!("post.wgsl"), effect.shader());
+        let shader = crate::create_fullscreen_shader(device, effect.name(), &source);
+        let mut bind_group_layouts = vec![&self.bind_group_layout];
+        if effect == PostEffect::ColorGrading {
+            bind_group_layouts.push(&self.lut_bind_group_layout);
+        }
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        crate::create_fullscreen_pipeline(device, &pipeline_layout, &shader, "fs_main", self.format, wgpu::BlendState::REPLACE, effect.name())
+    }
+
+    fn create_bind_groups(&self, device: &wgpu::Device, uniform_buffer: &wgpu::Buffer) -> Vec<wgpu::BindGroup> {
+        self.targets
+            .iter()
+            .map(|target| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&target.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&target.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: uniform_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("post_bind_group"),
+                })
+            })
+            .collect()
+    }
+
+    fn create_pass(&self, device: &wgpu::Device, effect: PostEffect) -> PostPass {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Buffer"),
+            contents: bytemuck::cast_slice(&[PostUniform {
+                params: [0.0; MAX_PARAMS],
+                resolution: [1.0; 4],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        PostPass {
+            effect,
+            enabled: false,
+            params: effect.params(),
+            pipeline: self.create_pipeline(device, effect),
+            bind_groups: self.create_bind_groups(device, &uniform_buffer),
+            uniform_buffer,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.targets = Self::create_targets(device, config);
+        for i in 0..self.passes.len() {
+            self.passes[i].bind_groups = self.create_bind_groups(device, &self.passes[i].uniform_buffer);
+        }
+    }
+
+    // Uses a LUT image instead of the sliders, see post_grading.wgsl for the layout
+    pub fn load_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, file_name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        let image = image::load_from_memory(bytes)?.to_rgba8();
+        self.lut = Self::create_lut(device, queue, &image, file_name)?;
+        self.lut_bind_group = Self::create_lut_bind_group(device, &self.lut_bind_group_layout, &self.lut);
+        self.lut_file = Some(file_name.to_string());
+        Ok(())
+    }
+
+    // Back to the LUT baked from the sliders, done on the next update
+    pub fn use_baked_lut(&mut self) {
+        self.lut_file = None;
+        self.baked = None;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.passes.iter().any(|pass| pass.enabled)
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, config: &wgpu::SurfaceConfiguration) {
+        let (width, height) = (config.width as f32, config.height as f32);
+        for pass in self.passes.iter().filter(|pass| pass.enabled) {
+            let mut params = [0.0; MAX_PARAMS];
+            for (value, param) in params.iter_mut().zip(pass.params.iter()) {
+                *value = param.value;
+            }
+            queue.write_buffer(&pass.uniform_buffer, 0, bytemuck::cast_slice(&[PostUniform {
+                params,
+                resolution: [width, height, 1.0 / width, 1.0 / height],
+            }]));
+        }
+
+        if self.lut_file.is_some() {
+            return;
+        }
+        let grading = match self.passes.iter().find(|pass| pass.effect == PostEffect::ColorGrading) {
+            Some(pass) => [pass.params[1].value, pass.params[2].value, pass.params[3].value, pass.params[4].value],
+            None => return,
+        };
+        if self.baked != Some(grading) {
+            let image = bake_lut(grading[0], grading[1], grading[2], grading[3]);
+            if let Ok(lut) = Self::create_lut(device, queue, &image, "baked_lut") {
+                self.lut = lut;
+                self.lut_bind_group = Self::create_lut_bind_group(device, &self.lut_bind_group_layout, &self.lut);
+            }
+            self.baked = Some(grading);
+        }
+    }
+
+    // Where tonemapping should draw when any pass is on
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let passes: Vec<&PostPass> = self.passes.iter().filter(|pass| pass.enabled).collect();
+        let mut source = 0;
+        for (i, pass) in passes.iter().enumerate() {
+            let output = if i + 1 == passes.len() { target } else { &self.targets[1 - source].view };
+            let mut bind_groups = vec![&pass.bind_groups[source]];
+            if pass.effect == PostEffect::ColorGrading {
+                bind_groups.push(&self.lut_bind_group);
+            }
+            crate::draw_fullscreen(encoder, pass.effect.name(), output, wgpu::LoadOp::Clear(wgpu::Color::BLACK), &pass.pipeline, &bind_groups);
+            source = 1 - source;
+        }
+    }
+}
+
+// A LUT strip with the grading sliders applied, in the layout post_grading.wgsl reads.
+// The sliders work on sRGB values, like they would in an image editor.
+fn bake_lut(contrast: f32, saturation: f32, temperature: f32, tint: f32) -> image::RgbaImage {
+    let step = 1.0 / (LUT_SIZE - 1) as f32;
+    image::RgbaImage::from_fn(LUT_SIZE * LUT_SIZE, LUT_SIZE, |x, y| {
+        let mut color = [
+            (x % LUT_SIZE) as f32 * step + temperature * 0.1,
+            y as f32 * step + tint * 0.1,
+            (x / LUT_SIZE) as f32 * step - temperature * 0.1,
+        ];
+        let luma = 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+        for channel in color.iter_mut() {
+            let saturated = luma + (*channel - luma) * saturation;
+            *channel = ((saturated - 0.5) * contrast + 0.5).clamp(0.0, 1.0);
+        }
+        image::Rgba([(color[0] * 255.0).round() as u8, (color[1] * 255.0).round() as u8, (color[2] * 255.0).round() as u8, 255])
+    })
+}
@@ -0,0 +1,24 @@
+// Shared by every post processing pass, each effect's own shader is added on after this
+
+struct Post {
+    // The effect's settings, in the order post.rs lists them
+    params: array<vec4<f32>, 2>,
+    // Width, height, 1 / width, 1 / height
+    resolution: vec4<f32>,
+};
+
+// The output of the pass before, or of tonemapping for the first one
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> post: Post;
+
+fn param(i: i32) -> f32 {
+    return post.params[i / 4][i % 4];
+}
+
+fn source(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0);
+}
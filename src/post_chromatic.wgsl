@@ -0,0 +1,11 @@
+// Chromatic aberration, pulls red and blue apart towards the edges like a cheap lens
+// Params: strength, in pixels at the edges
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let offset = (in.uv - 0.5) * 2.0 * param(0) * post.resolution.zw;
+    let color = source(in.uv);
+    let red = source(in.uv - offset).r;
+    let blue = source(in.uv + offset).b;
+    return vec4<f32>(red, color.g, blue, color.a);
+}
@@ -0,0 +1,35 @@
+// Colour grading, looks each colour up in a LUT. The LUT is a cube stored as a
+// strip of squares side by side, red across each square, green down and blue
+// from square to square. It maps sRGB colours to sRGB colours, like LUTs made
+// in image editors do.
+// Params: strength
+
+@group(1) @binding(0)
+var t_lut: texture_2d<f32>;
+@group(1) @binding(1)
+var s_lut: sampler;
+
+fn to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn lookup(color: vec3<f32>) -> vec3<f32> {
+    let size = f32(textureDimensions(t_lut).y);
+    let cell = clamp(to_srgb(color), vec3<f32>(0.0), vec3<f32>(1.0)) * (size - 1.0);
+    // Blue falls between two squares, both are read and blended
+    let slice = floor(cell.b);
+    let next = min(slice + 1.0, size - 1.0);
+    let uv = vec2<f32>((cell.r + 0.5) / (size * size), (cell.g + 0.5) / size);
+    // The LUT texture is sRGB, so these come out linear again
+    let a = textureSampleLevel(t_lut, s_lut, uv + vec2<f32>(slice / size, 0.0), 0.0).rgb;
+    let b = textureSampleLevel(t_lut, s_lut, uv + vec2<f32>(next / size, 0.0), 0.0).rgb;
+    return mix(a, b, cell.b - slice);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = source(in.uv);
+    return vec4<f32>(mix(color.rgb, lookup(color.rgb), param(0)), color.a);
+}
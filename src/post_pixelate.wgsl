@@ -0,0 +1,9 @@
+// Pixelation, every block of pixels takes the colour at its centre
+// Params: block size in pixels
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = max(param(0), 1.0);
+    let block = (floor(in.uv * post.resolution.xy / size) + 0.5) * size;
+    return source(block * post.resolution.zw);
+}
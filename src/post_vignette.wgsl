@@ -0,0 +1,14 @@
+// Vignette, darkens towards the corners
+// Params: intensity, radius, softness
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = source(in.uv);
+    let aspect = post.resolution.x * post.resolution.w;
+    // Round on screen whatever the window's shape, 1 at the corners
+    let offset = (in.uv - 0.5) * vec2<f32>(aspect, 1.0);
+    let distance = length(offset) / length(vec2<f32>(aspect, 1.0) * 0.5);
+    let radius = param(1);
+    let shade = 1.0 - smoothstep(radius - max(param(2), 0.001), radius, distance);
+    return vec4<f32>(color.rgb * mix(1.0, shade, param(0)), color.a);
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use crate::component::ComponentData;
+use crate::model::Bounds;
+use crate::physics::Shape;
+use crate::resources;
+
+// A reusable template for an object: which model to draw, how big it is, its
+// collider and the component driving it. Stored as RON next to the models.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Prefab {
+    pub name: String,
+    pub model: String,
+    pub scale: cgmath::Vector3<f32>,
+    pub mass: f32,
+    // Collider before the instance's scale is applied, None fits the model
+    #[serde(default)]
+    pub collider: Option<Shape>,
+    pub component: Option<ComponentData>,
+}
+
+// Per instance values that win over the prefab's. Anything left as None follows
+// the prefab, so edits to the prefab still reach the instance.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PrefabOverrides {
+    pub scale: Option<cgmath::Vector3<f32>>,
+    pub mass: Option<f32>,
+    pub collider: Option<Shape>,
+    pub component: Option<ComponentData>,
+}
+
+// Ties an instance back to the prefab it was spawned from
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PrefabLink {
+    // File the prefab was loaded from
+    pub prefab: String,
+    pub overrides: PrefabOverrides,
+}
+
+impl Prefab {
+    pub fn scale(&self, overrides: &PrefabOverrides) -> cgmath::Vector3<f32> {
+        overrides.scale.unwrap_or(self.scale)
+    }
+
+    pub fn mass(&self, overrides: &PrefabOverrides) -> f32 {
+        overrides.mass.unwrap_or(self.mass)
+    }
+
+    // The collider with the instance's scale already applied. Without one
+    // it's a box around the model's `bounds`.
+    pub fn collider(&self, overrides: &PrefabOverrides, bounds: &Bounds) -> Shape {
+        let collider = overrides.collider.or(self.collider).unwrap_or(Shape::Box(bounds.size()));
+        collider.scaled(self.scale(overrides))
+    }
+
+    pub fn component(&self, overrides: &PrefabOverrides) -> Option<ComponentData> {
+        overrides.component.clone().or(self.component.clone())
+    }
+}
+
+// Writes the prefab back to the res folder, run from the crate so it lands in the
+// source tree. The web downloads it like a scene.
+pub fn save(prefab: &Prefab, file_name: &str) -> anyhow::Result<()> {
+    let config = ron::ser::PrettyConfig::new().struct_names(true);
+    let text = ron::ser::to_string_pretty(prefab, config)?;
+    crate::scene::write_text(&format!("res/{}", file_name), &text)
+}
+
+pub async fn load_prefab(file_name: &str) -> anyhow::Result<Prefab> {
+    let text = resources::load_string(file_name).await?;
+    Ok(ron::from_str(&text)?)
+}
@@ -22,6 +22,21 @@ fn format_url(file_name: &str) -> reqwest::Url {
     base.join(file_name).unwrap()
 }
 
+// Runs a loading future to completion. The browser has to drive it from its own
+// executor, natively we can just block until it's done.
+pub fn spawn<F>(future: F)
+where
+    F: std::future::Future<Output = ()> + 'static,
+{
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            wasm_bindgen_futures::spawn_local(future);
+        } else {
+            pollster::block_on(future);
+        }
+    }
+}
+
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
@@ -63,15 +78,186 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
 
 pub async fn load_texture(
     file_name: &str,
+    kind: texture::TextureKind,
+    settings: texture::SamplerSettings,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+    texture::Texture::from_bytes(device, queue, &data, file_name, kind, settings)
+}
+
+// Splits an MTL texture statement like `-clamp on -bm 0.5 bricks.png` into the
+// file and how it should be sampled. `-clamp on` stops it repeating, and as our
+// own addition `-clamp mirror` repeats it mirrored. Other options are skipped.
+pub fn texture_options(value: &str) -> (String, texture::SamplerSettings) {
+    let mut settings = texture::SamplerSettings::default();
+    let mut tokens = value.split_whitespace().peekable();
+    let mut file = vec![];
+    while let Some(token) = tokens.next() {
+        match token {
+            "-clamp" => match tokens.next() {
+                Some("on") => settings = settings.with_address_mode(wgpu::AddressMode::ClampToEdge),
+                Some("mirror") => settings = settings.with_address_mode(wgpu::AddressMode::MirrorRepeat),
+                _ => settings = settings.with_address_mode(wgpu::AddressMode::Repeat),
+            },
+            "-blendu" | "-blendv" | "-bm" | "-boost" | "-cc" | "-imfchan" | "-texres" | "-type" => {
+                tokens.next();
+            }
+            "-mm" => {
+                tokens.next();
+                tokens.next();
+            }
+            // Offset, scale and turbulence take one to three numbers
+            "-o" | "-s" | "-t" => {
+                for _ in 0..3 {
+                    if tokens.peek().is_some_and(|t| t.parse::<f32>().is_ok()) {
+                        tokens.next();
+                    }
+                }
+            }
+            _ => file.push(token),
+        }
+    }
+    // Whatever's left is the file, which may have had spaces in it
+    (file.join(" "), settings)
+}
+
+// Loads a texture named in an MTL file, with its options applied. One that can't
+// be loaded is warned about and the caller puts a stand in there instead, so a
+// bad path doesn't lose the whole model.
+async fn load_material_texture(
+    value: &str,
+    kind: texture::TextureKind,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Option<texture::Texture> {
+    let (file_name, settings) = texture_options(value);
+    match load_texture(&file_name, kind, settings, device, queue).await {
+        Ok(texture) => Some(texture),
+        Err(e) => {
+            log::warn!("Couldn't load texture {}: {}", file_name, e);
+            None
+        }
+    }
+}
+
+// MTL has no real PBR model. This uses the PBR extension (Pr, Pm, Ke and their
+// maps) where the exporter wrote it and estimates from the Phong values where not.
+fn material_factors(m: &tobj::Material) -> model::MaterialFactors {
+    let param = |key: &str| -> Option<Vec<f32>> {
+        let values = m.unknown_param.get(key)?.split_whitespace().map(|v| v.parse().ok()).collect::<Option<Vec<f32>>>()?;
+        if values.is_empty() { None } else { Some(values) }
+    };
+    let mut factors = model::MaterialFactors::default();
+
+    let mut base = m.diffuse;
+    // Exporters often leave Kd out when there's a texture, it'd come through black
+    if base == [0.0; 3] && !m.diffuse_texture.is_empty() {
+        base = [1.0; 3];
+    }
+    factors.base_color = [base[0], base[1], base[2], m.dissolve.clamp(0.0, 1.0)];
+
+    // Blender writes Ns as (1 - roughness)^2 * 1000, so undo that
+    factors.roughness = match param("Pr") {
+        Some(pr) => pr[0],
+        None => 1.0 - (m.shininess / 1000.0).clamp(0.0, 1.0).sqrt(),
+    }
+    .clamp(0.0, 1.0);
+    factors.metallic = param("Pm").map(|pm| pm[0]).unwrap_or(0.0).clamp(0.0, 1.0);
+    factors.specular = (m.specular.iter().sum::<f32>() / 3.0).clamp(0.0, 1.0);
+    // The metallic and roughness maps are multiplied by these, with no values given they're used as they are
+    if m.unknown_param.contains_key("map_Pr") && param("Pr").is_none() {
+        factors.roughness = 1.0;
+    }
+    if m.unknown_param.contains_key("map_Pm") && param("Pm").is_none() {
+        factors.metallic = 1.0;
+    }
+
+    if let Some(ke) = param("Ke") {
+        factors.emissive = [ke[0], *ke.get(1).unwrap_or(&ke[0]), *ke.get(2).unwrap_or(&ke[0])];
+    } else if m.unknown_param.contains_key("map_Ke") {
+        factors.emissive = [1.0; 3];
+    }
+
+    // A dissolve map cuts shapes out, a dissolve value under 1 sees through
+    factors.alpha_mode = if !m.dissolve_texture.is_empty() {
+        model::AlphaMode::Mask
+    } else if m.dissolve < 1.0 {
+        model::AlphaMode::Blend
+    } else {
+        model::AlphaMode::Opaque
+    };
+    factors
+}
+
+// MTL keeps roughness and metallic in separate maps, they're packed into one
+// texture the glTF way, roughness in green and metallic in blue. Either file can
+// be empty, its channel is left white so the factor is used as it is.
+async fn load_metallic_roughness(
+    roughness_file: &str,
+    metallic_file: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let (roughness_file, settings) = texture_options(roughness_file);
+    let (metallic_file, metallic_settings) = texture_options(metallic_file);
+    // The packed texture can only be sampled one way, the roughness map's options win
+    let settings = if roughness_file.is_empty() { metallic_settings } else { settings };
+    let load = |file: String| async move {
+        if file.is_empty() {
+            return Ok::<_, anyhow::Error>(None);
+        }
+        let data = load_binary(&file).await?;
+        Ok(Some(image::load_from_memory(&data)?.to_luma8()))
+    };
+    let roughness = load(roughness_file).await?;
+    let metallic = load(metallic_file).await?;
+    let (width, height) = roughness.as_ref().or(metallic.as_ref()).map(|i| i.dimensions()).unwrap_or((1, 1));
+    // Maps of different sizes are stretched to match
+    let fit = |map: Option<image::GrayImage>| {
+        map.map(|m| if m.dimensions() == (width, height) { m } else { image::imageops::resize(&m, width, height, image::imageops::FilterType::Triangle) })
+    };
+    let roughness = fit(roughness);
+    let metallic = fit(metallic);
+    let packed = image::RgbaImage::from_fn(width, height, |x, y| {
+        let r = roughness.as_ref().map(|m| m.get_pixel(x, y)[0]).unwrap_or(255);
+        let m = metallic.as_ref().map(|m| m.get_pixel(x, y)[0]).unwrap_or(255);
+        image::Rgba([255, r, m, 255])
+    });
+    // Linear data, so it's loaded the same way as a normal map
+    texture::Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(packed), Some("metallic_roughness"), texture::TextureKind::Data, settings)
+}
+
+// How vertex data missing from an OBJ is made up
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ImportSettings {
+    // Faces meeting at more than this many degrees get a hard edge between them
+    // when normals are generated. 0 is flat shaded and 180 is smooth all over.
+    pub smoothing_angle: f32,
+    pub uv_projection: UvProjection,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UvProjection {
+    // Every vertex reads the texture's corner
+    None,
+    // Projected from the sides of a box around the model
+    Box,
+}
+
+impl Default for ImportSettings {
+    fn default() -> Self {
+        Self {
+            smoothing_angle: 60.0,
+            uv_projection: UvProjection::Box,
+        }
+    }
 }
 
 pub async fn load_model(
     file_name: &str,
+    settings: ImportSettings,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
@@ -88,69 +274,118 @@ pub async fn load_model(
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+            match load_string(&p).await {
+                Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                Err(_) => Err(tobj::LoadError::OpenFileFailed),
+            }
         },
     )
     .await?;
 
+    // A missing or broken MTL leaves the model with only the default material
+    let obj_materials = obj_materials.unwrap_or_else(|e| {
+        log::warn!("Couldn't load the materials for {}: {}", file_name, e);
+        vec![]
+    });
     let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-        })
+    for m in obj_materials {
+        let factors = material_factors(&m);
+        let mut textures = model::MaterialTextures::plain(device, queue);
+        // Without a map the base colour is just Kd
+        if !m.diffuse_texture.is_empty() {
+            textures.base_color = load_material_texture(&m.diffuse_texture, texture::TextureKind::Color, device, queue)
+                .await
+                .unwrap_or_else(|| texture::Texture::missing(device, queue));
+        }
+        // tobj reads map_Bump and bump for us, norm is left with the unknown parameters
+        let normal_file = match m.unknown_param.get("norm") {
+            Some(norm) if m.normal_texture.is_empty() => norm.clone(),
+            _ => m.normal_texture.clone(),
+        };
+        if !normal_file.is_empty() {
+            if let Some(normal) = load_material_texture(&normal_file, texture::TextureKind::Normal, device, queue).await {
+                textures.normal = normal;
+            }
+        }
+        let roughness_file = m.unknown_param.get("map_Pr").cloned().unwrap_or_default();
+        let metallic_file = m.unknown_param.get("map_Pm").cloned().unwrap_or_default();
+        if !roughness_file.is_empty() || !metallic_file.is_empty() {
+            match load_metallic_roughness(&roughness_file, &metallic_file, device, queue).await {
+                Ok(metallic_roughness) => textures.metallic_roughness = metallic_roughness,
+                Err(e) => log::warn!("Couldn't load the metallic and roughness maps for {}: {}", m.name, e),
+            }
+        }
+        if let Some(emissive_file) = m.unknown_param.get("map_Ke") {
+            textures.emissive = load_material_texture(emissive_file, texture::TextureKind::Color, device, queue)
+                .await
+                .unwrap_or_else(|| texture::Texture::missing(device, queue));
+        }
+        // There's no standard occlusion map in MTL, some exporters write map_ao and
+        // some put it in the ambient map
+        let occlusion_file = match m.unknown_param.get("map_ao") {
+            Some(ao) => ao.clone(),
+            None if m.ambient_texture != m.diffuse_texture => m.ambient_texture.clone(),
+            None => String::new(),
+        };
+        if !occlusion_file.is_empty() {
+            if let Some(occlusion) = load_material_texture(&occlusion_file, texture::TextureKind::Data, device, queue).await {
+                textures.occlusion = occlusion;
+            }
+        }
+        materials.push(model::Material::new(device, &m.name, factors, textures, layout));
+    }
+    // Meshes with no material, or one the MTL didn't have, are drawn plain white
+    let material_count = materials.len();
+    let needs_default = models.iter().any(|m| m.mesh.material_id.is_none_or(|id| id >= material_count));
+    if needs_default {
+        materials.push(model::Material::new(device, "default", model::MaterialFactors::default(), model::MaterialTextures::plain(device, queue), layout));
     }
 
+    let bounds = model::Bounds::from_positions(models.iter().map(|m| m.mesh.positions.as_slice()));
+
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
+            let material_id = m.mesh.material_id;
+            let has_normals = !m.mesh.normals.is_empty();
+            let has_tex_coords = !m.mesh.texcoords.is_empty();
+            let mut vertices = (0..m.mesh.positions.len() / 3)
                 .map(|i| {
-                    if m.mesh.normals.is_empty(){
-                        model::ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-                            normal: [0.0, 0.0, 0.0],
-                        }
-                    }else{
-                        model::ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-                            normal: [
+                    model::ModelVertex {
+                        position: [
+                            m.mesh.positions[i * 3],
+                            m.mesh.positions[i * 3 + 1],
+                            m.mesh.positions[i * 3 + 2],
+                        ],
+                        tex_coords: if has_tex_coords {
+                            [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]]
+                        } else {
+                            [0.0, 0.0]
+                        },
+                        // Made up below when the file has none
+                        normal: if has_normals {
+                            [
                                 m.mesh.normals[i * 3],
                                 m.mesh.normals[i * 3 + 1],
                                 m.mesh.normals[i * 3 + 2],
-                            ],
-                        }
+                            ]
+                        } else {
+                            [0.0, 0.0, 0.0]
+                        },
+                        // Filled in once all the vertices are read
+                        tangent: [0.0; 3],
+                        bitangent: [0.0; 3],
                     }
                 })
                 .collect::<Vec<_>>();
+            let mut indices = m.mesh.indices;
+            if !has_normals {
+                model::generate_normals(&mut vertices, &mut indices, settings.smoothing_angle);
+            }
+            if !has_tex_coords && settings.uv_projection == UvProjection::Box {
+                model::box_project_uvs(&mut vertices, &mut indices);
+            }
+            model::compute_tangents(&mut vertices, &indices);
 
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
@@ -159,7 +394,7 @@ pub async fn load_model(
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
+                contents: bytemuck::cast_slice(&indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
 
@@ -167,13 +402,13 @@ pub async fn load_model(
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+                num_elements: indices.len() as u32,
+                material: material_id.filter(|id| *id < material_count).unwrap_or(material_count),
             }
         })
         .collect::<Vec<_>>();
 
-    Ok(model::Model { name: file_name.to_owned(),meshes, materials,})
+    Ok(model::Model { name: file_name.to_owned(),meshes, materials, bounds,})
 }
 
 /*
@@ -361,4 +596,52 @@ async fn file_callback(files: Option<FileList>) {
 //create model instance for each model with no instance
 }
 }
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_file_repeats() {
+        let (file, settings) = texture_options("bricks.png");
+        assert_eq!(file, "bricks.png");
+        assert_eq!(settings, texture::SamplerSettings::default());
+    }
+
+    #[test]
+    fn clamp_options() {
+        let (file, settings) = texture_options("-clamp on bricks.png");
+        assert_eq!(file, "bricks.png");
+        assert_eq!(settings.address_mode_u, wgpu::AddressMode::ClampToEdge);
+        assert_eq!(settings.address_mode_v, wgpu::AddressMode::ClampToEdge);
+
+        let (file, settings) = texture_options("-clamp mirror bricks.png");
+        assert_eq!(file, "bricks.png");
+        assert_eq!(settings.address_mode_u, wgpu::AddressMode::MirrorRepeat);
+
+        let (file, settings) = texture_options("-clamp on -clamp off bricks.png");
+        assert_eq!(file, "bricks.png");
+        assert_eq!(settings.address_mode_u, wgpu::AddressMode::Repeat);
+    }
+
+    #[test]
+    fn offsets_take_up_to_three_numbers() {
+        let (file, _) = texture_options("-o 0.5 0.25 0 bricks.png");
+        assert_eq!(file, "bricks.png");
+        let (file, _) = texture_options("-o 0.5 bricks.png");
+        assert_eq!(file, "bricks.png");
+        let (file, settings) = texture_options("-s 2 2 -bm 0.3 -clamp on bricks.png");
+        assert_eq!(file, "bricks.png");
+        assert_eq!(settings.address_mode_u, wgpu::AddressMode::ClampToEdge);
+    }
+
+    #[test]
+    fn file_names_keep_their_spaces() {
+        let (file, settings) = texture_options("-clamp on old bricks 2.png");
+        assert_eq!(file, "old bricks 2.png");
+        assert_eq!(settings.address_mode_u, wgpu::AddressMode::ClampToEdge);
+        let (file, _) = texture_options("my texture.png");
+        assert_eq!(file, "my texture.png");
+    }
+}
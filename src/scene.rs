@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+use crate::component::ComponentData;
+use crate::light::Light;
+use crate::physics::RigidBody;
+use crate::prefab::PrefabLink;
+
+// Everything needed to rebuild a level. Saved as RON so layouts can be
+// diffed and checked into git.
+#[derive(Serialize, Deserialize)]
+pub struct Scene {
+    pub clear_color: [f32; 4],
+    pub camera: Camera,
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub prefabs: Vec<String>,
+    pub instances: Vec<SceneInstance>,
+    // Scenes saved before lighting get the default sun
+    #[serde(default = "crate::light::default_lights")]
+    pub lights: Vec<Light>,
+    #[serde(default = "crate::light::default_ambient")]
+    pub ambient: [f32; 3],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SceneInstance {
+    // Path of the model this is an instance of, must be one of `Scene::models`
+    pub model: String,
+    pub scale: cgmath::Vector3<f32>,
+    // Position and rotation live on the rigid body
+    pub rigid_body: RigidBody,
+    pub component: Option<ComponentData>,
+    // Prefab instances are refreshed from the prefab on load
+    #[serde(default)]
+    pub prefab: Option<PrefabLink>,
+    #[serde(default = "default_true")]
+    pub cast_shadows: bool,
+    #[serde(default = "default_true")]
+    pub receive_shadows: bool,
+}
+
+pub(crate) fn default_true() -> bool {
+    true
+}
+
+impl Scene {
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        let config = ron::ser::PrettyConfig::new().struct_names(true);
+        Ok(ron::ser::to_string_pretty(self, config)?)
+    }
+
+    pub fn from_ron(text: &str) -> anyhow::Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+}
+
+// Writes the scene out. Natively this is a file relative to the working
+// directory, on the web the browser downloads it.
+pub fn save(scene: &Scene, path: &str) -> anyhow::Result<()> {
+    write_text(path, &scene.to_ron()?)
+}
+
+// Shared with the prefab editor. The download is named after the last part of
+// `path`.
+pub(crate) fn write_text(path: &str, text: &str) -> anyhow::Result<()> {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            use wasm_bindgen::JsCast;
+
+            let blob = gloo::file::Blob::new_with_options(text, Some("text/plain"));
+            let url = gloo::file::ObjectUrl::from(blob);
+            let document = web_sys::window()
+                .and_then(|win| win.document())
+                .ok_or(anyhow::anyhow!("no document"))?;
+            let anchor = document
+                .create_element("a")
+                .map_err(|_| anyhow::anyhow!("couldn't create download link"))?
+                .dyn_into::<web_sys::HtmlAnchorElement>()
+                .map_err(|_| anyhow::anyhow!("couldn't create download link"))?;
+            anchor.set_href(&url);
+            anchor.set_download(path.rsplit('/').next().unwrap_or(path));
+            anchor.click();
+            // Dropping the URL revokes it, and the browser may not have started
+            // the download yet. It's let go on the next tick instead.
+            gloo::timers::callback::Timeout::new(0, move || drop(url)).forget();
+        } else {
+            std::fs::write(path, text)?;
+        }
+    }
+    Ok(())
+}
+
+// Reads a scene file into `inbox`. The web has to go through a file picker and
+// read the file asynchronously, so the caller polls `inbox` rather than getting
+// the text back directly.
+pub fn open(path: &str, inbox: Rc<RefCell<Option<String>>>) -> anyhow::Result<()> {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            use wasm_bindgen::{JsCast, closure::Closure};
+
+            let _ = path;
+            let document = web_sys::window()
+                .and_then(|win| win.document())
+                .ok_or(anyhow::anyhow!("no document"))?;
+            let input = document
+                .create_element("input")
+                .map_err(|_| anyhow::anyhow!("couldn't create file picker"))?
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .map_err(|_| anyhow::anyhow!("couldn't create file picker"))?;
+            input.set_type("file");
+            input.set_accept(".ron");
+
+            let onchange = Closure::wrap(Box::new(move |e: web_sys::Event| {
+                let input: web_sys::HtmlInputElement = e.target().unwrap().dyn_into().unwrap();
+                let inbox = inbox.clone();
+                if let Some(files) = input.files() {
+                    let files = gloo::file::FileList::from(files);
+                    if let Some(file) = files.first() {
+                        let file = file.clone();
+                        crate::resources::spawn(async move {
+                            match gloo::file::futures::read_as_text(&file).await {
+                                Ok(text) => *inbox.borrow_mut() = Some(text),
+                                Err(e) => log::warn!("Couldn't read scene file: {:?}", e),
+                            }
+                        });
+                    }
+                }
+            }) as Box<dyn FnMut(_)>);
+            input.set_onchange(Some(onchange.as_ref().unchecked_ref()));
+            onchange.forget();
+            input.click();
+        } else {
+            *inbox.borrow_mut() = Some(std::fs::read_to_string(path)?);
+        }
+    }
+    Ok(())
+}
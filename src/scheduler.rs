@@ -0,0 +1,437 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use crate::State;
+
+// What timers and coroutines run against. In the engine that's `State`, the
+// parameter only lets the scheduler be driven without a window in tests.
+pub trait Scheduled: Sized + 'static {
+    fn scheduler(&mut self) -> &mut Scheduler<Self>;
+}
+
+impl Scheduled for State {
+    fn scheduler(&mut self) -> &mut Scheduler {
+        &mut self.scheduler
+    }
+}
+
+// Handle to a timer or coroutine, used to cancel it
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TaskId(u64);
+
+struct Timer<S> {
+    id: TaskId,
+    // Simulation time the timer fires at
+    due: f32,
+    // Set for repeating timers
+    interval: Option<f32>,
+    action: Box<dyn FnMut(&mut S)>,
+}
+
+enum Wake<S> {
+    Now,
+    At(f32),
+    Until(Box<dyn FnMut(&S) -> bool>),
+}
+
+type Command<S> = Box<dyn FnOnce(&mut S)>;
+
+struct Shared<S> {
+    time: f32,
+    wake: Wake<S>,
+    commands: Vec<Command<S>>,
+}
+
+struct Coroutine<S> {
+    id: TaskId,
+    shared: Rc<RefCell<Shared<S>>>,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+// Runs delayed and repeating actions and coroutines against the simulation
+// clock, so nothing fires while the game is paused and everything follows the
+// time scale. Timers and coroutines are checked once per frame, after the
+// systems have run.
+pub struct Scheduler<S = State> {
+    time: f32,
+    next_id: u64,
+    timers: Vec<Timer<S>>,
+    coroutines: Vec<Coroutine<S>>,
+    // Tasks taken out to run this frame, and which of those were cancelled meanwhile
+    running: Vec<TaskId>,
+    cancelled: Vec<TaskId>,
+}
+
+impl<S: 'static> Scheduler<S> {
+    pub fn new() -> Self {
+        Self {
+            time: 0.0,
+            next_id: 0,
+            timers: vec![],
+            coroutines: vec![],
+            running: vec![],
+            cancelled: vec![],
+        }
+    }
+
+    // Seconds of simulation time since the engine started
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    fn next_id(&mut self) -> TaskId {
+        self.next_id += 1;
+        TaskId(self.next_id)
+    }
+
+    // Runs `action` once, `seconds` from now
+    pub fn after<F>(&mut self, seconds: f32, action: F) -> TaskId
+    where
+        F: FnOnce(&mut S) + 'static,
+    {
+        let mut action = Some(action);
+        let id = self.next_id();
+        self.timers.push(Timer {
+            id,
+            due: self.time + seconds,
+            interval: None,
+            action: Box::new(move |state| {
+                if let Some(action) = action.take() {
+                    action(state);
+                }
+            }),
+        });
+        id
+    }
+
+    // Runs `action` every `seconds` until cancelled. It fires at most once a
+    // frame, so very short intervals just run every frame.
+    pub fn every<F>(&mut self, seconds: f32, action: F) -> TaskId
+    where
+        F: FnMut(&mut S) + 'static,
+    {
+        let id = self.next_id();
+        self.timers.push(Timer {
+            id,
+            due: self.time + seconds,
+            interval: Some(seconds),
+            action: Box::new(action),
+        });
+        id
+    }
+
+    // Starts a coroutine. It first runs on the next scheduler tick and carries on
+    // each time whatever it's waiting on is done:
+    //
+    //     scheduler.start(|co| async move {
+    //         co.wait(2.0).await;
+    //         co.publish(Respawn);
+    //     });
+    pub fn start<F, Fut>(&mut self, coroutine: F) -> TaskId
+    where
+        F: FnOnce(Co<S>) -> Fut,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let id = self.next_id();
+        let shared = Rc::new(RefCell::new(Shared {
+            time: self.time,
+            wake: Wake::Now,
+            commands: vec![],
+        }));
+        let future = Box::pin(coroutine(Co { shared: shared.clone() }));
+        self.coroutines.push(Coroutine { id, shared, future });
+        id
+    }
+
+    // Stops a timer or coroutine, returns false if it had already finished
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        let timers = self.timers.len();
+        let coroutines = self.coroutines.len();
+        self.timers.retain(|t| t.id != id);
+        self.coroutines.retain(|c| c.id != id);
+        if self.timers.len() != timers || self.coroutines.len() != coroutines {
+            return true;
+        }
+        if self.running.contains(&id) && !self.cancelled.contains(&id) {
+            self.cancelled.push(id);
+            return true;
+        }
+        false
+    }
+
+    // Drops everything scheduled, used when a new scene replaces the old one
+    pub fn clear(&mut self) {
+        self.timers.clear();
+        self.coroutines.clear();
+        self.cancelled.extend(self.running.iter().copied());
+    }
+
+    fn finish(&mut self, id: TaskId) -> bool {
+        self.running.retain(|r| *r != id);
+        match self.cancelled.iter().position(|c| *c == id) {
+            Some(index) => {
+                self.cancelled.remove(index);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl Scheduler {
+    // Publishes `event` after a delay. Components can't hold on to themselves in a
+    // timer, so they schedule an event and pick it up in `on_event` instead.
+    pub fn publish_after<T: 'static>(&mut self, seconds: f32, event: T) -> TaskId {
+        self.after(seconds, move |state| state.publish(event))
+    }
+}
+
+impl<S: 'static> Default for Scheduler<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Advances the clock by `dt` simulation seconds and runs whatever is due.
+// Actions get the whole engine state, so they're run with the due tasks taken
+// out of the scheduler, letting them schedule and cancel freely.
+pub(crate) fn tick<S: Scheduled>(state: &mut S, dt: f32) {
+    state.scheduler().time += dt;
+    let time = state.scheduler().time;
+
+    let (due, waiting): (Vec<Timer<S>>, Vec<Timer<S>>) = std::mem::take(&mut state.scheduler().timers)
+        .into_iter()
+        .partition(|t| t.due <= time);
+    state.scheduler().timers = waiting;
+    state.scheduler().running.extend(due.iter().map(|t| t.id));
+    for mut timer in due {
+        if state.scheduler().cancelled.contains(&timer.id) {
+            state.scheduler().finish(timer.id);
+            continue;
+        }
+        (timer.action)(state);
+        if state.scheduler().finish(timer.id) {
+            if let Some(interval) = timer.interval {
+                timer.due = (timer.due + interval).max(time + f32::EPSILON);
+                state.scheduler().timers.push(timer);
+            }
+        }
+    }
+
+    let coroutines = std::mem::take(&mut state.scheduler().coroutines);
+    state.scheduler().running.extend(coroutines.iter().map(|c| c.id));
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    for mut coroutine in coroutines {
+        if state.scheduler().cancelled.contains(&coroutine.id) {
+            state.scheduler().finish(coroutine.id);
+            continue;
+        }
+        let ready = {
+            let mut shared = coroutine.shared.borrow_mut();
+            shared.time = time;
+            match &mut shared.wake {
+                Wake::Now => true,
+                Wake::At(at) => *at <= time,
+                Wake::Until(condition) => condition(state),
+            }
+        };
+        let done = if ready {
+            coroutine.shared.borrow_mut().wake = Wake::Now;
+            let poll = coroutine.future.as_mut().poll(&mut cx);
+            let commands = std::mem::take(&mut coroutine.shared.borrow_mut().commands);
+            for command in commands {
+                command(state);
+            }
+            poll.is_ready()
+        } else {
+            false
+        };
+        if state.scheduler().finish(coroutine.id) && !done {
+            state.scheduler().coroutines.push(coroutine);
+        }
+    }
+}
+
+// Given to a coroutine so it can wait and reach the engine
+pub struct Co<S = State> {
+    shared: Rc<RefCell<Shared<S>>>,
+}
+
+impl<S> Co<S> {
+    // Simulation time when the coroutine was last resumed
+    pub fn time(&self) -> f32 {
+        self.shared.borrow().time
+    }
+
+    pub fn wait(&self, seconds: f32) -> Yield<S> {
+        let at = self.time() + seconds;
+        self.suspend(Wake::At(at))
+    }
+
+    // Resumes on the first frame `condition` holds, checked once per frame
+    pub fn wait_until<F>(&self, condition: F) -> Yield<S>
+    where
+        F: FnMut(&S) -> bool + 'static,
+    {
+        self.suspend(Wake::Until(Box::new(condition)))
+    }
+
+    // Waits for the next frame
+    pub fn next_frame(&self) -> Yield<S> {
+        self.suspend(Wake::Now)
+    }
+
+    // Runs `command` against the engine once the coroutine next yields
+    pub fn with_state<F>(&self, command: F)
+    where
+        F: FnOnce(&mut S) + 'static,
+    {
+        self.shared.borrow_mut().commands.push(Box::new(command));
+    }
+
+    fn suspend(&self, wake: Wake<S>) -> Yield<S> {
+        Yield {
+            shared: self.shared.clone(),
+            wake: Some(wake),
+        }
+    }
+}
+
+impl Co {
+    pub fn publish<T: 'static>(&self, event: T) {
+        self.with_state(move |state| state.publish(event));
+    }
+}
+
+// Future returned by the `Co` waits. The first poll hands the wake condition to
+// the scheduler and suspends, the scheduler only polls again once it's met.
+pub struct Yield<S = State> {
+    shared: Rc<RefCell<Shared<S>>>,
+    wake: Option<Wake<S>>,
+}
+
+impl<S> Future for Yield<S> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        match self.wake.take() {
+            Some(wake) => {
+                self.shared.borrow_mut().wake = wake;
+                Poll::Pending
+            }
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+// Coroutines are polled every frame by `tick`, so there's nothing to wake
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stands in for the engine state, actions just log what they did
+    #[derive(Default)]
+    struct World {
+        scheduler: Scheduler<World>,
+        log: Vec<&'static str>,
+    }
+
+    impl Scheduled for World {
+        fn scheduler(&mut self) -> &mut Scheduler<World> {
+            &mut self.scheduler
+        }
+    }
+
+    #[test]
+    fn timer_fires_once() {
+        let mut world = World::default();
+        world.scheduler.after(1.0, |w| w.log.push("fired"));
+        tick(&mut world, 0.5);
+        assert!(world.log.is_empty());
+        tick(&mut world, 0.5);
+        tick(&mut world, 1.0);
+        assert_eq!(world.log, ["fired"]);
+    }
+
+    #[test]
+    fn cancelled_timer_never_fires() {
+        let mut world = World::default();
+        let id = world.scheduler.after(1.0, |w| w.log.push("fired"));
+        assert!(world.scheduler.cancel(id));
+        tick(&mut world, 2.0);
+        assert!(world.log.is_empty());
+        assert!(!world.scheduler.cancel(id));
+    }
+
+    #[test]
+    fn repeating_timer_fires_each_interval_until_cancelled() {
+        let mut world = World::default();
+        let id = world.scheduler.every(1.0, |w| w.log.push("tick"));
+        for _ in 0..3 {
+            tick(&mut world, 1.0);
+        }
+        assert_eq!(world.log.len(), 3);
+        world.scheduler.cancel(id);
+        tick(&mut world, 1.0);
+        assert_eq!(world.log.len(), 3);
+    }
+
+    #[test]
+    fn timer_can_cancel_itself() {
+        let mut world = World::default();
+        let id = Rc::new(RefCell::new(None));
+        let own = id.clone();
+        *id.borrow_mut() = Some(world.scheduler.every(1.0, move |w| {
+            w.log.push("tick");
+            w.scheduler.cancel(own.borrow().unwrap());
+        }));
+        tick(&mut world, 1.0);
+        tick(&mut world, 1.0);
+        assert_eq!(world.log, ["tick"]);
+    }
+
+    #[test]
+    fn coroutine_resumes_after_wait() {
+        let mut world = World::default();
+        world.scheduler.start(|co| async move {
+            co.with_state(|w| w.log.push("start"));
+            co.wait(2.0).await;
+            co.with_state(|w| w.log.push("resumed"));
+        });
+        tick(&mut world, 0.0);
+        assert_eq!(world.log, ["start"]);
+        tick(&mut world, 1.0);
+        assert_eq!(world.log, ["start"]);
+        tick(&mut world, 1.0);
+        assert_eq!(world.log, ["start", "resumed"]);
+        assert!(world.scheduler.coroutines.is_empty());
+    }
+
+    #[test]
+    fn coroutine_waits_until_condition() {
+        let mut world = World::default();
+        world.scheduler.start(|co| async move {
+            co.wait_until(|w: &World| w.log.contains(&"ready")).await;
+            co.with_state(|w| w.log.push("done"));
+        });
+        tick(&mut world, 1.0);
+        tick(&mut world, 1.0);
+        assert!(world.log.is_empty());
+        world.log.push("ready");
+        tick(&mut world, 1.0);
+        assert_eq!(world.log, ["ready", "done"]);
+    }
+}
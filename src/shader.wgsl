@@ -1,6 +1,7 @@
 // Vertex shader
 
 struct Camera {
+    view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
 }
 @group(1) @binding(0)
@@ -9,17 +10,32 @@ var<uniform> camera: Camera;
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
 }
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
     @location(7) model_matrix_2: vec4<f32>,
     @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) highlight: f32,
+    @location(13) receive_shadows: f32,
 }
 
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // The depth pass has to land on exactly the same depth as the main pass
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) highlight: f32,
+    @location(3) world_position: vec3<f32>,
+    @location(4) world_tangent: vec3<f32>,
+    @location(5) world_bitangent: vec3<f32>,
+    @location(6) receive_shadows: f32,
 }
 
 @vertex
@@ -33,9 +49,25 @@ fn vs_main(
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_normal = normalize(normal_matrix * model.normal);
+    // Tangents lie along the surface, so they follow the model matrix itself. The
+    // normal matrix is only right for normals, and skews them under uneven
+    // scale. `perturb_normal` squares them up against the normal again.
+    let model_3x3 = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+    out.world_tangent = model_3x3 * model.tangent;
+    out.world_bitangent = model_3x3 * model.bitangent;
+    out.highlight = instance.highlight;
+    out.receive_shadows = instance.receive_shadows;
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
@@ -45,8 +77,264 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+@group(0) @binding(6)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(7)
+var s_emissive: sampler;
+@group(0) @binding(8)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(9)
+var s_occlusion: sampler;
+
+struct Material {
+    base_color: vec4<f32>,
+    // Emissive colour, w is the specular factor
+    emissive: vec4<f32>,
+    // Metallic, roughness, occlusion strength, alpha cutoff
+    params: vec4<f32>,
+    // 0 opaque, 1 mask, 2 blend
+    alpha_mode: u32,
+}
+@group(0) @binding(10)
+var<uniform> material: Material;
+
+struct Light {
+    position: vec4<f32>,
+    direction: vec4<f32>,
+    // Already multiplied by the intensity
+    color: vec4<f32>,
+    // Kind (0 directional, 1 point, 2 spot), range, cos inner angle, cos outer angle
+    params: vec4<f32>,
+    // First shadow map layer or -1 for none, how many layers
+    shadow: vec4<f32>,
+}
+struct Lights {
+    lights: array<Light, 8>,
+    ambient: vec4<f32>,
+    count: u32,
+}
+@group(2) @binding(0)
+var<uniform> lights: Lights;
+
+struct Shadows {
+    view_proj: array<mat4x4<f32>, 8>,
+    // x is how big a texel is in world units, or at one unit away for spot lights
+    layers: array<vec4<f32>, 8>,
+    // Far end of each cascade along the camera's view
+    splits: vec4<f32>,
+    camera_forward: vec4<f32>,
+    // Size of a texel in the texture, PCF on (1) or off (0)
+    params: vec4<f32>,
+}
+@group(2) @binding(1)
+var<uniform> shadows: Shadows;
+@group(2) @binding(2)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(3)
+var s_shadow: sampler_comparison;
+
+// Ambient occlusion from ssao.rs at half the size of the screen, white with SSAO off
+@group(3) @binding(0)
+var t_ao: texture_2d<f32>;
+@group(3) @binding(1)
+var s_ao: sampler;
+
+const PI: f32 = 3.14159265;
+
+// What the lighting needs to know about the surface at a point
+struct Surface {
+    albedo: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    // Reflectance looking straight at the surface
+    f0: vec3<f32>,
+}
+
+// GGX normal distribution, how many microfacets face along the half vector
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's method with Schlick-GGX, how much the microfacets shadow each other
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (vec3<f32>(1.0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Cook-Torrance diffuse and specular from one light
+fn shade(light: Light, position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, surface: Surface) -> vec3<f32> {
+    let kind = u32(light.params.x);
+    var light_dir = -light.direction.xyz;
+    var attenuation = 1.0;
+    if kind != 0u {
+        let to_light = light.position.xyz - position;
+        let distance = length(to_light);
+        light_dir = to_light / max(distance, 0.0001);
+        // Smooth falloff that reaches zero exactly at the range
+        let fade = clamp(1.0 - pow(distance / light.params.y, 4.0), 0.0, 1.0);
+        attenuation = fade * fade / (1.0 + distance * distance);
+    }
+    if kind == 2u {
+        let cos_angle = dot(-light_dir, light.direction.xyz);
+        attenuation *= smoothstep(light.params.w, light.params.z, cos_angle);
+    }
+    let n_dot_l = max(dot(normal, light_dir), 0.0);
+    if n_dot_l <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let half_dir = normalize(light_dir + view_dir);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+    let n_dot_h = max(dot(normal, half_dir), 0.0);
+    let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+    let d = distribution_ggx(n_dot_h, surface.roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, surface.roughness);
+    let f = fresnel_schlick(v_dot_h, surface.f0);
+    let specular = d * g * f / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    // Metals have no diffuse, and what's reflected isn't there to be diffused
+    let k_d = (vec3<f32>(1.0) - f) * (1.0 - surface.metallic);
+    // Light colours count as already multiplied by pi, so a white light of intensity
+    // 1 straight onto a white surface still comes out white
+    return (k_d * surface.albedo + specular * PI) * light.color.rgb * n_dot_l * attenuation;
+}
+
+// How much of the light reaches the point, 0 in full shadow and 1 in full light
+fn shadow_factor(light: Light, position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let first = i32(light.shadow.x);
+    if first < 0 {
+        return 1.0;
+    }
+    var layer = first;
+    let count = i32(light.shadow.y);
+    if count > 1 {
+        // Cascades are picked by how far along the view the point is, past the last there's no shadow
+        let depth = dot(position - camera.view_pos.xyz, shadows.camera_forward.xyz);
+        var cascade = count;
+        for (var i = count - 1; i >= 0; i -= 1) {
+            if depth < shadows.splits[i] {
+                cascade = i;
+            }
+        }
+        if cascade >= count {
+            return 1.0;
+        }
+        layer = first + cascade;
+    }
+
+    var light_dir = -light.direction.xyz;
+    var texel = shadows.layers[layer].x;
+    if u32(light.params.x) == 2u {
+        light_dir = normalize(light.position.xyz - position);
+        texel *= distance(light.position.xyz, position);
+    }
+    // Reading the map a little out along the normal stops surfaces shadowing
+    // themselves, more so where the light comes in at a glancing angle
+    let slope = 1.0 - max(dot(normal, light_dir), 0.0);
+    let offset_position = position + normal * texel * (0.5 + 1.5 * slope);
+    let clip = shadows.view_proj[layer] * vec4<f32>(offset_position, 1.0);
+    if clip.w <= 0.0 {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    if shadows.params.y < 0.5 {
+        return textureSampleCompareLevel(t_shadow, s_shadow, uv, layer, ndc.z);
+    }
+    // Percentage closer filtering, the share of the texels around that are lit
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadows.params.x;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, layer, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Bends the surface normal by the normal map, read in tangent space
+fn perturb_normal(normal: vec3<f32>, tangent: vec3<f32>, bitangent: vec3<f32>, tangent_normal: vec3<f32>) -> vec3<f32> {
+    // Meshes without texture coordinates have no tangents to go on
+    if dot(tangent, tangent) < 0.000001 || dot(bitangent, bitangent) < 0.000001 {
+        return normal;
+    }
+    // Interpolation leaves them slightly off square, straighten them back up
+    let t = normalize(tangent - normal * dot(normal, tangent));
+    let b = normalize(bitangent - normal * dot(normal, bitangent) - t * dot(t, bitangent));
+    let tbn = mat3x3<f32>(t, b, normal);
+    return normalize(tbn * tangent_normal);
+}
+
+// The depth pass only needs the holes cut out of masked materials
+@fragment
+fn fs_depth(in: VertexOutput) {
+    let alpha = textureSample(t_diffuse, s_diffuse, in.tex_coords).a * material.base_color.a;
+    if material.alpha_mode == 1u && alpha < material.params.w {
+        discard;
+    }
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let base_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.base_color;
+    let tangent_normal = textureSample(t_normal, s_normal, in.tex_coords).xyz * 2.0 - 1.0;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).rgb * material.emissive.rgb;
+    let occlusion = mix(1.0, textureSample(t_occlusion, s_occlusion, in.tex_coords).r, material.params.z);
+    if material.alpha_mode == 1u && base_color.a < material.params.w {
+        discard;
+    }
+
+    var surface: Surface;
+    surface.albedo = base_color.rgb;
+    surface.metallic = clamp(material.params.x * metallic_roughness.b, 0.0, 1.0);
+    // Perfectly smooth surfaces make the highlight vanish to a point
+    surface.roughness = clamp(material.params.y * metallic_roughness.g, 0.04, 1.0);
+    // Non-metals reflect a little of every colour, metals reflect their own colour
+    surface.f0 = mix(vec3<f32>(0.08 * material.emissive.w), base_color.rgb, surface.metallic);
+
+    let normal = perturb_normal(normalize(in.world_normal), in.world_tangent, in.world_bitangent, tangent_normal);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    // SSAO only darkens the ambient light, direct light has shadows for that.
+    // See-through surfaces weren't in the depth it was worked out from.
+    var ambient_occlusion = occlusion;
+    if material.alpha_mode != 2u {
+        let ao_uv = in.clip_position.xy / (vec2<f32>(textureDimensions(t_ao)) * 2.0);
+        ambient_occlusion *= textureSampleLevel(t_ao, s_ao, ao_uv, 0.0).r;
+    }
+    var lighting = lights.ambient.rgb * base_color.rgb * ambient_occlusion;
+    for (var i = 0u; i < min(lights.count, 8u); i += 1u) {
+        var shadow = 1.0;
+        if in.receive_shadows > 0.5 {
+            shadow = shadow_factor(lights.lights[i], in.world_position, normalize(in.world_normal));
+        }
+        lighting += shade(lights.lights[i], in.world_position, normal, view_dir, surface) * shadow;
+    }
+    var alpha = 1.0;
+    if material.alpha_mode == 2u {
+        alpha = base_color.a;
+    }
+    let color = vec4<f32>(lighting + emissive, alpha);
+    // Objects selected in the editor are tinted orange
+    return mix(color, vec4<f32>(1.0, 0.6, 0.1, 1.0), in.highlight * 0.5);
+}
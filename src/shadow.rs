@@ -0,0 +1,368 @@
+use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use wgpu::util::DeviceExt;
+
+use crate::camera::Camera;
+use crate::light::{Light, LightKind, MAX_LIGHTS};
+use crate::model::{self, Vertex};
+use crate::{texture, InstanceRaw, ModelInstances};
+
+// Width and height of every shadow map layer, in texels
+pub const SHADOW_SIZE: u32 = 1024;
+// Layers in the shadow map texture. A spot light or a plain directional light
+// takes one, a cascaded directional light takes CASCADES. Lights that don't
+// fit get no shadow.
+pub const SHADOW_LAYERS: usize = 8;
+pub const CASCADES: usize = 3;
+// How far from the camera directional light shadows reach
+pub const SHADOW_DISTANCE: f32 = 40.0;
+// Blend between evenly spaced cascades (0) and logarithmic ones (1). Higher
+// keeps more of the detail near the camera.
+const SPLIT_LAMBDA: f32 = 0.6;
+// Directional shadow maps reach this far back towards the light past the
+// camera's view, so things out of shot still cast into it
+const CASTER_DISTANCE: f32 = 40.0;
+
+// The camera's matrix maps depth into a third of wgpu's range, this is the
+// plain GL to wgpu remap the shadow maps are rendered with
+#[rustfmt::skip]
+const SHADOW_DEPTH_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// Matches `Shadows` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    view_proj: [[[f32; 4]; 4]; SHADOW_LAYERS],
+    // x is how big a texel is in world units, or at one unit away for spot lights
+    layers: [[f32; 4]; SHADOW_LAYERS],
+    // Far end of each cascade, as a distance along the camera's view
+    splits: [f32; 4],
+    camera_forward: [f32; 4],
+    // Size of a texel in the texture, PCF on (1) or off (0)
+    params: [f32; 4],
+}
+
+// Depth maps rendered from each shadow casting light, read by the main shader
+// to find out what's in shadow
+pub struct ShadowMaps {
+    // Soften edges by comparing against the 3x3 texels around the point
+    pub pcf: bool,
+    texture: texture::Texture,
+    // One per layer, rendered into one at a time
+    layer_views: Vec<wgpu::TextureView>,
+    layer_buffers: Vec<wgpu::Buffer>,
+    layer_bind_groups: Vec<wgpu::BindGroup>,
+    uniform_buffer: wgpu::Buffer,
+    pipeline: wgpu::RenderPipeline,
+    // How many layers were given out this frame
+    used: usize,
+}
+
+impl ShadowMaps {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let texture = texture::Texture::create_depth_texture_layers(device, SHADOW_SIZE, SHADOW_SIZE, SHADOW_LAYERS as u32, 1, "shadow_texture");
+        let layer_views = (0..SHADOW_LAYERS as u32)
+            .map(|layer| {
+                texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow_layer_view"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let layer_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("shadow_layer_bind_group_layout"),
+        });
+        let layer_buffers: Vec<wgpu::Buffer> = (0..SHADOW_LAYERS)
+            .map(|_| {
+                let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Shadow Layer Buffer"),
+                    contents: bytemuck::cast_slice(&[identity]),
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
+        let layer_bind_groups = layer_buffers
+            .iter()
+            .map(|buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &layer_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: buffer.as_entire_binding(),
+                        }
+                    ],
+                    label: Some("shadow_layer_bind_group"),
+                })
+            })
+            .collect();
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform {
+                view_proj: [Matrix4::identity().into(); SHADOW_LAYERS],
+                layers: [[0.0; 4]; SHADOW_LAYERS],
+                splits: [0.0; 4],
+                camera_forward: [0.0; 4],
+                params: [1.0 / SHADOW_SIZE as f32, 1.0, 0.0, 0.0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&layer_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            // Only depth is written
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Thin things like planes should still cast, the bias deals with acne
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                // Pushes the depth back a little, more on slopes, so lit surfaces don't shadow themselves
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pcf: true,
+            texture,
+            layer_views,
+            layer_buffers,
+            layer_bind_groups,
+            uniform_buffer,
+            pipeline,
+            used: 0,
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.texture.sampler
+    }
+
+    pub fn uniform_buffer(&self) -> &wgpu::Buffer {
+        &self.uniform_buffer
+    }
+
+    // Works out the light matrices for this frame and gives out layers. Returns
+    // what each light needs in its uniform: first layer (-1 for none) and layer count.
+    pub fn update(&mut self, queue: &wgpu::Queue, lights: &[Light], camera: &Camera) -> Vec<[f32; 4]> {
+        let mut matrices: Vec<Matrix4<f32>> = vec![];
+        let mut texel_sizes: Vec<f32> = vec![];
+        let mut shadows = vec![];
+        let splits = cascade_splits(camera);
+
+        for light in lights.iter().take(MAX_LIGHTS) {
+            let layers = match light.kind {
+                _ if !light.cast_shadows => 0,
+                LightKind::Point => 0,
+                LightKind::Directional if light.cascaded => CASCADES,
+                _ => 1,
+            };
+            if layers == 0 || matrices.len() + layers > SHADOW_LAYERS {
+                shadows.push([-1.0, 0.0, 0.0, 0.0]);
+                continue;
+            }
+            shadows.push([matrices.len() as f32, layers as f32, 0.0, 0.0]);
+            match light.kind {
+                LightKind::Spot => {
+                    let (matrix, texel) = spot_matrix(light);
+                    matrices.push(matrix);
+                    texel_sizes.push(texel);
+                }
+                _ if layers == 1 => {
+                    let (matrix, texel) = directional_matrix(light, camera, camera.znear, SHADOW_DISTANCE.min(camera.zfar));
+                    matrices.push(matrix);
+                    texel_sizes.push(texel);
+                }
+                _ => {
+                    let mut near = camera.znear;
+                    for far in splits.iter().take(layers) {
+                        let (matrix, texel) = directional_matrix(light, camera, near, *far);
+                        matrices.push(matrix);
+                        texel_sizes.push(texel);
+                        near = *far;
+                    }
+                }
+            }
+        }
+
+        self.used = matrices.len();
+        let mut uniform = ShadowUniform {
+            view_proj: [Matrix4::identity().into(); SHADOW_LAYERS],
+            layers: [[0.0; 4]; SHADOW_LAYERS],
+            splits: [0.0; 4],
+            camera_forward: [0.0; 4],
+            params: [1.0 / SHADOW_SIZE as f32, if self.pcf { 1.0 } else { 0.0 }, 0.0, 0.0],
+        };
+        for (layer, (matrix, texel)) in matrices.iter().zip(texel_sizes.iter()).enumerate() {
+            let raw: [[f32; 4]; 4] = (*matrix).into();
+            uniform.view_proj[layer] = raw;
+            uniform.layers[layer] = [*texel, 0.0, 0.0, 0.0];
+            queue.write_buffer(&self.layer_buffers[layer], 0, bytemuck::cast_slice(&[raw]));
+        }
+        for (split, far) in uniform.splits.iter_mut().zip(splits.iter()) {
+            *split = *far;
+        }
+        let forward = (camera.target - camera.eye).normalize();
+        uniform.camera_forward = [forward.x, forward.y, forward.z, 0.0];
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        shadows
+    }
+
+    // Draws everything that casts shadows into the layers given out by `update`
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, model_instances: &[ModelInstances]) {
+        for layer in 0..self.used {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[layer],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            shadow_pass.set_pipeline(&self.pipeline);
+            shadow_pass.set_bind_group(0, &self.layer_bind_groups[layer], &[]);
+            for model_instance in model_instances.iter() {
+                if model_instance.instances.is_empty() {
+                    continue;
+                }
+                shadow_pass.set_vertex_buffer(1, model_instance.instance_buffer.slice(..));
+                for mesh in &model_instance.model.meshes {
+                    shadow_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    shadow_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    shadow_pass.draw_indexed(0..mesh.num_elements, 0, 0..model_instance.instances.len() as u32);
+                }
+            }
+        }
+    }
+}
+
+// Far distance of each cascade, a mix of even and logarithmic spacing
+fn cascade_splits(camera: &Camera) -> [f32; CASCADES] {
+    let near = camera.znear.max(0.01);
+    let far = SHADOW_DISTANCE.min(camera.zfar).max(near + 0.01);
+    let mut splits = [far; CASCADES];
+    for (i, split) in splits.iter_mut().enumerate() {
+        let fraction = (i + 1) as f32 / CASCADES as f32;
+        let even = near + (far - near) * fraction;
+        let log = near * (far / near).powf(fraction);
+        *split = even + (log - even) * SPLIT_LAMBDA;
+    }
+    splits
+}
+
+// Some up that isn't along the light
+fn light_up(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() }
+}
+
+// A perspective view down the cone, a little wider so the soft edge is covered
+fn spot_matrix(light: &Light) -> (Matrix4<f32>, f32) {
+    let direction = light.unit_direction();
+    let position = Point3::new(light.position.x, light.position.y, light.position.z);
+    let view = Matrix4::look_at_rh(position, position + direction, light_up(direction));
+    let fov = (light.outer_angle.max(light.inner_angle) * 2.0 + 10.0).clamp(1.0, 170.0);
+    let proj = cgmath::perspective(cgmath::Deg(fov), 1.0, 0.1, light.range.max(0.2));
+    let texel = 2.0 * (fov.to_radians() / 2.0).tan() / SHADOW_SIZE as f32;
+    (SHADOW_DEPTH_MATRIX * proj * view, texel)
+}
+
+// An orthographic box around the part of the camera's view from `near` to `far`.
+// It's fitted to a sphere so it keeps its size as the camera turns, and moved in
+// whole texels so shadow edges don't crawl as the camera moves.
+fn directional_matrix(light: &Light, camera: &Camera, near: f32, far: f32) -> (Matrix4<f32>, f32) {
+    let direction = light.unit_direction();
+    let forward = (camera.target - camera.eye).normalize();
+    let right = forward.cross(camera.up).normalize();
+    let up = right.cross(forward);
+    let tan = (camera.fovy.to_radians() / 2.0).tan();
+    let eye = camera.eye.to_homogeneous().truncate();
+
+    let mut corners = vec![];
+    for distance in [near, far] {
+        let height = distance * tan;
+        let width = height * camera.aspect;
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+            corners.push(eye + forward * distance + right * (width * x) + up * (height * y));
+        }
+    }
+    let center = corners.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, c| sum + c) / corners.len() as f32;
+    let radius = corners.iter().map(|c| (c - center).magnitude()).fold(0.0, f32::max).max(0.01);
+
+    let view = Matrix4::look_at_rh(Point3::new(0.0, 0.0, 0.0), Point3::new(direction.x, direction.y, direction.z), light_up(direction));
+    let texel = radius * 2.0 / SHADOW_SIZE as f32;
+    let light_center = view * Vector4::new(center.x, center.y, center.z, 1.0);
+    let x = (light_center.x / texel).round() * texel;
+    let y = (light_center.y / texel).round() * texel;
+    // The view looks down -z, so depth is the negative of z
+    let depth = -light_center.z;
+    let proj = cgmath::ortho(x - radius, x + radius, y - radius, y + radius, depth - radius - CASTER_DISTANCE, depth + radius);
+    (SHADOW_DEPTH_MATRIX * proj * view, texel)
+}
@@ -0,0 +1,36 @@
+// Depth only pass, draws the scene from a light into one shadow map layer
+
+struct Layer {
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> layer: Layer;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(14) cast_shadows: f32,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    // Objects that don't cast are moved outside clip space so they're never drawn
+    if instance.cast_shadows < 0.5 {
+        return vec4<f32>(2.0, 2.0, 2.0, 1.0);
+    }
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return layer.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+}
@@ -0,0 +1,286 @@
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use crate::camera::Camera;
+use crate::texture;
+
+// One channel is all the occlusion needs
+const AO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    // How far around each point to look for what's covering it, in world units
+    pub radius: f32,
+    pub intensity: f32,
+    // Depth differences smaller than this don't count, stops flat surfaces shading themselves
+    pub bias: f32,
+    pub samples: u32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 0.5,
+            intensity: 1.0,
+            bias: 0.02,
+            samples: 16,
+        }
+    }
+}
+
+// Matches `Ssao` in ssao.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    params: [f32; 4],
+    resolution: [f32; 4],
+    eye: [f32; 4],
+    view_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+// Ambient occlusion from the depth buffer. The scene's depth is drawn first,
+// this works out the occlusion from it at half size and blurs it, then the main
+// pass darkens the ambient light with `bind_group`. With SSAO off or the depth
+// buffer unreadable, that's a white texture and nothing changes.
+pub struct Ssao {
+    pub settings: SsaoSettings,
+    // Straight out of ssao.wgsl, then blurred into `blurred`
+    ao: texture::Texture,
+    blurred: texture::Texture,
+    uniform_buffer: wgpu::Buffer,
+    depth_bind_group_layout: wgpu::BindGroupLayout,
+    // None when the depth buffer can't be read, SSAO is skipped then
+    depth_bind_group: Option<wgpu::BindGroup>,
+    depth_sample_count: u32,
+    // A texture and its sampler, for the blur and for the main pass
+    bind_group_layout: wgpu::BindGroupLayout,
+    ao_bind_group: wgpu::BindGroup,
+    blurred_bind_group: wgpu::BindGroup,
+    white_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+}
+
+impl Ssao {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, config: &wgpu::SurfaceConfiguration) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("ssao_bind_group_layout"),
+        });
+        let depth_bind_group_layout = Self::create_depth_layout(device, 1);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSAO Buffer"),
+            contents: bytemuck::cast_slice(&[SsaoUniform {
+                params: [0.0; 4],
+                resolution: [1.0; 4],
+                eye: [0.0; 4],
+                view_proj: cgmath::Matrix4::identity().into(),
+                inv_view_proj: cgmath::Matrix4::identity().into(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let blur_shader = crate::create_fullscreen_shader(device, "SSAO Blur Shader", include_str!("ssao_blur.wgsl"));
+        let blur_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Blur Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let blur_pipeline = crate::create_fullscreen_pipeline(device, &blur_pipeline_layout, &blur_shader, "fs_main", AO_FORMAT, wgpu::BlendState::REPLACE, "SSAO Blur Pipeline");
+        let pipeline = Self::create_pipeline(device, &depth_bind_group_layout, 1);
+
+        let white_image = image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4]));
+        let white = texture::Texture::from_image(device, queue, &image::DynamicImage::ImageRgba8(white_image), Some("ssao_white_texture"), texture::TextureKind::Data, texture::SamplerSettings::clamped()).unwrap();
+        let white_bind_group = Self::create_bind_group(device, &bind_group_layout, &white);
+        let (ao, blurred) = Self::create_targets(device, config);
+        let ao_bind_group = Self::create_bind_group(device, &bind_group_layout, &ao);
+        let blurred_bind_group = Self::create_bind_group(device, &bind_group_layout, &blurred);
+
+        Self {
+            settings: SsaoSettings::default(),
+            ao,
+            blurred,
+            uniform_buffer,
+            depth_bind_group_layout,
+            depth_bind_group: None,
+            depth_sample_count: 1,
+            bind_group_layout,
+            ao_bind_group,
+            blurred_bind_group,
+            white_bind_group,
+            pipeline,
+            blur_pipeline,
+        }
+    }
+
+    // Half the size of the screen, rounded up so every pixel has a texel
+    fn create_targets(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> (texture::Texture, texture::Texture) {
+        let half_config = wgpu::SurfaceConfiguration {
+            width: config.width.div_ceil(2),
+            height: config.height.div_ceil(2),
+            ..config.clone()
+        };
+        (
+            texture::Texture::create_render_target(device, &half_config, AO_FORMAT, 1, "ssao_texture"),
+            texture::Texture::create_render_target(device, &half_config, AO_FORMAT, 1, "ssao_blurred_texture"),
+        )
+    }
+
+    fn create_depth_layout(device: &wgpu::Device, sample_count: u32) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: sample_count > 1,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("ssao_depth_bind_group_layout"),
+        })
+    }
+
+    // A multisampled depth buffer is declared and read differently, so the
+    // shader starts with the version of `depth_at` that fits
+    fn create_pipeline(device: &wgpu::Device, depth_bind_group_layout: &wgpu::BindGroupLayout, sample_count: u32) -> wgpu::RenderPipeline {
+        let depth = if sample_count > 1 { include_str!("ssao_depth_ms.wgsl") } else { include_str!("ssao_depth.wgsl") };
+        let source = format!("{}\n{}", depth, include_str!("ssao.wgsl"));
+        let shader = crate::create_fullscreen_shader(device, "SSAO Shader", &source);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Pipeline Layout"),
+            bind_group_layouts: &[depth_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        crate::create_fullscreen_pipeline(device, &pipeline_layout, &shader, "fs_main", AO_FORMAT, wgpu::BlendState::REPLACE, "SSAO Pipeline")
+    }
+
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture: &texture::Texture) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("ssao_bind_group"),
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        (self.ao, self.blurred) = Self::create_targets(device, config);
+        self.ao_bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.ao);
+        self.blurred_bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.blurred);
+    }
+
+    // Called whenever the depth texture is remade. The pipeline is rebuilt for a
+    // multisampled one, and SSAO goes without when it can't be read at all.
+    pub fn set_depth(&mut self, device: &wgpu::Device, depth: Option<&texture::Texture>, sample_count: u32) {
+        if sample_count != self.depth_sample_count {
+            self.depth_sample_count = sample_count;
+            self.depth_bind_group_layout = Self::create_depth_layout(device, sample_count);
+            self.pipeline = Self::create_pipeline(device, &self.depth_bind_group_layout, sample_count);
+        }
+        self.depth_bind_group = depth.map(|depth| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.depth_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&depth.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: self.uniform_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some("ssao_depth_bind_group"),
+            })
+        });
+    }
+
+    // Whether SSAO can run, it needs the depth buffer
+    pub fn can_run(&self) -> bool {
+        self.depth_bind_group.is_some()
+    }
+
+    // Whether the scene's depth has to be drawn before the main pass this frame
+    pub fn is_active(&self) -> bool {
+        self.settings.enabled && self.can_run()
+    }
+
+    // Group 3 of the main pass, the blurred occlusion or white
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        if self.is_active() { &self.blurred_bind_group } else { &self.white_bind_group }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera, config: &wgpu::SurfaceConfiguration) {
+        if !self.is_active() {
+            return;
+        }
+        let view_proj = camera.build_view_projection_matrix();
+        let inv_view_proj = view_proj.invert().unwrap_or(cgmath::Matrix4::identity());
+        let (width, height) = (config.width as f32, config.height as f32);
+        let settings = &self.settings;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[SsaoUniform {
+            params: [settings.radius, settings.intensity, settings.bias, settings.samples as f32],
+            resolution: [width, height, 1.0 / width, 1.0 / height],
+            eye: [camera.eye.x, camera.eye.y, camera.eye.z, 1.0],
+            view_proj: view_proj.into(),
+            inv_view_proj: inv_view_proj.into(),
+        }]));
+    }
+
+    // After the depth is drawn and before the main pass reads `bind_group`
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder) {
+        let depth_bind_group = match (&self.depth_bind_group, self.settings.enabled) {
+            (Some(depth_bind_group), true) => depth_bind_group,
+            _ => return,
+        };
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::WHITE);
+        crate::draw_fullscreen(encoder, "SSAO Pass", &self.ao.view, clear, &self.pipeline, &[depth_bind_group]);
+        crate::draw_fullscreen(encoder, "SSAO Blur Pass", &self.blurred.view, clear, &self.blur_pipeline, &[&self.ao_bind_group]);
+    }
+}
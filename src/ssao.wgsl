@@ -0,0 +1,90 @@
+// SSAO, darkens creases and corners by checking how much of the hemisphere over
+// each point is buried behind what's in the depth buffer. It's worked out at
+// half size into ssao.rs's AO texture, blurred, then shader.wgsl darkens the
+// ambient light with it. `depth_at` comes from ssao_depth.wgsl or
+// ssao_depth_ms.wgsl, added on in front of this.
+
+struct Ssao {
+    // Radius in world units, intensity, bias, samples
+    params: vec4<f32>,
+    // Width, height, 1 / width, 1 / height of the screen, not the AO texture
+    resolution: vec4<f32>,
+    eye: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(1)
+var<uniform> ssao: Ssao;
+
+const MAX_SAMPLES: i32 = 64;
+
+fn world_position(pixel: vec2<i32>) -> vec3<f32> {
+    let uv = (vec2<f32>(pixel) + 0.5) * ssao.resolution.zw;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth_at(pixel), 1.0);
+    let world = ssao.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+// Of the two neighbours either side, the step to the nearer one, so edges don't bend the normal
+fn nearer_step(position: vec3<f32>, before: vec3<f32>, after: vec3<f32>) -> vec3<f32> {
+    return select(position - before, after - position, length(after - position) < length(position - before));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Each texel covers two by two pixels, shader.wgsl reads it back the same way
+    let pixel = vec2<i32>(in.clip_position.xy) * 2;
+    // Nothing was drawn here
+    if depth_at(pixel) >= 1.0 {
+        return vec4<f32>(1.0);
+    }
+    let radius = ssao.params.x;
+    let intensity = ssao.params.y;
+    let bias = ssao.params.z;
+    let count = clamp(i32(ssao.params.w), 1, MAX_SAMPLES);
+
+    let position = world_position(pixel);
+    let dx = nearer_step(position, world_position(pixel - vec2<i32>(1, 0)), world_position(pixel + vec2<i32>(1, 0)));
+    let dy = nearer_step(position, world_position(pixel - vec2<i32>(0, 1)), world_position(pixel + vec2<i32>(0, 1)));
+    var normal = normalize(cross(dx, dy));
+    if dot(normal, ssao.eye.xyz - position) < 0.0 {
+        normal = -normal;
+    }
+    let helper = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(normal.x) > 0.9);
+    let tangent = normalize(cross(helper, normal));
+    let bitangent = cross(normal, tangent);
+
+    // Turns the sample pattern differently for each pixel, trading banding for fine noise
+    let noise = fract(52.9829189 * fract(dot(in.clip_position.xy, vec2<f32>(0.06711056, 0.00583715))));
+    let distance = length(ssao.eye.xyz - position);
+
+    var occlusion = 0.0;
+    for (var i = 0; i < count; i++) {
+        // Spiral over the hemisphere, more samples near the normal and near the point
+        let t = (f32(i) + 0.5) / f32(count);
+        let sin_theta = sqrt(t);
+        let cos_theta = sqrt(1.0 - t);
+        let phi = f32(i) * 2.39996323 + noise * 6.28318531;
+        let direction = (tangent * cos(phi) + bitangent * sin(phi)) * sin_theta + normal * cos_theta;
+        let reach = fract(t + noise);
+        let sample_position = position + direction * radius * mix(0.1, 1.0, reach * reach);
+
+        let clip = ssao.view_proj * vec4<f32>(sample_position, 1.0);
+        if clip.w <= 0.0 {
+            continue;
+        }
+        let ndc = clip.xy / clip.w;
+        let sample_uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if any(sample_uv < vec2<f32>(0.0)) || any(sample_uv > vec2<f32>(1.0)) {
+            continue;
+        }
+        let scene_distance = length(ssao.eye.xyz - world_position(vec2<i32>(sample_uv * ssao.resolution.xy)));
+        let sample_distance = length(ssao.eye.xyz - sample_position);
+        if scene_distance < sample_distance - bias {
+            // Whatever's in the way counts less the further in front of the point it is
+            occlusion += smoothstep(0.0, 1.0, radius / max(abs(distance - scene_distance), 0.0001));
+        }
+    }
+    let ao = clamp(1.0 - occlusion / f32(count) * intensity, 0.0, 1.0);
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
@@ -0,0 +1,21 @@
+// Evens out the noise SSAO leaves. Each tap sits on a texel corner so it
+// averages four of them, and the taps spread evenly around the middle one.
+
+@group(0) @binding(0)
+var t_ao: texture_2d<f32>;
+@group(0) @binding(1)
+var s_ao: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_ao));
+    var total = 0.0;
+    for (var y = -2; y < 2; y++) {
+        for (var x = -2; x < 2; x++) {
+            let offset = (vec2<f32>(f32(x), f32(y)) + 0.5) * texel;
+            total += textureSampleLevel(t_ao, s_ao, in.uv + offset, 0.0).r;
+        }
+    }
+    let ao = total / 16.0;
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
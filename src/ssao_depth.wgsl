@@ -0,0 +1,10 @@
+// How ssao.wgsl reads the depth buffer without MSAA. ssao_depth_ms.wgsl is the
+// same for a multisampled one, ssao.rs adds whichever fits in front.
+
+@group(0) @binding(0)
+var t_depth: texture_depth_2d;
+
+fn depth_at(pixel: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(t_depth));
+    return textureLoad(t_depth, clamp(pixel, vec2<i32>(0), size - 1), 0);
+}
@@ -0,0 +1,10 @@
+// ssao_depth.wgsl for a multisampled depth buffer. The first sample of each
+// pixel stands in for the rest.
+
+@group(0) @binding(0)
+var t_depth: texture_depth_multisampled_2d;
+
+fn depth_at(pixel: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(t_depth));
+    return textureLoad(t_depth, clamp(pixel, vec2<i32>(0), size - 1), 0);
+}
@@ -1,6 +1,78 @@
+use cgmath::InnerSpace;
 use image::GenericImageView;
 use anyhow::*;
 
+// How a texture is read. Materials get these from their MTL texture options.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SamplerSettings {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    // Blending between mip levels, linear is trilinear filtering
+    pub mipmap_filter: wgpu::FilterMode,
+    // 1 turns it off, up to 16. Only used when every filter is linear, and
+    // dropped on devices that can't do it.
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerSettings {
+    // Tiling with smooth filtering, what most material textures want
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy: 8,
+        }
+    }
+}
+
+impl SamplerSettings {
+    // Stops at the edge instead of repeating
+    pub fn clamped() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_address_mode(self, mode: wgpu::AddressMode) -> Self {
+        Self { address_mode_u: mode, address_mode_v: mode, ..self }
+    }
+
+    fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+        let linear = self.mag_filter == wgpu::FilterMode::Linear
+            && self.min_filter == wgpu::FilterMode::Linear
+            && self.mipmap_filter == wgpu::FilterMode::Linear;
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            // wgpu rejects anisotropy with any nearest filter
+            anisotropy_clamp: if linear { self.anisotropy.clamp(1, 16) } else { 1 },
+            ..Default::default()
+        })
+    }
+}
+
+// What a texture holds, which decides how it's stored and how its mips are made
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TextureKind {
+    // Colours, stored as sRGB and averaged in linear light
+    Color,
+    // Plain numbers like roughness or occlusion, used as they are
+    Data,
+    // Directions packed into 0 to 1, kept unit length in the smaller mips
+    Normal,
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -12,19 +84,48 @@ impl Texture {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8], 
-        label: &str
+        label: &str,
+        kind: TextureKind,
+        settings: SamplerSettings,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(device, queue, &img, Some(label), kind, settings)
+    }
+
+    // A 1x1 normal map pointing straight out, for materials that don't have one
+    pub fn flat_normal_map(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self::solid(device, queue, [128, 128, 255, 255], "flat_normal_map", TextureKind::Normal)
+    }
+
+    // Leaves whatever it's multiplied with as it is
+    pub fn white(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        Self::solid(device, queue, [255, 255, 255, 255], "white_texture", TextureKind::Color)
+    }
+
+    // Magenta and black checks standing in for a texture that couldn't be loaded,
+    // so it's easy to spot rather than silently wrong
+    pub fn missing(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let img = image::RgbaImage::from_fn(64, 64, |x, y| {
+            if (x / 8 + y / 8) % 2 == 0 { image::Rgba([255, 0, 255, 255]) } else { image::Rgba([0, 0, 0, 255]) }
+        });
+        let settings = SamplerSettings { mag_filter: wgpu::FilterMode::Nearest, ..SamplerSettings::default() };
+        Self::from_image(device, queue, &image::DynamicImage::ImageRgba8(img), Some("missing_texture"), TextureKind::Color, settings).unwrap()
+    }
+
+    // A 1x1 texture of one colour
+    pub fn solid(device: &wgpu::Device, queue: &wgpu::Queue, rgba: [u8; 4], label: &str, kind: TextureKind) -> Self {
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba)));
+        Self::from_image(device, queue, &img, Some(label), kind, SamplerSettings::default()).unwrap()
     }
 
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
-        label: Option<&str>
+        label: Option<&str>,
+        kind: TextureKind,
+        settings: SamplerSettings,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
         let size = wgpu::Extent3d {
@@ -32,73 +133,91 @@ impl Texture {
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
+        // Every level down to 1x1, so far away surfaces read a smaller copy instead of shimmering
+        let mip_level_count = size.max_mips(wgpu::TextureDimension::D2);
         let texture = device.create_texture(
             &wgpu::TextureDescriptor {
                 label,
                 size,
-                mip_level_count: 1,
+                mip_level_count,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                // Only colours are gamma corrected, anything else would be bent out of shape
+                format: if kind == TextureKind::Color {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else {
+                    wgpu::TextureFormat::Rgba8Unorm
+                },
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
                 view_formats: &[],
             }
         );
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            size,
-        );
+        for (mip_level, level) in mip_chain(img, kind, mip_level_count).iter().enumerate() {
+            let mip_level = mip_level as u32;
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                level,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * level.width()),
+                    rows_per_image: Some(level.height()),
+                },
+                wgpu::Extent3d {
+                    width: level.width(),
+                    height: level.height(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            }
-        );
+        let sampler = settings.create_sampler(device);
         
         Ok(Self { texture, view, sampler })
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
     
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    // WebGL2 can't read multisampled textures in shaders, see `depth_readable`
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32, label: &str) -> Self {
+        Self::create_depth_texture_layers(device, config.width, config.height, 1, sample_count, label)
+    }
+
+    // Same as above with any size and a number of layers, shadow maps keep one
+    // light per layer. With more than one layer the view covers them all as an array.
+    pub fn create_depth_texture_layers(device: &wgpu::Device, width: u32, height: u32, layers: u32, sample_count: u32, label: &str) -> Self {
         let size = wgpu::Extent3d { // 2.
-            width: config.width,
-            height: config.height,
-            depth_or_array_layers: 1,
+            width,
+            height,
+            depth_or_array_layers: layers,
         };
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
-                | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: if Self::depth_readable(sample_count) {
+                wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            },
             view_formats: &[],
         };
         let texture = device.create_texture(&desc);
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: if layers > 1 { Some(wgpu::TextureViewDimension::D2Array) } else { None },
+            ..Default::default()
+        });
         let sampler = device.create_sampler(
             &wgpu::SamplerDescriptor { // 4.
                 address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -116,4 +235,115 @@ impl Texture {
 
         Self { texture, view, sampler }
     }
+
+    // Whether a depth texture with this many samples can be bound for shaders to read
+    pub fn depth_readable(sample_count: u32) -> bool {
+        sample_count == 1 || !cfg!(target_arch = "wasm32")
+    }
+
+    // A colour target the size of the surface. Multisampled ones are drawn into and
+    // resolved, single sampled ones can also be read by a later pass.
+    pub fn create_render_target(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, format: wgpu::TextureFormat, sample_count: u32, label: &str) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: if sample_count > 1 {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            },
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = SamplerSettings::clamped().create_sampler(device);
+
+        Self { texture, view, sampler }
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+// The image and `count - 1` mips under it, each half the size of the last. They're
+// made on the CPU since WebGL can't run the compute or blit passes a GPU version
+// would need. Colours are averaged in linear light, or the sRGB values would make
+// the small mips darker, and normals are put back to unit length after averaging.
+pub fn mip_chain(img: &image::DynamicImage, kind: TextureKind, count: u32) -> Vec<image::RgbaImage> {
+    let rgba = img.to_rgba8();
+    let mut level = image::Rgba32FImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0.map(|c| c as f32 / 255.0);
+        match kind {
+            TextureKind::Color => image::Rgba([srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]),
+            _ => image::Rgba([r, g, b, a]),
+        }
+    });
+    let mut mips = vec![rgba];
+    for _ in 1..count {
+        let width = (level.width() / 2).max(1);
+        let height = (level.height() / 2).max(1);
+        level = image::imageops::resize(&level, width, height, image::imageops::FilterType::Triangle);
+        if kind == TextureKind::Normal {
+            for pixel in level.pixels_mut() {
+                let normal = cgmath::Vector3::new(pixel[0], pixel[1], pixel[2]) * 2.0 - cgmath::Vector3::new(1.0, 1.0, 1.0);
+                if normal.magnitude2() > 1e-6 {
+                    let normal = normal.normalize() * 0.5 + cgmath::Vector3::new(0.5, 0.5, 0.5);
+                    pixel.0 = [normal.x, normal.y, normal.z, pixel[3]];
+                }
+            }
+        }
+        mips.push(image::RgbaImage::from_fn(width, height, |x, y| {
+            let [r, g, b, a] = level.get_pixel(x, y).0;
+            let encode = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            match kind {
+                TextureKind::Color => image::Rgba([encode(linear_to_srgb(r)), encode(linear_to_srgb(g)), encode(linear_to_srgb(b)), encode(a)]),
+                _ => image::Rgba([encode(r), encode(g), encode(b), encode(a)]),
+            }
+        }));
+    }
+    mips
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_mips_average_in_linear_light() {
+        // Black and white stripes should come out as half the light, not half the sRGB value
+        let img = image::RgbaImage::from_fn(2, 1, |x, _| if x == 0 { image::Rgba([0, 0, 0, 255]) } else { image::Rgba([255, 255, 255, 255]) });
+        let mips = mip_chain(&image::DynamicImage::ImageRgba8(img), TextureKind::Color, 2);
+        assert_eq!(mips.len(), 2);
+        assert_eq!(mips[1].get_pixel(0, 0)[0], 188);
+    }
+
+    #[test]
+    fn data_mips_average_as_they_are() {
+        let img = image::RgbaImage::from_fn(2, 1, |x, _| if x == 0 { image::Rgba([0, 0, 0, 255]) } else { image::Rgba([255, 255, 255, 255]) });
+        let mips = mip_chain(&image::DynamicImage::ImageRgba8(img), TextureKind::Data, 2);
+        assert_eq!(mips[1].get_pixel(0, 0)[0], 128);
+    }
+
+    #[test]
+    fn normal_mips_stay_unit_length() {
+        // Two normals tipped opposite ways along x average to a short one pointing out
+        let img = image::RgbaImage::from_fn(2, 1, |x, _| if x == 0 { image::Rgba([38, 128, 218, 255]) } else { image::Rgba([218, 128, 218, 255]) });
+        let mips = mip_chain(&image::DynamicImage::ImageRgba8(img), TextureKind::Normal, 2);
+        let pixel = mips[1].get_pixel(0, 0);
+        let normal = cgmath::Vector3::new(pixel[0], pixel[1], pixel[2]).map(|c| c as f32 / 255.0 * 2.0 - 1.0);
+        assert!((normal.magnitude() - 1.0).abs() < 0.02);
+        assert!(normal.z > 0.99);
+    }
 }
@@ -0,0 +1,59 @@
+// Brings the HDR scene, with its bloom added on, down into what the screen can show
+
+struct Tonemap {
+    // Already a multiplier, not stops
+    exposure: f32,
+    // 0 ACES, 1 Reinhard, 2 clamp
+    tonemapper: u32,
+    // 0 with bloom off
+    bloom_intensity: f32,
+    _padding: f32,
+};
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var t_bloom: texture_2d<f32>;
+@group(0) @binding(2)
+var s_hdr: sampler;
+@group(0) @binding(3)
+var<uniform> tonemap: Tonemap;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let x = color * 0.6;
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (vec3<f32>(1.0) + color);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSampleLevel(t_hdr, s_hdr, in.uv, 0.0).rgb;
+    if tonemap.bloom_intensity > 0.0 {
+        color += textureSampleLevel(t_bloom, s_hdr, in.uv, 0.0).rgb * tonemap.bloom_intensity;
+    }
+    color *= tonemap.exposure;
+
+    var mapped: vec3<f32>;
+    switch tonemap.tonemapper {
+        case 0u: {
+            mapped = aces(color);
+        }
+        case 1u: {
+            mapped = reinhard(color);
+        }
+        default: {
+            mapped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+    // Still linear, the surface is sRGB and encodes it on write
+    return vec4<f32>(mapped, 1.0);
+}